@@ -0,0 +1,94 @@
+//! Throughput of the local rate-limiter store under contention, against a single map behind
+//! one lock as the store used to be.
+//!
+//! Run with `cargo bench --bench limiter_store`. Every thread checks requests from its own set
+//! of clients, as the listeners do for different connections; the `scan` rows use a new client
+//! for every request, as an address scan would, to show the store staying within `max_entries`.
+
+use std::collections::HashMap;
+use std::hint::black_box;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use sheldx::services::{ new_limiter, unix_now, LimitParams, Limiter, LimiterKey, LimiterStore };
+use sheldx::utils::RateLimitAlgorithm;
+
+const CHECKS_PER_THREAD: usize = 200_000;
+const CLIENTS_PER_THREAD: usize = 5_000;
+const MAX_ENTRIES: usize = 100_000;
+
+const PARAMS: LimitParams = LimitParams { limit: 100, window: Duration::from_secs(60), burst: 100 };
+
+/// A store with a single lock, for comparison.
+type SingleLockMap = Mutex<HashMap<LimiterKey, Box<dyn Limiter>>>;
+
+fn keys(thread: usize, count: usize) -> Vec<LimiterKey> {
+    (0..count)
+        .map(|client| LimiterKey {
+            rule: "api.example.com".to_string(),
+            key: format!("10.{}.{}.{}", thread, client / 256, client % 256),
+        })
+        .collect()
+}
+
+/// Checks per second over every thread, each running `work` over its own keys.
+fn throughput<F>(threads: usize, clients: usize, work: F) -> f64 where F: Fn(&[LimiterKey]) + Sync {
+    let keys: Vec<Vec<LimiterKey>> = (0..threads).map(|thread| keys(thread, clients)).collect();
+    let started = Instant::now();
+    thread::scope(|scope| {
+        for keys in &keys {
+            let work = &work;
+            scope.spawn(move || work(keys));
+        }
+    });
+    ((threads * CHECKS_PER_THREAD) as f64) / started.elapsed().as_secs_f64()
+}
+
+fn sharded(store: &LimiterStore, keys: &[LimiterKey]) {
+    let now = unix_now();
+    for i in 0..CHECKS_PER_THREAD {
+        black_box(store.check(&keys[i % keys.len()], RateLimitAlgorithm::TokenBucket, PARAMS, now));
+    }
+}
+
+fn single_lock(map: &SingleLockMap, keys: &[LimiterKey]) {
+    let now = unix_now();
+    for i in 0..CHECKS_PER_THREAD {
+        let mut map = map.lock().unwrap();
+        let limiter = map
+            .entry(keys[i % keys.len()].clone())
+            .or_insert_with(|| new_limiter(RateLimitAlgorithm::TokenBucket, PARAMS));
+        black_box(limiter.check(now));
+    }
+}
+
+fn main() {
+    let available = thread::available_parallelism().map_or(1, |n| n.get());
+    println!("{} CPUs available, {} checks per thread\n", available, CHECKS_PER_THREAD);
+    println!("{:<10} {:>8} {:>16} {:>16} {:>10}", "workload", "threads", "sharded/s", "single lock/s", "entries");
+
+    for threads in [1, 2, 4, 8, 16] {
+        let store = LimiterStore::new(MAX_ENTRIES, Duration::from_secs(300));
+        let sharded_rate = throughput(threads, CLIENTS_PER_THREAD, |keys| sharded(&store, keys));
+        let map = SingleLockMap::default();
+        let single_rate = throughput(threads, CLIENTS_PER_THREAD, |keys| single_lock(&map, keys));
+        println!("{:<10} {:>8} {:>16.0} {:>16.0} {:>10}", "clients", threads, sharded_rate, single_rate, store.len());
+    }
+
+    for threads in [1, 4, 16] {
+        let store = LimiterStore::new(MAX_ENTRIES, Duration::from_secs(300));
+        let sharded_rate = throughput(threads, CHECKS_PER_THREAD, |keys| sharded(&store, keys));
+        let map = SingleLockMap::default();
+        let single_rate = throughput(threads, CHECKS_PER_THREAD, |keys| single_lock(&map, keys));
+        let unbounded = map.lock().unwrap().len();
+        println!(
+            "{:<10} {:>8} {:>16.0} {:>16.0} {:>10}",
+            "scan",
+            threads,
+            sharded_rate,
+            single_rate,
+            format!("{} (vs {})", store.len(), unbounded)
+        );
+    }
+}
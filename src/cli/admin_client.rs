@@ -0,0 +1,61 @@
+use std::error::Error;
+
+use http_body_util::{ BodyExt, Empty };
+use hyper::body::Bytes;
+use hyper::client::conn::http1;
+use hyper::header::{ AUTHORIZATION, HOST };
+use hyper::{ Method, Request };
+use hyper_util::rt::TokioIo;
+use serde_json::Value;
+use tokio::io::{ AsyncRead, AsyncWrite };
+use tokio::net::{ TcpStream, UnixStream };
+
+use crate::utils::AdminConfig;
+
+/// Sends a request to the admin API of the running proxy and returns the status and JSON body.
+pub async fn admin_request(
+    admin: &AdminConfig,
+    method: Method,
+    path: &str
+) -> Result<(u16, Value), Box<dyn Error>> {
+    let unreachable = |e: std::io::Error| {
+        let address = admin.socket.clone().map(|socket| format!("unix:{}", socket)).unwrap_or(admin.listen.clone());
+        format!("Could not reach the admin API at {}, is sheldx running? ({})", address, e)
+    };
+
+    match &admin.socket {
+        Some(socket) => {
+            let stream = UnixStream::connect(socket).await.map_err(unreachable)?;
+            send(stream, "localhost", admin, method, path).await
+        }
+        None => {
+            let stream = TcpStream::connect(&admin.listen).await.map_err(unreachable)?;
+            send(stream, &admin.listen, admin, method, path).await
+        }
+    }
+}
+
+async fn send<S>(
+    stream: S,
+    host: &str,
+    admin: &AdminConfig,
+    method: Method,
+    path: &str
+) -> Result<(u16, Value), Box<dyn Error>>
+    where S: AsyncRead + AsyncWrite + Unpin + Send + 'static
+{
+    let (mut send_request, connection) = http1::handshake(TokioIo::new(stream)).await?;
+    tokio::spawn(connection);
+
+    let request = Request::builder()
+        .method(method)
+        .uri(path)
+        .header(HOST, host)
+        .header(AUTHORIZATION, format!("Bearer {}", admin.token))
+        .body(Empty::<Bytes>::new())?;
+    let response = send_request.send_request(request).await?;
+    let status = response.status().as_u16();
+    let body = response.into_body().collect().await?.to_bytes();
+
+    Ok((status, serde_json::from_slice(&body)?))
+}
@@ -0,0 +1,266 @@
+use std::error::Error;
+
+use hyper::Method;
+use serde_json::Value;
+
+use super::admin_client::admin_request;
+use super::{ CheckConfigArgs, Cli, Command, ConvertArgs, MigrateConfigArgs, PrintDefaultConfigArgs, RunArgs };
+use crate::server::{ start_sheldx, ListenerOverrides };
+use crate::utils::{
+    config_path,
+    create_default_config,
+    default_config_text,
+    init_logger,
+    load_configs,
+    main_config_format,
+    migrate_config_text,
+    override_log_level,
+    read_config_file,
+    set_config_format,
+    set_config_path,
+    validate_config_file,
+    write_config_text,
+    AdminConfig,
+    ConfigFormat,
+    Configs,
+};
+
+/// Runs the command given on the command line.
+pub async fn run_cli(cli: Cli) -> Result<(), Box<dyn Error>> {
+    if let Some(config) = cli.config {
+        set_config_path(config);
+    }
+    if let Some(format) = cli.config_format {
+        set_config_format(format);
+    }
+
+    match cli.command.unwrap_or(Command::Run(RunArgs::default())) {
+        Command::Run(args) => run(args).await,
+        Command::CheckConfig(args) => check_config(args),
+        Command::PrintDefaultConfig(args) => print_default_config(args),
+        Command::Convert(args) => convert(args),
+        Command::MigrateConfig(args) => migrate_config_file(args),
+        Command::PrintSchema => print_schema(),
+        Command::Reload => reload().await,
+        Command::Status => status().await,
+        Command::Version => {
+            println!("sheldx {}", env!("CARGO_PKG_VERSION"));
+            Ok(())
+        }
+    }
+}
+
+async fn run(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    if let Some(level) = args.log_level {
+        override_log_level(level);
+    }
+    if args.create_default_config {
+        create_default_config()?;
+    }
+
+    // fall back to default logging so a broken config file is still reported
+    let (logging, show_logs_on_console) = match load_configs() {
+        Ok(configs) => (configs.logging.unwrap_or_default(), configs.show_logs_on_console),
+        Err(_) => (Default::default(), true),
+    };
+    init_logger(&logging, show_logs_on_console)?;
+
+    let overrides = ListenerOverrides { http_port: args.http_port, https_port: args.https_port };
+    start_sheldx(overrides).await.map_err(|e| {
+        // check if the is related tp permission denied
+        if e.to_string().contains("Permission denied") {
+            // tell user to give sheldx permission to bind to port 80 by telling how to do it
+            log::error!(
+                "Permission denied. You may need to run Sheldx as root or give it permission to bind to port 80"
+            );
+            log::info!(
+                "You can give Sheldx permission to bind to port 80 or any other port by running the following command:"
+            );
+            log::info!("sudo setcap cap_net_bind_service=+ep /path/to/sheldx");
+        } else {
+            log::error!("Error starting Sheldx: {}", e);
+        }
+
+        e
+    })?;
+
+    // TODO: Implement graceful shutdown setup
+
+    Ok(())
+}
+
+fn check_config(args: CheckConfigArgs) -> Result<(), Box<dyn Error>> {
+    let path = config_path();
+    let report = validate_config_file(&path);
+
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report.diagnostics)?);
+    } else {
+        for diagnostic in &report.diagnostics {
+            eprintln!("{}", diagnostic);
+        }
+    }
+
+    if report.has_errors() {
+        return Err(format!("Configuration file {} is invalid", path.display()).into());
+    }
+    if !args.json {
+        println!("Configuration file {} is valid", path.display());
+    }
+    Ok(())
+}
+
+fn print_default_config(args: PrintDefaultConfigArgs) -> Result<(), Box<dyn Error>> {
+    print!("{}", default_config_text(args.format)?);
+    Ok(())
+}
+
+fn print_schema() -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(&schemars::schema_for!(Configs))?);
+    Ok(())
+}
+
+/// Rewrites a configuration file in another format, keeping `include` and `${...}` as written.
+fn convert(args: ConvertArgs) -> Result<(), Box<dyn Error>> {
+    let (input, from) = match args.input {
+        Some(input) => {
+            let format = ConfigFormat::from_path(&input);
+            (input, format)
+        }
+        None => {
+            let input = config_path();
+            let format = main_config_format(&input);
+            (input, format)
+        }
+    };
+    let to = args.to
+        .or_else(|| args.output.as_deref().and_then(ConfigFormat::from_extension))
+        .ok_or("Pass --to, or an output file ending in .toml, .yaml or .json")?;
+
+    let table = read_config_file(&input, from).map_err(|diagnostics| {
+        for diagnostic in &diagnostics {
+            eprintln!("{}", diagnostic);
+        }
+        format!("Configuration file {} could not be read", input.display())
+    })?;
+    if table.contains_key("include") {
+        eprintln!("Files included by {} are not converted, convert them separately", input.display());
+    }
+
+    let text = write_config_text(&table, to)?;
+    match args.output {
+        Some(output) => {
+            std::fs::write(&output, text)?;
+            eprintln!("Converted {} ({}) to {} ({})", input.display(), from, output.display(), to);
+        }
+        None => print!("{}", text),
+    }
+    Ok(())
+}
+
+/// Upgrades a TOML configuration file to the current version, keeping its comments and layout.
+fn migrate_config_file(args: MigrateConfigArgs) -> Result<(), Box<dyn Error>> {
+    let (input, format) = match args.input {
+        Some(input) => {
+            let format = ConfigFormat::from_path(&input);
+            (input, format)
+        }
+        None => {
+            let input = config_path();
+            let format = main_config_format(&input);
+            (input, format)
+        }
+    };
+    if format != ConfigFormat::Toml {
+        return Err(format!("Only TOML files can be upgraded in place; {} is {}", input.display(), format).into());
+    }
+
+    let text = std::fs::read_to_string(&input)?;
+    let (migrated, warnings) = migrate_config_text(&text).map_err(|diagnostics| {
+        for diagnostic in &diagnostics {
+            eprintln!("{}", diagnostic);
+        }
+        format!("Configuration file {} could not be upgraded", input.display())
+    })?;
+    for warning in &warnings {
+        eprintln!("{}", warning);
+    }
+
+    if !args.write {
+        print!("{}", migrated);
+    } else if migrated == text {
+        eprintln!("{} is already at the current version", input.display());
+    } else {
+        std::fs::write(&input, migrated)?;
+        eprintln!("Upgraded {}", input.display());
+    }
+    Ok(())
+}
+
+async fn reload() -> Result<(), Box<dyn Error>> {
+    let admin = admin_config()?;
+    admin_json(&admin, Method::POST, "/reload").await?;
+    println!("Configuration reloaded");
+    Ok(())
+}
+
+async fn status() -> Result<(), Box<dyn Error>> {
+    let admin = admin_config()?;
+    let listeners = admin_json(&admin, Method::GET, "/listeners").await?;
+    let upstreams = admin_json(&admin, Method::GET, "/upstreams").await?;
+
+    println!("sheldx is running");
+    println!("Listeners:");
+    for listener in listeners.as_array().into_iter().flatten() {
+        println!(
+            "  {:<6} {:<22} connections: {}",
+            listener["name"].as_str().unwrap_or_default(),
+            listener["address"].as_str().unwrap_or_default(),
+            listener["active_connections"]
+        );
+    }
+
+    println!("Upstreams:");
+    for upstream in upstreams.as_array().into_iter().flatten() {
+        let health = match upstream["healthy"].as_bool() {
+            _ if upstream["drained"].as_bool() == Some(true) => "drained",
+            Some(true) => "healthy",
+            Some(false) => "unhealthy",
+            None => "unknown",
+        };
+        let hosts: Vec<&str> = upstream["hosts"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect();
+        println!(
+            "  {:<22} {:<9} hosts: {}",
+            upstream["target"].as_str().unwrap_or_default(),
+            health,
+            hosts.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// The admin API settings of the running proxy, read from its configuration file.
+fn admin_config() -> Result<AdminConfig, Box<dyn Error>> {
+    load_configs()?
+        .admin.filter(|admin| admin.enabled)
+        .ok_or_else(|| {
+            format!(
+                "The admin API is not enabled in {}. Add an [admin] section, or send SIGHUP to the sheldx process to reload it",
+                config_path().display()
+            ).into()
+        })
+}
+
+async fn admin_json(admin: &AdminConfig, method: Method, path: &str) -> Result<Value, Box<dyn Error>> {
+    let (status, body) = admin_request(admin, method, path).await?;
+    if status != 200 {
+        let message = body["error"].as_str().unwrap_or("unexpected response");
+        return Err(format!("The admin API answered {} {}: {}", status, path, message).into());
+    }
+    Ok(body)
+}
@@ -0,0 +1,108 @@
+mod admin_client;
+mod commands;
+
+pub use commands::*;
+
+use std::path::PathBuf;
+
+use clap::{ Args, Parser, Subcommand };
+use log::LevelFilter;
+
+use crate::utils::ConfigFormat;
+
+/// SheldX reverse proxy.
+#[derive(Debug, Parser)]
+#[command(name = "sheldx", version, about)]
+pub struct Cli {
+    /// Configuration file to use. Defaults to /etc/sheldx/configs/main.conf.
+    #[arg(short, long, global = true, value_name = "PATH", env = "SHELDX_CONFIG")]
+    pub config: Option<PathBuf>,
+
+    /// Format of the configuration file (toml, yaml or json). Defaults to the file's extension.
+    #[arg(long, global = true, value_name = "FORMAT", env = "SHELDX_CONFIG_FORMAT")]
+    pub config_format: Option<ConfigFormat>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Start the proxy. This is the default when no command is given.
+    Run(RunArgs),
+    /// Check the configuration file and report every problem found.
+    CheckConfig(CheckConfigArgs),
+    /// Print the default configuration.
+    PrintDefaultConfig(PrintDefaultConfigArgs),
+    /// Rewrite a configuration file in another format.
+    Convert(ConvertArgs),
+    /// Upgrade a TOML configuration file to the current version, keeping its comments.
+    MigrateConfig(MigrateConfigArgs),
+    /// Print the JSON Schema of the configuration file, for editors and CI.
+    PrintSchema,
+    /// Ask the running proxy to reload its configuration, through the admin API.
+    Reload,
+    /// Show the listeners, connections and upstreams of the running proxy, through the admin API.
+    Status,
+    /// Print the version.
+    Version,
+}
+
+#[derive(Debug, Args)]
+pub struct CheckConfigArgs {
+    /// Print the problems as JSON, for editors and CI.
+    #[arg(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct PrintDefaultConfigArgs {
+    /// Format to print (toml, yaml or json).
+    #[arg(long, value_name = "FORMAT", default_value = "toml")]
+    pub format: ConfigFormat,
+}
+
+#[derive(Debug, Args)]
+pub struct ConvertArgs {
+    /// File to convert. Defaults to the configuration file.
+    #[arg(value_name = "INPUT")]
+    pub input: Option<PathBuf>,
+
+    /// Where to write the converted file. Defaults to standard output.
+    #[arg(short, long, value_name = "PATH")]
+    pub output: Option<PathBuf>,
+
+    /// Format to convert to (toml, yaml or json). Defaults to the extension of the output file.
+    #[arg(long, value_name = "FORMAT")]
+    pub to: Option<ConfigFormat>,
+}
+
+#[derive(Debug, Args)]
+pub struct MigrateConfigArgs {
+    /// File to upgrade. Defaults to the configuration file.
+    #[arg(value_name = "INPUT")]
+    pub input: Option<PathBuf>,
+
+    /// Rewrite the file in place instead of printing the upgraded file.
+    #[arg(long)]
+    pub write: bool,
+}
+
+#[derive(Debug, Args, Default)]
+pub struct RunArgs {
+    /// Default log level (off, error, warn, info, debug or trace), overriding `logging.level`.
+    #[arg(long, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Port for the plain HTTP listener.
+    #[arg(long, value_name = "PORT")]
+    pub http_port: Option<u16>,
+
+    /// Port for the TLS listener.
+    #[arg(long, value_name = "PORT")]
+    pub https_port: Option<u16>,
+
+    /// Write the default configuration to the configuration path if no file exists there.
+    #[arg(long)]
+    pub create_default_config: bool,
+}
@@ -1,23 +1,59 @@
-use std::collections::HashMap;
+use std::error::Error as StdError;
 use std::fs::{ self, File };
 use std::io::Read;
+use std::net::IpAddr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{ Duration, Instant };
 
-use http_body_util::{ BodyExt, Full };
-use hyper::{ body::Bytes, client::conn::http1, Request, Response };
-use ratelimit::Ratelimiter;
+use chrono::Local;
+use http_body_util::{ BodyExt, Full, LengthLimitError, Limited };
+use hyper::body::{ Body, Bytes };
+use hyper::header::{ CONTENT_LENGTH, CONTENT_TYPE, HOST, REFERER, USER_AGENT };
+use hyper::{ client::conn::http1, Request, Response };
 use thiserror::Error;
-use tokio::sync::Mutex;
+use serde_json::json;
 use tokio::{ net::TcpStream, spawn, time::timeout };
 
-use crate::services::enforce_rate_limit;
+use crate::server::{ ConnectionInfo, RateLimiterMap };
+use crate::services::{
+  acquire_upstream_slot,
+  apply_forwarding_headers,
+  apply_header_rules,
+  config_ip_lists,
+  enforce_rate_limit,
+  header_vars,
+  is_upstream_drained,
+  record_rate_limit_decision,
+  record_request,
+  record_upstream_connect_error,
+  record_upstream_failure,
+  record_upstream_retry,
+  record_upstream_success,
+  resolve_client_ip,
+  strip_hop_by_hop_headers,
+  tracing_enabled,
+  ConfigIpLists,
+  Span,
+  SpanContext,
+  SpanKind,
+  DEFAULT_CLIENT_IP_HEADERS,
+  RateLimitDecision,
+  RateLimitResponse,
+};
 use crate::utils::{
+  current_request_id,
+  write_access_log,
   extract_host,
   get_forwarding_rule,
   http_error_response,
-  load_configs,
-  HttpMessageError,
+  current_configs,
+  prefers_json,
+  resolve_request_id,
+  set_request_id_header,
+  AccessLogEntry,
+  Configs,
+  IpRanges,
+  REQUEST_ID,
 };
 
 #[derive(Error, Debug)]
@@ -44,42 +80,207 @@ pub enum ProxyError {
 // const   RATE_LIMIT_HTML: &str = include!("/etc/sheldx/static/rate_limit.html");
 pub async fn handle_http_connections(
   req: Request<hyper::body::Incoming>,
-  client_ip: String,
-  rate_limiter_map: Arc<Mutex<HashMap<String, Ratelimiter>>>
+  conn: Arc<ConnectionInfo>,
+  rate_limiter_map: RateLimiterMap
+) -> Result<Response<Full<Bytes>>, ProxyError> {
+  let started_at = Instant::now();
+  let configs = current_configs().map_err(|_| ProxyError::ConfigError)?;
+  let request_id_config = configs.request_id.clone().unwrap_or_default();
+  let request_id = resolve_request_id(req.headers(), &request_id_config);
+  let access_logs = configs.access_logs.clone().unwrap_or_default();
+  let mut access_log = new_access_log_entry(&req, &conn, &request_id);
+
+  let mut request_span = Span::root(
+    &format!("{} {}", access_log.method, access_log.host),
+    SpanKind::Server,
+    SpanContext::from_headers(req.headers())
+  );
+  request_span.set_attribute("http.request.method", access_log.method.as_str());
+  request_span.set_attribute("url.path", req.uri().path());
+  request_span.set_attribute("server.address", access_log.host.as_str());
+  request_span.set_attribute("sheldx.request_id", request_id.as_str());
+  let trace = request_span.context().clone();
+
+  let request_timeout = conn.request_limits.request_timeout.map(Duration::from_secs);
+
+  // every log line emitted while proxying carries the request id
+  let mut result = REQUEST_ID.scope(request_id.clone(), async {
+    let ip_lists = config_ip_lists(&configs);
+    let proxied = proxy_request(req, conn, rate_limiter_map, &configs, &ip_lists, &mut access_log, &trace);
+    match request_timeout {
+      Some(request_timeout) =>
+        timeout(request_timeout, proxied).await.unwrap_or_else(|_| {
+          log::warn!("Gave up on the request after {:?}", request_timeout);
+          let Ok(response) = http_error_response(
+            504,
+            "The request took too long to complete. Please try again later.".to_string(),
+            "Gateway Timeout".to_string()
+          );
+          Ok(response)
+        }),
+      None => proxied.await,
+    }
+  }).await;
+
+  request_span.set_attribute("client.address", access_log.client_ip.as_str());
+  match &mut result {
+    Ok(response) => {
+      set_request_id_header(response.headers_mut(), &request_id_config, &request_id);
+      access_log.status = Some(response.status().as_u16());
+      access_log.bytes_out = response.body().size_hint().exact().unwrap_or(0);
+      request_span.set_attribute("http.response.status_code", response.status().as_u16() as i64);
+      if response.status().is_server_error() {
+        request_span.set_error(response.status());
+      }
+    }
+    Err(e) => request_span.set_error(e),
+  }
+  access_log.total_latency_ms = started_at.elapsed().as_secs_f64() * 1000.0;
+  write_access_log(&access_log, &access_logs);
+
+  // label by the matched rule rather than the raw host header to keep the series bounded
+  let (host, route) = match &access_log.upstream {
+    Some(upstream) => (access_log.host.as_str(), upstream.as_str()),
+    None => ("-", "-"),
+  };
+  let status = access_log.status.map(|status| status.to_string()).unwrap_or_else(|| "error".to_string());
+  record_request(host, route, &status, started_at.elapsed());
+
+  result
+}
+
+/// Starts the access log entry for `req`; the proxy fills in the rest as it goes.
+fn new_access_log_entry(
+  req: &Request<hyper::body::Incoming>,
+  conn: &ConnectionInfo,
+  request_id: &str
+) -> AccessLogEntry {
+  let header = |name| {
+    req
+      .headers()
+      .get(name)
+      .and_then(|value| value.to_str().ok())
+      .map(str::to_string)
+  };
+
+  AccessLogEntry {
+    timestamp: Local::now(),
+    request_id: request_id.to_string(),
+    client_ip: conn.peer_ip.to_string(),
+    host: header(HOST).unwrap_or_else(|| "-".to_string()),
+    method: req.method().to_string(),
+    path: req
+      .uri()
+      .path_and_query()
+      .map(|pq| pq.to_string())
+      .unwrap_or_else(|| "/".to_string()),
+    protocol: format!("{:?}", req.version()),
+    status: None,
+    bytes_in: header(CONTENT_LENGTH)
+      .and_then(|length| length.parse().ok())
+      .unwrap_or(0),
+    bytes_out: 0,
+    referer: header(REFERER),
+    user_agent: header(USER_AGENT),
+    upstream: None,
+    upstream_latency_ms: None,
+    total_latency_ms: 0.0,
+    rate_limit: RateLimitDecision::NoRule.as_str().to_string(),
+  }
+}
+
+async fn proxy_request(
+  req: Request<hyper::body::Incoming>,
+  conn: Arc<ConnectionInfo>,
+  rate_limiter_map: RateLimiterMap,
+  configs: &Configs,
+  ip_lists: &ConfigIpLists,
+  access_log: &mut AccessLogEntry,
+  trace: &SpanContext
 ) -> Result<Response<Full<Bytes>>, ProxyError> {
-  log::debug!("Client IP: {:?}", client_ip);
-  let connection_timeout: Duration = Duration::from_secs(5);
-  let max_retries: u8 = 3;
   let host = extract_host(&req).map_err(|_| ProxyError::HostError)?;
   log::debug!("Host: {:?}", host);
+  log::debug!("Configs: {}", configs.redacted());
+  let client_ip_headers = configs.client_ip_headers
+    .clone()
+    .unwrap_or_else(|| DEFAULT_CLIENT_IP_HEADERS.iter().map(|h| h.to_string()).collect());
+  let client_addr = resolve_client_ip(req.headers(), &conn.peer_ip, &ip_lists.trusted_proxies, &client_ip_headers);
+  let client_ip = client_addr.to_string();
+  log::debug!("Client IP: {:?} (peer {:?})", client_ip, conn.peer_ip);
+  access_log.client_ip = client_ip.clone();
 
-  let configs = load_configs().map_err(|_| ProxyError::ConfigError)?;
-  log::debug!("Configs: {:?}", configs);
+  // Turn away denied clients before they count against any rate limit
+  if let Some(access_list) = ip_lists.access_list(&host) {
+    if !access_list.allows(&client_addr) {
+      log::info!("Denied access to {} for {}", host, client_ip);
+      let Ok(response) = http_error_response(
+        403,
+        "Your IP address is not allowed to access this site.".to_string(),
+        "Forbidden".to_string()
+      );
+      return Ok(response);
+    }
+  }
 
-  let rate_limit_status = enforce_rate_limit(&req, &client_ip, &rate_limiter_map, &configs).await?;
+  let mut rate_limit_span = Span::child(trace, "rate-limit check", SpanKind::Internal);
+  let rate_limit_status = enforce_rate_limit(&req, &client_ip, &rate_limiter_map, configs, ip_lists).await?;
   log::debug!("Rate limit status: {:?}", rate_limit_status.response);
-  log::debug!("Rate limit status: {:?}", rate_limit_status.response);
-
-  if rate_limit_status.status_code != 200 {
-    let html_content = read_file_content("/etc/sheldx/static/rate_limit.html").map_err(
-      |_| ProxyError::RuleNotFound
-    )?;
-    let final_html = html_content
-      .replace("{{user_ip}}", &client_ip)
-      .replace("{{seconds}}", &rate_limit_status.seconds.to_string());
+  access_log.rate_limit = rate_limit_status.decision.as_str().to_string();
+  record_rate_limit_decision(rate_limit_status.decision);
+  rate_limit_span.set_attribute("sheldx.rate_limit.decision", rate_limit_status.decision.as_str());
+  drop(rate_limit_span);
 
-    return Ok(
-      Response::builder()
-        .status(rate_limit_status.status_code)
-        .body(Full::from(Bytes::from(final_html)))
-        .unwrap()
+  if rate_limit_status.decision == RateLimitDecision::Unavailable && rate_limit_status.status_code != 200 {
+    let Ok(response) = http_error_response(
+      503,
+      "Rate limits can't be checked right now. Please try again later.".to_string(),
+      "Service Unavailable".to_string()
     );
+    return Ok(response);
+  }
+
+  let mut response = if rate_limit_status.status_code != 200 {
+    show_rate_limited(&req, &client_ip, &rate_limit_status)
+  } else {
+    forward_request(req, &conn, configs, &client_addr, &ip_lists.trusted_proxies, access_log, trace).await?
+  };
+  rate_limit_status.set_headers(response.headers_mut());
+  Ok(response)
+}
+
+/// Sends a request that passed the rate limits to its upstream, or serves the static or
+/// default page when there are no forwarding rules.
+async fn forward_request(
+  req: Request<hyper::body::Incoming>,
+  conn: &ConnectionInfo,
+  configs: &Configs,
+  client_addr: &IpAddr,
+  trusted_proxies: &IpRanges,
+  access_log: &mut AccessLogEntry,
+  trace: &SpanContext
+) -> Result<Response<Full<Bytes>>, ProxyError> {
+  let upstream = configs.upstream.clone().unwrap_or_default();
+  let connection_timeout = Duration::from_secs(upstream.connect_timeout);
+  let max_retries = upstream.max_retries;
+  let host = extract_host(&req).map_err(|_| ProxyError::HostError)?;
+
+  // Turn away bodies declared too large up front, and cut off the others as they stream past
+  let max_body_size = conn.request_limits.max_body_size;
+  if let Some(max_body_size) = max_body_size {
+    let declared = req
+      .headers()
+      .get(CONTENT_LENGTH)
+      .and_then(|length| length.to_str().ok())
+      .and_then(|length| length.parse::<u64>().ok());
+    if declared.is_some_and(|length| length > (max_body_size as u64)) {
+      return Ok(show_payload_too_large(max_body_size));
+    }
   }
+  let mut req = req.map(|body| Limited::new(body, max_body_size.unwrap_or(usize::MAX)));
 
   // Handle static files or error responses if no forwarding rules are configured
-  let forwarding_rules = configs.forwarding_rules;
-  if forwarding_rules.is_none() {
-    if let Some(static_files_directory) = configs.static_files_directory {
+  if configs.forwarding_rules.is_none() {
+    if let Some(static_files_directory) = &configs.static_files_directory {
       log::debug!("Serving static file from directory: {}", static_files_directory);
       let file_content = fs
         ::read_to_string(static_files_directory)
@@ -96,43 +297,118 @@ pub async fn handle_http_connections(
   }
 
   // Get the forwarding rule for the host
-  let forwarding_rules = forwarding_rules.ok_or(ProxyError::NoForwardingRules)?;
+  let forwarding_rules = configs.forwarding_rules.as_ref().ok_or(ProxyError::NoForwardingRules)?;
   log::debug!("Forwarding Rules: {:?}", forwarding_rules);
 
-  let rule = get_forwarding_rule(&Some(forwarding_rules), &host).map_err(
+  let rule = get_forwarding_rule(&configs.forwarding_rules, &host).map_err(
     |_| ProxyError::RuleNotFound
   )?;
 
   let destination = rule.target;
   log::debug!("Destination: {:?}", destination);
+  access_log.upstream = Some(destination.clone());
+
+  if is_upstream_drained(&destination) {
+    log::info!("Not forwarding to drained upstream {}", destination);
+    let Ok(response) = http_error_response(
+      503,
+      "The upstream server is temporarily out of rotation. Please try again later.".to_string(),
+      "Service Unavailable".to_string()
+    );
+    return Ok(response);
+  }
+
+  // Hold a slot of the rule until its response has been read
+  let _slot = match &rule.concurrency {
+    Some(concurrency) => match acquire_upstream_slot(&rule.host, &destination, concurrency).await {
+      Ok(slot) => Some(slot),
+      Err(saturation) => {
+        log::warn!("Not forwarding to busy upstream {} ({})", destination, saturation.as_str());
+        let Ok(response) = http_error_response(
+          503,
+          "The upstream server is busy. Please try again later.".to_string(),
+          "Service Unavailable".to_string()
+        );
+        return Ok(response);
+      }
+    }
+    None => None,
+  };
+
+  let vars = header_vars(conn, client_addr, &host);
+  strip_hop_by_hop_headers(req.headers_mut());
+  let peer_is_trusted = trusted_proxies.contains(&conn.peer_ip);
+  apply_forwarding_headers(req.headers_mut(), conn, client_addr, &host, peer_is_trusted);
+  if let Some(request_id) = current_request_id() {
+    set_request_id_header(req.headers_mut(), &configs.request_id.clone().unwrap_or_default(), &request_id);
+  }
+  if let Some(request_headers) = &rule.request_headers {
+    apply_header_rules(req.headers_mut(), request_headers, &vars);
+  }
 
   let mut last_error = None;
   for attempt in 1..=max_retries {
+    let attempt_started_at = Instant::now();
+    let mut connect_span = Span::child(trace, "upstream connect", SpanKind::Internal);
+    connect_span.set_attribute("server.address", destination.as_str());
+    connect_span.set_attribute("sheldx.upstream.attempt", attempt as i64);
     match timeout(connection_timeout, TcpStream::connect(&destination)).await {
       Ok(Ok(stream)) => {
+        record_upstream_success(&destination);
         let io = hyper_util::rt::TokioIo::new(stream);
         let (mut send_request, connection) = http1
           ::handshake(io).await
           .map_err(|_| ProxyError::HttpCommError)?;
+        drop(connect_span);
 
-        spawn(async move {
+        // the connection is driven on its own task, which doesn't inherit the request id
+        let request_id = current_request_id().unwrap_or_default();
+        spawn(REQUEST_ID.scope(request_id, async move {
           if let Err(err) = connection.await {
             log::error!("Error serving connection: {:?}", err);
           }
-        });
+        }));
 
-        let res = send_request.send_request(req).await.map_err(|_| ProxyError::HttpCommError)?;
-        let (parts, body) = res.into_parts();
+        let mut upstream_span = Span::child(trace, "upstream request", SpanKind::Client);
+        upstream_span.set_attribute("server.address", destination.as_str());
+        if tracing_enabled() {
+          upstream_span.context().inject(req.headers_mut());
+        }
+
+        let res = match send_request.send_request(req).await {
+          Ok(res) => res,
+          Err(e) => {
+            upstream_span.set_error(&e);
+            if let Some(max_body_size) = max_body_size.filter(|_| is_body_too_large(&e)) {
+              return Ok(show_payload_too_large(max_body_size));
+            }
+            return Err(ProxyError::HttpCommError);
+          }
+        };
+        upstream_span.set_attribute("http.response.status_code", res.status().as_u16() as i64);
+        let (mut parts, body) = res.into_parts();
+        strip_hop_by_hop_headers(&mut parts.headers);
+        if let Some(response_headers) = &rule.response_headers {
+          apply_header_rules(&mut parts.headers, response_headers, &vars);
+        }
         let bytes = body.collect().await.map_err(|_| ProxyError::HttpCommError)?;
         let final_body: Full<Bytes> = Full::from(bytes.to_bytes());
+        access_log.upstream_latency_ms = Some(attempt_started_at.elapsed().as_secs_f64() * 1000.0);
+        drop(upstream_span);
 
         return Ok(Response::from_parts(parts, final_body));
       }
       Ok(Err(e)) => {
+        connect_span.set_error(&e);
+        record_upstream_connect_error(&destination, "error");
+        record_upstream_failure(&destination, &e.to_string());
         last_error = Some(ProxyError::ConnectionError(e.to_string()));
         log::warn!("Attempt {} to connect to {} failed: {}", attempt, destination, e);
       }
       Err(_) => {
+        connect_span.set_error("connection timed out");
+        record_upstream_connect_error(&destination, "timeout");
+        record_upstream_failure(&destination, "connection timed out");
         last_error = Some(ProxyError::ConnectionError("Connection timed out".to_string()));
         log::warn!("Attempt {} to connect to {} timed out", attempt, destination);
       }
@@ -140,10 +416,14 @@ pub async fn handle_http_connections(
 
     if attempt < max_retries {
       log::info!("Retrying connection to {} (attempt {})", destination, attempt + 1);
+      record_upstream_retry(&destination);
     }
   }
 
- Ok(show_internal_server_error())
+  if let Some(err) = last_error {
+    log::error!("Giving up on {} after {} attempts: {}", destination, max_retries, err);
+  }
+  Ok(show_internal_server_error())
 }
 
 fn read_file_content(path: &str) -> Result<String, std::io::Error> {
@@ -153,6 +433,63 @@ fn read_file_content(path: &str) -> Result<String, std::io::Error> {
   Ok(content)
 }
 
+/// The 429 page: JSON for API clients that ask for it, the `rate_limit.html` template otherwise.
+fn show_rate_limited<B>(req: &Request<B>, client_ip: &str, status: &RateLimitResponse) -> Response<Full<Bytes>> {
+  let request_id = current_request_id().unwrap_or_default();
+  if prefers_json(req.headers()) {
+    let body = json!({
+      "error": "rate_limited",
+      "message": status.response,
+      "retry_after": status.seconds,
+      "request_id": request_id,
+    });
+    return Response::builder()
+      .status(status.status_code)
+      .header(CONTENT_TYPE, "application/json")
+      .body(Full::from(Bytes::from(body.to_string())))
+      .unwrap();
+  }
+
+  match read_file_content("/etc/sheldx/static/rate_limit.html") {
+    Ok(html_content) => {
+      let final_html = html_content
+        .replace("{{user_ip}}", client_ip)
+        .replace("{{seconds}}", &status.seconds.to_string())
+        .replace("{{request_id}}", &request_id);
+      Response::builder()
+        .status(status.status_code)
+        .header(CONTENT_TYPE, "text/html")
+        .body(Full::from(Bytes::from(final_html)))
+        .unwrap()
+    }
+    Err(_) => {
+      let Ok(response) = http_error_response(status.status_code, status.response.clone(), "Too Many Requests".to_string());
+      response
+    }
+  }
+}
+
+fn show_payload_too_large(max_body_size: usize) -> Response<Full<Bytes>> {
+  let Ok(response) = http_error_response(
+    413,
+    format!("Request bodies are limited to {} bytes.", max_body_size),
+    "Payload Too Large".to_string()
+  );
+  response
+}
+
+/// Whether sending a request failed because its body went over `max_body_size`.
+fn is_body_too_large(err: &hyper::Error) -> bool {
+  let mut source = err.source();
+  while let Some(cause) = source {
+    if cause.is::<LengthLimitError>() {
+      return true;
+    }
+    source = cause.source();
+  }
+  false
+}
+
 fn show_default_page() -> Response<Full<Bytes>> {
   let file_content = read_file_content("/etc/sheldx/static/index.html").unwrap_or_else(|_|
     "Default page not found".to_string()
@@ -168,9 +505,12 @@ fn show_default_page() -> Response<Full<Bytes>> {
 
 
 fn show_internal_server_error() -> Response<Full<Bytes>> {
- let html_content = read_file_content("/etc/sheldx/static/internal_server_error.html").unwrap_or_else(|_| {
-     "<h1>Internal Server Error</h1>".to_string()
- });
+ let request_id = current_request_id().unwrap_or_default();
+ let html_content = read_file_content("/etc/sheldx/static/internal_server_error.html")
+   .map(|html| html.replace("{{request_id}}", &request_id))
+   .unwrap_or_else(|_| {
+     format!("<h1>Internal Server Error</h1><p>Request ID: {}</p>", request_id)
+   });
   Response::builder()
       .status(500)
       .body(Full::from(Bytes::from(html_content)))
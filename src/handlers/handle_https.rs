@@ -4,7 +4,7 @@ use hyper::{ body::{ Bytes, Incoming }, Request, Response };
 use super::ProxyError;
 
 pub async fn handle_https_connections(
-  req: Request<Incoming>
+  _req: Request<Incoming>
 ) -> Result<Response<Full<Bytes>>, ProxyError> {
 
     // setup https connection
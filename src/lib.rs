@@ -1,3 +1,4 @@
+pub mod cli;
 pub mod handlers;
 pub mod server;
 pub mod utils;
@@ -1,38 +1,15 @@
-use std::error::Error;
+use std::process::ExitCode;
 
-use server::start_sheldx;
-use utils::init_logger;
-mod handlers;
-mod utils;
-mod server;
-mod services;
+use clap::Parser;
+use sheldx::cli::{ run_cli, Cli };
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-  init_logger()?;
-
-  start_sheldx().await.map_err(|e| {
-    // check if the is related tp permission denied
-    if e.to_string().contains("Permission denied") {
-      // tell user to give sheldx permission to bind to port 80 by telling how to do it
-      log::error!(
-        "Permission denied. You may need to run Sheldx as root or give it permission to bind to port 80"
-      );
-      log::info!(
-        "You can give Sheldx permission to bind to port 80 or any other port by running the following command:"
-      );
-      log::info!("sudo setcap cap_net_bind_service=+ep /path/to/sheldx");
-    } else {
-      log::error!("Error starting Sheldx: {}", e);
+async fn main() -> ExitCode {
+  match run_cli(Cli::parse()).await {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(e) => {
+      eprintln!("Error: {}", e);
+      ExitCode::FAILURE
     }
-
-    e
-  })?;
-
-  // TODO: Implement graceful shutdown setup
-
-  Ok(())
+  }
 }
-
-
-
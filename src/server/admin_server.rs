@@ -0,0 +1,356 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::error::Error as StdError;
+use std::fs;
+use std::net::{ IpAddr, SocketAddr };
+use std::os::unix::fs::PermissionsExt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use http_body_util::Full;
+use hyper::body::{ Bytes, Incoming };
+use hyper::header::AUTHORIZATION;
+use hyper::server::conn::http1 as http1_serevr;
+use hyper::service::service_fn;
+use hyper::{ Method, Request, Response };
+use hyper_util::rt::TokioIo;
+use serde_json::{ json, Value };
+use tokio::io::{ AsyncRead, AsyncWrite };
+use tokio::net::{ TcpListener, UnixListener };
+
+use super::{ listeners, reload_configs, RateLimiterMap };
+use crate::services::{ active_connections, set_upstream_drained, unix_now, upstream_state };
+use crate::utils::{ current_configs, AdminConfig };
+
+/// Pause after a failed accept, so running out of file descriptors doesn't spin the loop.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Binds the admin API, on a Unix socket when one is configured and on `listen` otherwise, and
+/// serves it in the background.
+pub async fn spawn_admin_server(
+    config: AdminConfig,
+    rate_limiter_map: RateLimiterMap
+) -> Result<(), Box<dyn StdError>> {
+    if config.token.is_empty() {
+        return Err("The admin API requires a non-empty token".into());
+    }
+    let token = Arc::new(config.token);
+
+    if let Some(socket) = config.socket {
+        // a socket left behind by a previous run would make the bind fail
+        if fs::metadata(&socket).is_ok() {
+            fs::remove_file(&socket)?;
+        }
+        let listener = UnixListener::bind(&socket).map_err(|e| {
+            log::error!("Failed to bind admin socket {}: {}", socket, e);
+            e
+        })?;
+        fs::set_permissions(&socket, fs::Permissions::from_mode(0o600))?;
+        log::info!("Serving admin API on unix:{}", socket);
+
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => serve_connection(stream, token.clone(), rate_limiter_map.clone()),
+                    Err(e) => {
+                        log::error!("Failed to accept admin connection: {}", e);
+                        tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    }
+                }
+            }
+        });
+        return Ok(());
+    }
+
+    let listener = TcpListener::bind(&config.listen).await.map_err(|e| {
+        log::error!("Failed to bind admin listener to {}: {}", config.listen, e);
+        e
+    })?;
+    if !listener.local_addr().is_ok_and(|addr: SocketAddr| addr.ip().is_loopback()) {
+        log::warn!("The admin API is listening on {}, which is not a loopback address", config.listen);
+    }
+    log::info!("Serving admin API on http://{}", config.listen);
+
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => serve_connection(stream, token.clone(), rate_limiter_map.clone()),
+                Err(e) => {
+                    log::error!("Failed to accept admin connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                }
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn serve_connection<S>(stream: S, token: Arc<String>, rate_limiter_map: RateLimiterMap)
+    where S: AsyncRead + AsyncWrite + Unpin + Send + 'static
+{
+    tokio::spawn(async move {
+        let service = service_fn(move |req| serve_admin(req, token.clone(), rate_limiter_map.clone()));
+        if let Err(err) = http1_serevr::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+            log::debug!("Error serving admin connection: {:?}", err);
+        }
+    });
+}
+
+async fn serve_admin(
+    req: Request<Incoming>,
+    token: Arc<String>,
+    rate_limiter_map: RateLimiterMap
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    if !is_authorized(&req, &token) {
+        return Ok(json_response(401, json!({ "error": "missing or invalid bearer token" })));
+    }
+
+    let params = query_params(&req);
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/config") => config_snapshot(),
+        (&Method::GET, "/listeners") => listener_status(),
+        (&Method::GET, "/connections") => connection_counts(),
+        (&Method::GET, "/upstreams") => upstream_health(),
+        (&Method::GET, "/ratelimit") => rate_limit_state(&rate_limiter_map, params.get("key"), params.get("rule")).await,
+        (&Method::POST, "/reload") => reload(),
+        (&Method::POST, "/upstreams/drain") => drain_upstream(params.get("target"), true),
+        (&Method::POST, "/upstreams/undrain") => drain_upstream(params.get("target"), false),
+        (&Method::POST, "/ratelimit/reset") =>
+            reset_rate_limit(&rate_limiter_map, params.get("key"), params.get("rule")).await,
+        (
+            _,
+            | "/config"
+            | "/listeners"
+            | "/connections"
+            | "/upstreams"
+            | "/ratelimit"
+            | "/reload"
+            | "/upstreams/drain"
+            | "/upstreams/undrain"
+            | "/ratelimit/reset",
+        ) => json_response(405, json!({ "error": "method not allowed" })),
+        _ => json_response(404, json!({ "error": "not found" })),
+    };
+
+    log::info!("Admin API: {} {} -> {}", req.method(), req.uri().path(), response.status());
+    Ok(response)
+}
+
+fn config_snapshot() -> Response<Full<Bytes>> {
+    let configs = match current_configs() {
+        Ok(configs) => configs,
+        Err(e) => {
+            return json_response(500, json!({ "error": e.to_string() }));
+        }
+    };
+
+    json_response(200, configs.redacted())
+}
+
+fn listener_status() -> Response<Full<Bytes>> {
+    let listeners: Vec<Value> = listeners()
+        .into_iter()
+        .map(|listener| {
+            json!({
+                "name": listener.name,
+                "address": listener.address,
+                "tls": listener.tls,
+                "active_connections": active_connections(listener.name),
+            })
+        })
+        .collect();
+    json_response(200, json!(listeners))
+}
+
+fn connection_counts() -> Response<Full<Bytes>> {
+    let counts: HashMap<&str, i64> = listeners()
+        .iter()
+        .map(|listener| (listener.name, active_connections(listener.name)))
+        .collect();
+    let total: i64 = counts.values().sum();
+    json_response(200, json!({ "listeners": counts, "total": total }))
+}
+
+/// Health of every configured upstream, based on the outcome of recent connection attempts.
+/// Upstreams that haven't been contacted yet are reported with an unknown (`null`) health.
+fn upstream_health() -> Response<Full<Bytes>> {
+    let configs = match current_configs() {
+        Ok(configs) => configs,
+        Err(e) => {
+            return json_response(500, json!({ "error": e.to_string() }));
+        }
+    };
+
+    let mut hosts_by_target: Vec<(String, Vec<String>)> = Vec::new();
+    for rule in configs.forwarding_rules.clone().unwrap_or_default() {
+        match hosts_by_target.iter_mut().find(|(target, _)| *target == rule.target) {
+            Some((_, hosts)) => hosts.push(rule.host),
+            None => hosts_by_target.push((rule.target, vec![rule.host])),
+        }
+    }
+
+    let upstreams: Vec<Value> = hosts_by_target
+        .into_iter()
+        .map(|(target, hosts)| {
+            let state = upstream_state(&target);
+            json!({
+                "target": target,
+                "hosts": hosts,
+                "healthy": state.as_ref().filter(|state| state.last_success.is_some() || state.last_failure.is_some()).map(|state| state.is_healthy()),
+                "drained": state.as_ref().is_some_and(|state| state.drained),
+                "consecutive_failures": state.as_ref().map(|state| state.consecutive_failures).unwrap_or(0),
+                "last_error": state.as_ref().and_then(|state| state.last_error.clone()),
+                "last_success": state.as_ref().and_then(|state| state.last_success).map(|time| time.to_rfc3339()),
+                "last_failure": state.as_ref().and_then(|state| state.last_failure).map(|time| time.to_rfc3339()),
+            })
+        })
+        .collect();
+    json_response(200, json!(upstreams))
+}
+
+/// The local limiters of a key under every rule, or only under `rule`.
+async fn rate_limit_state(
+    rate_limiter_map: &RateLimiterMap,
+    key: Option<&String>,
+    rule: Option<&String>
+) -> Response<Full<Bytes>> {
+    let Some(key) = key else {
+        return json_response(400, json!({ "error": "the key query parameter is required" }));
+    };
+    let key = limiter_key(key);
+
+    let limiters: Vec<Value> = rate_limiter_map
+        .snapshot(&key, rule.map(String::as_str), unix_now())
+        .into_iter()
+        .map(|limiter| {
+            json!({
+                "rule": limiter.key.rule,
+                "key": limiter.key.key,
+                "algorithm": limiter.algorithm,
+                "available": limiter.remaining,
+                "limit": limiter.params.limit,
+                "window_ms": limiter.params.window.as_millis() as u64,
+                "max_tokens": limiter.params.burst,
+                "idle_ms": limiter.idle.as_millis() as u64,
+            })
+        })
+        .collect();
+
+    if limiters.is_empty() {
+        return json_response(404, json!({ "error": format!("no rate-limit bucket for {}", key) }));
+    }
+    json_response(200, json!(limiters))
+}
+
+fn reload() -> Response<Full<Bytes>> {
+    match reload_configs() {
+        Ok(_) => json_response(200, json!({ "status": "reloaded" })),
+        Err(e) => json_response(500, json!({ "error": e.to_string() })),
+    }
+}
+
+fn drain_upstream(target: Option<&String>, drained: bool) -> Response<Full<Bytes>> {
+    let Some(target) = target else {
+        return json_response(400, json!({ "error": "the target query parameter is required" }));
+    };
+
+    // refuse unknown targets so a typo doesn't look like a successful drain
+    let is_configured = current_configs()
+        .ok()
+        .is_some_and(|configs| {
+            configs.forwarding_rules.iter().flatten().any(|rule| rule.target == *target)
+        });
+    if !is_configured {
+        return json_response(404, json!({ "error": format!("{} is not a configured upstream", target) }));
+    }
+
+    set_upstream_drained(target, drained);
+    log::warn!("Upstream {} {} via the admin API", target, if drained { "drained" } else { "undrained" });
+    json_response(200, json!({ "target": target, "drained": drained }))
+}
+
+async fn reset_rate_limit(
+    rate_limiter_map: &RateLimiterMap,
+    key: Option<&String>,
+    rule: Option<&String>
+) -> Response<Full<Bytes>> {
+    let Some(key) = key else {
+        return json_response(400, json!({ "error": "the key query parameter is required" }));
+    };
+    let key = limiter_key(key);
+
+    let removed = rate_limiter_map.remove(&key, rule.map(String::as_str));
+    json_response(200, json!({ "key": key, "reset": removed > 0, "limiters": removed }))
+}
+
+/// The limiter key named by the `key` parameter, which may be a bare client IP instead of
+/// `ip:<address>`.
+fn limiter_key(key: &str) -> String {
+    match key.parse::<IpAddr>() {
+        Ok(ip) => format!("ip:{}", ip),
+        Err(_) => key.to_string(),
+    }
+}
+
+/// Checks the `Authorization: Bearer <token>` header, comparing in constant time.
+fn is_authorized(req: &Request<Incoming>, token: &str) -> bool {
+    let Some(presented) = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+    else {
+        return false;
+    };
+
+    let (presented, token) = (presented.trim().as_bytes(), token.as_bytes());
+    presented.len() == token.len() &&
+        presented
+            .iter()
+            .zip(token)
+            .fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn query_params(req: &Request<Incoming>) -> HashMap<String, String> {
+    req.uri()
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            Some((percent_decode(name), percent_decode(value)))
+        })
+        .collect()
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .filter(|_| bytes[i] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(if bytes[i] == b'+' { b' ' } else { bytes[i] });
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn json_response(status: u16, body: Value) -> Response<Full<Bytes>> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Full::from(Bytes::from(body.to_string())))
+        .unwrap()
+}
@@ -0,0 +1,63 @@
+use std::convert::Infallible;
+use std::error::Error as StdError;
+use std::time::Duration;
+
+use http_body_util::Full;
+use hyper::body::{ Bytes, Incoming };
+use hyper::server::conn::http1 as http1_serevr;
+use hyper::service::service_fn;
+use hyper::{ Request, Response };
+use hyper_util::rt::TokioIo;
+use tokio::net::TcpListener;
+
+use crate::services::gather_metrics;
+use crate::utils::MetricsConfig;
+
+/// Pause after a failed accept, so running out of file descriptors doesn't spin the loop.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Binds the metrics listener and serves it in the background.
+pub async fn spawn_metrics_server(config: MetricsConfig) -> Result<(), Box<dyn StdError>> {
+    let listener = TcpListener::bind(&config.listen).await.map_err(|e| {
+        log::error!("Failed to bind metrics listener to {}: {}", config.listen, e);
+        e
+    })?;
+    log::info!("Serving metrics on http://{}{}", config.listen, config.path);
+
+    tokio::spawn(async move {
+        loop {
+            let stream = match listener.accept().await {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    log::error!("Failed to accept metrics connection: {}", e);
+                    tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                    continue;
+                }
+            };
+
+            let path = config.path.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req| serve_metrics(req, path.clone()));
+                if let Err(err) = http1_serevr::Builder::new().serve_connection(TokioIo::new(stream), service).await {
+                    log::debug!("Error serving metrics connection: {:?}", err);
+                }
+            });
+        }
+    });
+
+    Ok(())
+}
+
+async fn serve_metrics(req: Request<Incoming>, path: String) -> Result<Response<Full<Bytes>>, Infallible> {
+    if req.uri().path() != path {
+        return Ok(Response::builder().status(404).body(Full::from(Bytes::from("Not Found"))).unwrap());
+    }
+
+    Ok(
+        Response::builder()
+            .status(200)
+            .header("Content-Type", "text/plain; version=0.0.4")
+            .body(Full::from(Bytes::from(gather_metrics())))
+            .unwrap()
+    )
+}
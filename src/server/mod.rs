@@ -1,4 +1,10 @@
+mod admin_server;
+mod metrics_server;
+mod reload;
 mod start;
 mod start_sheldx;
+pub use admin_server::*;
+pub use metrics_server::*;
+pub use reload::*;
 pub use start::*;
 pub use start_sheldx::*;
\ No newline at end of file
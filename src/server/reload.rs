@@ -0,0 +1,36 @@
+use tokio::signal::unix::{ signal, SignalKind };
+
+use crate::services::{ apply_connection_limits, record_config_reload };
+use std::sync::Arc;
+
+use crate::utils::{ apply_log_levels, load_configs, set_current_configs, ConfigError, Configs };
+
+/// Reloads the configuration file and applies the settings that can change at runtime.
+///
+/// New requests pick up forwarding and rate-limit rules from the reloaded configuration; settings
+/// held elsewhere, such as log levels and connection limits, are re-applied here.
+pub fn reload_configs() -> Result<Arc<Configs>, ConfigError> {
+    let configs = load_configs().inspect_err(|_| record_config_reload(false))?;
+    let configs = set_current_configs(configs);
+    record_config_reload(true);
+    apply_log_levels(&configs.logging.clone().unwrap_or_default());
+    apply_connection_limits(&configs);
+    log::info!("Configuration reloaded");
+    Ok(configs)
+}
+
+/// Reloads the configuration whenever the process receives `SIGHUP`.
+pub fn spawn_reload_on_sighup() -> Result<(), std::io::Error> {
+    let mut hangups = signal(SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        while hangups.recv().await.is_some() {
+            log::info!("Received SIGHUP, reloading configuration");
+            if let Err(e) = reload_configs() {
+                log::error!("Failed to reload configuration, keeping the previous settings: {}", e);
+            }
+        }
+    });
+
+    Ok(())
+}
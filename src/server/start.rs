@@ -1,32 +1,77 @@
-use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::net::{ IpAddr, Ipv4Addr, SocketAddr };
-use std::{ error::Error as StdError, sync::Arc };
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::time::{ Duration, Instant };
+use std::{ error::Error as StdError, sync::{ Arc, Mutex, RwLock } };
 use async_trait::async_trait;
 use hyper::server::conn::http1 as http1_serevr;
 use hyper::service::service_fn;
-use hyper_util::rt::TokioIo;
+use hyper_util::rt::{ TokioIo, TokioTimer };
+use lazy_static::lazy_static;
 use rustls::ServerConfig;
-use tokio::io::AsyncWriteExt;
-use tokio::net::TcpListener;
-use tokio::sync::Mutex;
+use serde::Serialize;
+use tokio::net::{ TcpListener, TcpStream };
 use tokio_rustls::TlsAcceptor;
 
-use crate::handlers::{ handle_http_connections, handle_https_connections };
-use crate::utils::load_configs;
-use ratelimit::Ratelimiter;
+use crate::handlers::handle_http_connections;
+use crate::services::{ record_tls_handshake_failure, request_limits, LimiterStore, ListenerConnections };
+use crate::utils::{ current_configs, RequestLimitsConfig };
 
-pub type RateLimiterMap = Arc<Mutex<HashMap<String, Ratelimiter>>>;
+pub type RateLimiterMap = Arc<LimiterStore>;
+
+/// Pause after running out of file descriptors, so the accept loop doesn't spin until some are
+/// released.
+const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Details about the downstream connection a request arrived on.
+#[derive(Debug, Clone)]
+pub struct ConnectionInfo {
+  /// Address of the directly connected peer, which may be another proxy.
+  pub peer_ip: IpAddr,
+  pub is_tls: bool,
+  pub tls_version: Option<String>,
+  pub tls_cipher: Option<String>,
+  pub tls_sni: Option<String>,
+  /// The request limits in force when the connection was accepted. Changes on reload apply to
+  /// new connections.
+  pub request_limits: RequestLimitsConfig,
+}
+
+/// A listener accepting client connections.
+#[derive(Debug, Clone, Serialize)]
+pub struct ListenerInfo {
+  pub name: &'static str,
+  pub address: String,
+  pub tls: bool,
+}
+
+lazy_static! {
+  static ref LISTENERS: RwLock<Vec<ListenerInfo>> = RwLock::new(Vec::new());
+}
+
+/// Listeners that are currently bound.
+pub fn listeners() -> Vec<ListenerInfo> {
+  LISTENERS.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone()
+}
+
+fn register_listener(name: &'static str, address: SocketAddr, tls: bool) {
+  let mut listeners = LISTENERS.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+  listeners.push(ListenerInfo { name, address: address.to_string(), tls });
+}
 
 /// A struct representing a server that does not use TLS.
 pub struct WithoutTLS {
   pub port: Option<u16>, // Default port for HTTP is 80, but user can change it
+  /// Rate-limit buckets, shared with the other listeners and the admin API.
+  pub rate_limiter_map: RateLimiterMap,
 }
 pub struct WithTLS {
   pub port: Option<u16>, // Default port for HTTPS is 443, but user can change it
+  pub rate_limiter_map: RateLimiterMap,
 }
 
+#[allow(clippy::upper_case_acronyms)]
 pub enum PORTS {
   HTTP = 8080,
   HTTPS = 443,
@@ -41,7 +86,7 @@ pub trait Server {
 #[async_trait]
 impl Server for WithoutTLS {
   async fn start(&self) -> Result<(), Box<dyn StdError>> {
-    let configs = load_configs()?;
+    let configs = current_configs()?;
     // chheck if tehre is atleast one ule
 
     log::warn!("Sheldx recommends using TLS for production use");
@@ -53,38 +98,43 @@ impl Server for WithoutTLS {
       log::error!("Failed to bind to address: {}", e);
       e
     })?;
-
-    let rate_limiter_map: RateLimiterMap = Arc::new(Mutex::new(HashMap::new()));
+    register_listener("http", addr, false);
+    let connections = ListenerConnections::new("http");
 
     loop {
-      let (stream, _) = listener.accept().await?;
-      let client_ip = stream.peer_addr()?.ip().to_string();
-      log::info!("Accepted connection from: {}", client_ip);
+      connections.wait_for_room().await;
+      let Some((stream, peer_addr)) = accept(&listener).await else {
+        continue;
+      };
+      let peer_ip = peer_addr.ip();
+      log::info!("Accepted connection from: {}", peer_ip);
+      let connection_permit = match connections.admit(&peer_ip) {
+        Ok(permit) => permit,
+        Err(refusal) => {
+          log::warn!("Refused connection from {}: {}", peer_ip, refusal.as_str());
+          continue;
+        }
+      };
 
-      let rate_limiter_map = rate_limiter_map.clone();
+      let rate_limiter_map = self.rate_limiter_map.clone();
       let io = TokioIo::new(stream);
 
       if configs.show_logs_on_console {
-        log::info!("Accepted connection from: {}", client_ip);
+        log::info!("Accepted connection from: {}", peer_ip);
       }
 
+      let conn = Arc::new(ConnectionInfo {
+        peer_ip,
+        is_tls: false,
+        tls_version: None,
+        tls_cipher: None,
+        tls_sni: None,
+        request_limits: request_limits(),
+      });
+
       tokio::spawn(async move {
-        if
-          let Err(err) = http1_serevr::Builder
-            ::new()
-            .auto_date_header(true)
-            .half_close(true)
-            .serve_connection(
-              io,
-              service_fn(move |req| {
-                let rate_limiter_map = rate_limiter_map.clone();
-                handle_http_connections(req, client_ip.clone(), rate_limiter_map)
-              })
-            ).await
-        {
-          // show 500 error
-          log::error!("Error serving connection: {:?}", err);
-        }
+        let _connection_permit = connection_permit;
+        serve_connection(io, conn, rate_limiter_map).await;
       });
     }
   }
@@ -95,14 +145,14 @@ impl Server for WithTLS {
   async fn start(&self) -> Result<(), Box<dyn StdError>> {
     // setup the keys
 
-    let configs = load_configs()?;
+    let configs = current_configs()?;
     let port = self.port.unwrap_or(PORTS::HTTPS as u16);
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), 443);
+    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), port);
 
     log::info!("Starting server on: {}", addr);
 
-    let mut cert_path = configs.cert_path.clone();
-    let mut key_path = configs.key_path.clone();
+    let cert_path = configs.cert_path.clone();
+    let key_path = configs.key_path.clone();
 
     let cert_file = &mut BufReader::new(File::open(cert_path).unwrap());
     let key_file = &mut BufReader::new(File::open(key_path).unwrap());
@@ -118,41 +168,190 @@ impl Server for WithTLS {
       .with_no_client_auth()
       .with_single_cert(certs, private_key);
 
-    let listener = TcpListener::bind(format!("0.0.0.0:{}", 4444)).await.unwrap();
+    let listener = TcpListener::bind(&addr).await.map_err(|e| {
+      log::error!("Failed to bind to address: {}", e);
+      e
+    })?;
     let tls_acceptor = TlsAcceptor::from(Arc::new(config.unwrap()));
-    let rate_limiter_map: RateLimiterMap = Arc::new(Mutex::new(HashMap::new()));
+    register_listener("https", addr, true);
+    let connections = ListenerConnections::new("https");
 
     loop {
-      let (mut stream, _) = listener.accept().await?;
-      let client_ip = stream.peer_addr()?.ip().to_string();
-      log::info!("Accepted connection from: {}", client_ip);
-
-      let rate_limiter_map = rate_limiter_map.clone();
+      connections.wait_for_room().await;
+      let Some((stream, peer_addr)) = accept(&listener).await else {
+        continue;
+      };
+      let peer_ip = peer_addr.ip();
+      log::info!("Accepted connection from: {}", peer_ip);
+      let connection_permit = match connections.admit(&peer_ip) {
+        Ok(permit) => permit,
+        Err(refusal) => {
+          log::warn!("Refused connection from {}: {}", peer_ip, refusal.as_str());
+          continue;
+        }
+      };
 
-      // write simple http sresponse i am from sheldx https server
+      let rate_limiter_map = self.rate_limiter_map.clone();
+      let tls_acceptor = tls_acceptor.clone();
+      let request_limits = request_limits();
 
-      let mut https_stream = tls_acceptor.accept(stream).await.unwrap();
-      let io = TokioIo::new(https_stream);
+      // handshake inside the task so a slow client can't hold up the accept loop
       tokio::spawn(async move {
-        if
-          let Err(err) = http1_serevr::Builder
-            ::new()
-            .auto_date_header(true)
-            .half_close(true)
-            .serve_connection(
-              io,
-              service_fn(move |req| {
-                
-                  let rate_limiter_map = rate_limiter_map.clone();
-               handle_http_connections(req, client_ip.clone(), rate_limiter_map)
-                
-                 })
-            ).await
-        {
-          // show 500 error
+        let _connection_permit = connection_permit;
+        serve_tls_connection(stream, peer_ip, tls_acceptor, request_limits, rate_limiter_map).await;
+      });
+    }
+  }
+}
+
+/// Completes the TLS handshake of an accepted connection and serves its requests. A client that
+/// hasn't finished the handshake within the `header_read_timeout` is disconnected.
+pub async fn serve_tls_connection(
+  stream: TcpStream,
+  peer_ip: IpAddr,
+  tls_acceptor: TlsAcceptor,
+  request_limits: RequestLimitsConfig,
+  rate_limiter_map: RateLimiterMap
+) {
+  let handshake_timeout = Duration::from_secs(request_limits.header_read_timeout);
+  let https_stream = match tokio::time::timeout(handshake_timeout, tls_acceptor.accept(stream)).await {
+    Ok(Ok(https_stream)) => https_stream,
+    Ok(Err(e)) => {
+      record_tls_handshake_failure("https");
+      log::warn!("TLS handshake with {} failed: {}", peer_ip, e);
+      return;
+    }
+    Err(_) => {
+      record_tls_handshake_failure("https");
+      log::warn!("TLS handshake with {} timed out after {:?}", peer_ip, handshake_timeout);
+      return;
+    }
+  };
+  let (_, tls_session) = https_stream.get_ref();
+  let conn = Arc::new(ConnectionInfo {
+    peer_ip,
+    is_tls: true,
+    tls_version: tls_session.protocol_version().and_then(|v| v.as_str()).map(str::to_string),
+    tls_cipher: tls_session
+      .negotiated_cipher_suite()
+      .and_then(|suite| suite.suite().as_str())
+      .map(str::to_string),
+    tls_sni: tls_session.server_name().map(str::to_string),
+    request_limits,
+  });
+  let io = TokioIo::new(https_stream);
+  serve_connection(io, conn, rate_limiter_map).await;
+}
+
+/// Accepts the next connection. Failures only cost that connection, so they are logged and the
+/// listener keeps going.
+async fn accept(listener: &TcpListener) -> Option<(TcpStream, SocketAddr)> {
+  match listener.accept().await {
+    Ok(accepted) => Some(accepted),
+    Err(e) => {
+      log::error!("Failed to accept connection: {}", e);
+      if matches!(e.raw_os_error(), Some(libc::EMFILE | libc::ENFILE)) {
+        tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+      }
+      None
+    }
+  }
+}
+
+/// Serves the requests of an accepted connection, with the `request_limits` in force when it
+/// was accepted.
+pub async fn serve_connection<I>(io: I, conn: Arc<ConnectionInfo>, rate_limiter_map: RateLimiterMap)
+  where I: hyper::rt::Read + hyper::rt::Write + Unpin + Send + 'static
+{
+  let limits = conn.request_limits.clone();
+  let peer_ip = conn.peer_ip;
+  let activity = Arc::new(ConnectionActivity::default());
+  let service_activity = activity.clone();
+  let connection = http1_serevr::Builder
+    ::new()
+    .timer(TokioTimer::new())
+    .header_read_timeout(Duration::from_secs(limits.header_read_timeout))
+    .max_buf_size(limits.max_header_size)
+    .max_headers(limits.max_headers)
+    .auto_date_header(true)
+    .half_close(true)
+    .serve_connection(
+      io,
+      service_fn(move |req| {
+        let rate_limiter_map = rate_limiter_map.clone();
+        let conn = conn.clone();
+        let request = service_activity.start_request();
+        async move {
+          let _request = request;
+          handle_http_connections(req, conn, rate_limiter_map).await
+        }
+      })
+    );
+  tokio::pin!(connection);
+
+  // close connections left idle between requests, once any response in progress is sent
+  let keep_alive_timeout = Duration::from_secs(limits.keep_alive_timeout);
+  let mut closing = false;
+  loop {
+    tokio::select! {
+      result = connection.as_mut() => {
+        if let Err(err) = result {
           log::error!("Error serving connection: {:?}", err);
         }
-      });
+        return;
+      }
+      _ = activity.idle_for(keep_alive_timeout), if !closing => {
+        log::debug!("Closing connection from {} after {:?} idle", peer_ip, keep_alive_timeout);
+        closing = true;
+        connection.as_mut().graceful_shutdown();
+      }
+    }
+  }
+}
+
+/// When a connection last finished a request, to tell how long it has been idle.
+pub struct ConnectionActivity {
+  in_progress: AtomicUsize,
+  idle_since: Mutex<Instant>,
+}
+
+/// A request being handled, which keeps its connection from counting as idle.
+pub struct RequestInProgress {
+  activity: Arc<ConnectionActivity>,
+}
+
+impl Default for ConnectionActivity {
+  fn default() -> Self {
+    ConnectionActivity { in_progress: AtomicUsize::new(0), idle_since: Mutex::new(Instant::now()) }
+  }
+}
+
+impl ConnectionActivity {
+  pub fn start_request(self: &Arc<Self>) -> RequestInProgress {
+    self.in_progress.fetch_add(1, Ordering::SeqCst);
+    RequestInProgress { activity: self.clone() }
+  }
+
+  /// Completes once the connection has gone `timeout` without a request in progress.
+  pub async fn idle_for(&self, timeout: Duration) {
+    loop {
+      let idle_since = *self.idle_since.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+      let deadline = if self.in_progress.load(Ordering::SeqCst) > 0 {
+        Instant::now() + timeout
+      } else {
+        idle_since + timeout
+      };
+      if self.in_progress.load(Ordering::SeqCst) == 0 && Instant::now() >= deadline {
+        return;
+      }
+      tokio::time::sleep_until(deadline.into()).await;
     }
   }
 }
+
+impl Drop for RequestInProgress {
+  fn drop(&mut self) {
+    *self.activity.idle_since.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Instant::now();
+    self.activity.in_progress.fetch_sub(1, Ordering::SeqCst);
+  }
+}
@@ -1,17 +1,55 @@
 
-use crate::{server::{WithTLS, PORTS}, utils::{load_configs, start_redis}};
-use super::{Server, WithoutTLS};
-use std::error::Error;
+use crate::{server::{WithTLS, PORTS}, services::{apply_connection_limits, init_tracing, spawn_limiter_sweeper, LimiterStore}, utils::{config_path, current_configs, start_redis, validate_config_file, Severity}};
+use super::{spawn_admin_server, spawn_metrics_server, spawn_reload_on_sighup, RateLimiterMap, Server, WithoutTLS};
+use std::{error::Error, sync::Arc, time::Duration};
 
-pub async fn start_sheldx() -> Result<(), Box<dyn Error>> {
-    let configs = load_configs()?;
+/// Listener settings given on the command line, which win over the defaults.
+#[derive(Debug, Clone, Default)]
+pub struct ListenerOverrides {
+    pub http_port: Option<u16>,
+    pub https_port: Option<u16>,
+}
+
+pub async fn start_sheldx(overrides: ListenerOverrides) -> Result<(), Box<dyn Error>> {
+    let report = validate_config_file(&config_path());
+    for diagnostic in &report.diagnostics {
+        match diagnostic.severity {
+            Severity::Error => log::error!("{}", diagnostic),
+            Severity::Warning => log::warn!("{}", diagnostic),
+        }
+    }
+    if report.has_errors() {
+        return Err("Invalid configuration, fix the errors above or run `sheldx check-config`".into());
+    }
+    let configs = current_configs()?;
+    spawn_reload_on_sighup()?;
+    apply_connection_limits(&configs);
+
+    if let Some(metrics) = configs.metrics.clone().filter(|metrics| metrics.enabled) {
+        spawn_metrics_server(metrics).await?;
+    }
+
+    if let Some(tracing) = configs.tracing.as_ref().filter(|tracing| tracing.enabled) {
+        init_tracing(tracing)?;
+    }
+
+    // one set of buckets for every listener, so the admin API can inspect and reset them
+    let settings = configs.rate_limiter.clone().unwrap_or_default();
+    let rate_limiter_map: RateLimiterMap = Arc::new(
+        LimiterStore::new(settings.max_entries, Duration::from_secs(settings.idle_timeout))
+    );
+    spawn_limiter_sweeper(rate_limiter_map.clone());
+
+    if let Some(admin) = configs.admin.clone().filter(|admin| admin.enabled) {
+        spawn_admin_server(admin, rate_limiter_map.clone()).await?;
+    }
 
     if configs.is_tls_enabled {
         // TLS support not implemented yet
-      let server = WithTLS { port: Some(PORTS::HTTPS as u16) };
+      let server = WithTLS { port: overrides.https_port.or(Some(PORTS::HTTPS as u16)), rate_limiter_map: rate_limiter_map.clone() };
         if let Err(e) = server.start().await {
             log::error!("Error starting server: {}", e);
-            return Err(e.into());
+            return Err(e);
         }
     }
 
@@ -19,10 +57,10 @@ pub async fn start_sheldx() -> Result<(), Box<dyn Error>> {
     log::warn!("Sheldx recommends using TLS for production use.");
 
     // Start the server without TLS
-    let server = WithoutTLS { port: Some(PORTS::HTTP as u16) };
+    let server = WithoutTLS { port: overrides.http_port.or(Some(PORTS::HTTP as u16)), rate_limiter_map };
     if let Err(e) = server.start().await {
         log::error!("Error starting server: {}", e);
-        return Err(e.into());
+        return Err(e);
     }
 
     // Attempt to start Redis, with fallback to in-memory cache if it fails
@@ -0,0 +1,99 @@
+use std::net::{ IpAddr, SocketAddr };
+
+use hyper::header::HeaderMap;
+
+use crate::utils::{ canonical_ip, IpRanges };
+
+/// Headers consulted when `client_ip_headers` is not configured.
+pub const DEFAULT_CLIENT_IP_HEADERS: [&str; 1] = ["x-forwarded-for"];
+
+/// Works out the address of the real client behind any trusted proxies.
+///
+/// Headers are only consulted when the directly connected peer is in `trusted_proxies`, and are
+/// tried in the order given by `sources`. `X-Forwarded-For` and `Forwarded` are walked from the
+/// right, skipping trusted hops, so a client cannot spoof its address by prepending entries.
+/// Any other header is expected to hold a single address, as `CF-Connecting-IP` does.
+pub fn resolve_client_ip(
+    headers: &HeaderMap,
+    peer_ip: &IpAddr,
+    trusted_proxies: &IpRanges,
+    sources: &[String]
+) -> IpAddr {
+    let peer_ip = canonical_ip(peer_ip);
+    if !trusted_proxies.contains(&peer_ip) {
+        return peer_ip;
+    }
+
+    for source in sources {
+        let source = source.to_ascii_lowercase();
+        let values: Vec<&str> = headers
+            .get_all(source.as_str())
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        if values.is_empty() {
+            continue;
+        }
+
+        let resolved = match source.as_str() {
+            "x-forwarded-for" => {
+                let chain = values
+                    .iter()
+                    .flat_map(|value| value.split(','))
+                    .map(parse_ip);
+                rightmost_untrusted(chain, trusted_proxies)
+            }
+            "forwarded" => {
+                let chain = values
+                    .iter()
+                    .flat_map(|value| value.split(','))
+                    .map(forwarded_for);
+                rightmost_untrusted(chain, trusted_proxies)
+            }
+            _ => values.last().and_then(|value| parse_ip(value)),
+        };
+
+        if let Some(ip) = resolved {
+            return ip;
+        }
+    }
+
+    peer_ip
+}
+
+/// Walks a forwarding chain from the closest hop outwards and returns the first address that is
+/// not a trusted proxy. Gives up at the first unparsable entry, since nothing before it can be
+/// trusted either.
+fn rightmost_untrusted<I>(chain: I, trusted_proxies: &IpRanges) -> Option<IpAddr>
+    where I: DoubleEndedIterator<Item = Option<IpAddr>>
+{
+    let mut last_seen = None;
+    for ip in chain.rev() {
+        let ip = ip?;
+        if !trusted_proxies.contains(&ip) {
+            return Some(ip);
+        }
+        last_seen = Some(ip);
+    }
+    last_seen
+}
+
+/// Extracts the `for=` node of a single `Forwarded` element.
+fn forwarded_for(element: &str) -> Option<IpAddr> {
+    element
+        .split(';')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case("for"))
+        .and_then(|(_, value)| parse_ip(value.trim().trim_matches('"')))
+}
+
+/// Parses an address that may carry a port or IPv6 brackets, e.g. `[2001:db8::1]:4711`.
+fn parse_ip(value: &str) -> Option<IpAddr> {
+    let value = value.trim();
+    value
+        .parse::<IpAddr>()
+        .ok()
+        .or_else(|| value.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        .or_else(|| value.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok())
+        .map(|ip| canonical_ip(&ip))
+}
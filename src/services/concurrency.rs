@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex };
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tokio::sync::{ OwnedSemaphorePermit, Semaphore };
+use tokio::time::timeout;
+
+use super::{ record_upstream_rejection, track_in_flight, track_queued, GaugeGuard };
+use crate::utils::ConcurrencyConfig;
+
+lazy_static! {
+    /// Slots of each forwarding rule with a concurrency limit, by the host of the rule.
+    static ref RULES: Mutex<HashMap<String, Arc<TargetSlots>>> = Mutex::new(HashMap::new());
+}
+
+struct TargetSlots {
+    max_in_flight: usize,
+    semaphore: Arc<Semaphore>,
+    queued: AtomicUsize,
+}
+
+/// Why a request couldn't get a slot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Saturation {
+    /// The target and its queue were full.
+    QueueFull,
+    /// The request waited in the queue for `queue_timeout_ms` without a slot coming free.
+    QueueTimeout,
+}
+
+impl Saturation {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Saturation::QueueFull => "queue_full",
+            Saturation::QueueTimeout => "queue_timeout",
+        }
+    }
+}
+
+/// A request's place among those in flight to a target, freed when dropped.
+pub struct UpstreamSlot {
+    _permit: OwnedSemaphorePermit,
+    _in_flight: GaugeGuard,
+}
+
+/// Waits for a slot of the forwarding rule for `host` to send a request to `target`, queuing
+/// behind other requests as `config` allows. Each rule has its own slots, also when rules share
+/// a target.
+pub async fn acquire_upstream_slot(
+    host: &str,
+    target: &str,
+    config: &ConcurrencyConfig
+) -> Result<UpstreamSlot, Saturation> {
+    let slots = rule_slots(host, config.max_in_flight);
+    let permit = match Arc::clone(&slots.semaphore).try_acquire_owned() {
+        Ok(permit) => permit,
+        Err(_) => {
+            // left on drop, also when the client goes away while waiting
+            let place = QueuePlace::join(&slots.queued);
+            let waited = if place.ahead < config.queue_size {
+                let _queued = track_queued(target);
+                let wait = Duration::from_millis(config.queue_timeout_ms);
+                match timeout(wait, Arc::clone(&slots.semaphore).acquire_owned()).await {
+                    Ok(Ok(permit)) => Ok(permit),
+                    _ => Err(Saturation::QueueTimeout),
+                }
+            } else {
+                Err(Saturation::QueueFull)
+            };
+            drop(place);
+
+            match waited {
+                Ok(permit) => permit,
+                Err(saturation) => {
+                    record_upstream_rejection(target, saturation.as_str());
+                    return Err(saturation);
+                }
+            }
+        }
+    };
+
+    Ok(UpstreamSlot { _permit: permit, _in_flight: track_in_flight(target) })
+}
+
+/// The slots of the rule for `host`, replaced when its limit changed on reload. Requests holding
+/// slots of the old limit finish as they are.
+fn rule_slots(host: &str, max_in_flight: usize) -> Arc<TargetSlots> {
+    let mut rules = RULES.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let slots = rules
+        .entry(host.to_string())
+        .and_modify(|slots| {
+            if slots.max_in_flight != max_in_flight {
+                *slots = Arc::new(TargetSlots::new(max_in_flight));
+            }
+        })
+        .or_insert_with(|| Arc::new(TargetSlots::new(max_in_flight)));
+    Arc::clone(slots)
+}
+
+impl TargetSlots {
+    fn new(max_in_flight: usize) -> Self {
+        TargetSlots {
+            max_in_flight,
+            semaphore: Arc::new(Semaphore::new(max_in_flight)),
+            queued: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// A request's place in a target's queue, counted until dropped.
+struct QueuePlace<'a> {
+    queued: &'a AtomicUsize,
+    /// Requests that were already queued.
+    ahead: usize,
+}
+
+impl<'a> QueuePlace<'a> {
+    fn join(queued: &'a AtomicUsize) -> Self {
+        QueuePlace { queued, ahead: queued.fetch_add(1, Ordering::SeqCst) }
+    }
+}
+
+impl Drop for QueuePlace<'_> {
+    fn drop(&mut self) {
+        self.queued.fetch_sub(1, Ordering::SeqCst);
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex, RwLock };
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use tokio::sync::Notify;
+use tokio::time::timeout;
+
+use super::{ record_connection_delayed, record_connection_refused, track_connection, GaugeGuard };
+use crate::utils::{
+    canonical_ip,
+    Configs,
+    ConnectionLimitsConfig,
+    ConnectionOverflow,
+    IpRanges,
+    RequestLimitsConfig,
+};
+
+/// How often a full listener looks again at its limit, in case it was raised on reload.
+const LIMIT_RECHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref SETTINGS: RwLock<LimitSettings> = RwLock::new(LimitSettings::default());
+    /// Connections open from each client IP, over every listener.
+    static ref PER_IP: Mutex<HashMap<IpAddr, usize>> = Mutex::new(HashMap::new());
+}
+
+#[derive(Default)]
+struct LimitSettings {
+    limits: ConnectionLimitsConfig,
+    trusted_proxies: IpRanges,
+    request_limits: RequestLimitsConfig,
+}
+
+/// Applies the `connection_limits` and `request_limits` of `configs`. Connections already open
+/// are kept, even when over the new limits, and keep the request limits they were accepted with.
+pub fn apply_connection_limits(configs: &Configs) {
+    let limits = configs.connection_limits.clone().unwrap_or_default();
+    let trusted_proxies = IpRanges::parse(configs.trusted_proxies.as_deref().unwrap_or_default());
+    let request_limits = configs.request_limits.clone().unwrap_or_default();
+    let mut settings = SETTINGS.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    *settings = LimitSettings { limits, trusted_proxies, request_limits };
+}
+
+/// The request limits new connections are served with.
+pub fn request_limits() -> RequestLimitsConfig {
+    SETTINGS.read().unwrap_or_else(|poisoned| poisoned.into_inner()).request_limits.clone()
+}
+
+fn connection_limits() -> ConnectionLimitsConfig {
+    SETTINGS.read().unwrap_or_else(|poisoned| poisoned.into_inner()).limits.clone()
+}
+
+/// Why a connection was closed as soon as it was accepted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionRefusal {
+    /// The listener had `max_connections` open.
+    ListenerFull,
+    /// The client IP had `max_connections_per_ip` open.
+    PerIpLimit,
+}
+
+impl ConnectionRefusal {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ConnectionRefusal::ListenerFull => "listener_full",
+            ConnectionRefusal::PerIpLimit => "per_ip_limit",
+        }
+    }
+}
+
+/// The connections open on one listener.
+pub struct ListenerConnections {
+    name: &'static str,
+    open: AtomicUsize,
+    closed: Notify,
+}
+
+impl ListenerConnections {
+    pub fn new(name: &'static str) -> Arc<Self> {
+        Arc::new(ListenerConnections { name, open: AtomicUsize::new(0), closed: Notify::new() })
+    }
+
+    /// Waits, before accepting, while the listener is full and set to delay new connections.
+    pub async fn wait_for_room(&self) {
+        let mut delayed = false;
+        loop {
+            let closed = self.closed.notified();
+            let limits = connection_limits();
+            let full = limits.max_connections.is_some_and(|max| self.open.load(Ordering::SeqCst) >= max);
+            if !full || limits.when_full != ConnectionOverflow::Delay {
+                return;
+            }
+
+            if !delayed {
+                delayed = true;
+                record_connection_delayed(self.name);
+                log::warn!("Listener {} is at its connection limit, waiting for a connection to close", self.name);
+            }
+            let _ = timeout(LIMIT_RECHECK_INTERVAL, closed).await;
+        }
+    }
+
+    /// Counts a connection just accepted from `peer_ip`, unless it goes over a limit. The
+    /// connection is counted until the returned permit is dropped.
+    pub fn admit(self: &Arc<Self>, peer_ip: &IpAddr) -> Result<ConnectionPermit, ConnectionRefusal> {
+        let peer_ip = canonical_ip(peer_ip);
+        let (limits, trusted) = {
+            let settings = SETTINGS.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+            (settings.limits.clone(), settings.trusted_proxies.contains(&peer_ip))
+        };
+
+        let refusal = if limits.max_connections.is_some_and(|max| self.open.load(Ordering::SeqCst) >= max) {
+            Some(ConnectionRefusal::ListenerFull)
+        } else if !trusted && !claim_ip_slot(&peer_ip, limits.max_connections_per_ip) {
+            Some(ConnectionRefusal::PerIpLimit)
+        } else {
+            None
+        };
+        if let Some(refusal) = refusal {
+            record_connection_refused(self.name, refusal.as_str());
+            return Err(refusal);
+        }
+
+        self.open.fetch_add(1, Ordering::SeqCst);
+        Ok(ConnectionPermit {
+            listener: Arc::clone(self),
+            peer_ip: (!trusted).then_some(peer_ip),
+            _active: track_connection(self.name),
+        })
+    }
+}
+
+/// Counts a connection from `peer_ip`, unless it already has `max` open.
+fn claim_ip_slot(peer_ip: &IpAddr, max: Option<usize>) -> bool {
+    let mut per_ip = PER_IP.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let open = per_ip.entry(*peer_ip).or_default();
+    if max.is_some_and(|max| *open >= max) {
+        return false;
+    }
+    *open += 1;
+    true
+}
+
+/// The connections open from `peer_ip` over every listener, or `None` once it has none left.
+/// Trusted proxies aren't counted.
+pub fn connections_from_ip(peer_ip: &IpAddr) -> Option<usize> {
+    PER_IP.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).get(&canonical_ip(peer_ip)).copied()
+}
+
+fn release_ip_slot(peer_ip: &IpAddr) {
+    let mut per_ip = PER_IP.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(open) = per_ip.get_mut(peer_ip) {
+        *open = open.saturating_sub(1);
+        if *open == 0 {
+            per_ip.remove(peer_ip);
+        }
+    }
+}
+
+/// An open client connection, counted against the limits until dropped.
+pub struct ConnectionPermit {
+    listener: Arc<ListenerConnections>,
+    /// The client IP the connection counts against; trusted proxies aren't counted.
+    peer_ip: Option<IpAddr>,
+    _active: GaugeGuard,
+}
+
+impl Drop for ConnectionPermit {
+    fn drop(&mut self) {
+        if let Some(peer_ip) = &self.peer_ip {
+            release_ip_slot(peer_ip);
+        }
+        self.listener.open.fetch_sub(1, Ordering::SeqCst);
+        self.listener.closed.notify_waiters();
+    }
+}
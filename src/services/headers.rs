@@ -0,0 +1,192 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use hyper::header::{ HeaderMap, HeaderName, HeaderValue, CONNECTION, FORWARDED };
+
+use crate::{ server::ConnectionInfo, utils::{ canonical_ip, current_request_id, render_placeholders, HeaderRules } };
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_REAL_IP: &str = "x-real-ip";
+
+/// Hop-by-hop headers that only apply to a single connection (RFC 9110, section 7.6.1)
+/// and must not be forwarded by a proxy.
+const HOP_BY_HOP_HEADERS: [&str; 9] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Variables available to header value templates, keyed by name without braces.
+pub type HeaderVars = HashMap<&'static str, String>;
+
+/// Builds the template variables for a request from `client_ip` arriving on `conn` for `host`.
+pub fn header_vars(conn: &ConnectionInfo, client_ip: &IpAddr, host: &str) -> HeaderVars {
+    let mut vars = HeaderVars::new();
+    vars.insert("client_ip", client_ip.to_string());
+    vars.insert("host", host.to_string());
+    vars.insert("scheme", (if conn.is_tls { "https" } else { "http" }).to_string());
+    vars.insert("tls_version", conn.tls_version.clone().unwrap_or_default());
+    vars.insert("tls_cipher", conn.tls_cipher.clone().unwrap_or_default());
+    vars.insert("tls_sni", conn.tls_sni.clone().unwrap_or_default());
+    vars.insert("request_id", current_request_id().unwrap_or_default());
+    vars
+}
+
+/// Replaces every `{{name}}` in `template` with the matching variable, leaving unknown names as
+/// they are.
+pub fn render_template(template: &str, vars: &HeaderVars) -> String {
+    render_placeholders(template, |name| vars.get(name).cloned())
+}
+
+/// Removes hop-by-hop headers, including any header named in the `Connection` header.
+pub fn strip_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let listed: Vec<String> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect();
+
+    for name in listed.iter().map(String::as_str).chain(HOP_BY_HOP_HEADERS) {
+        headers.remove(name);
+    }
+}
+
+/// Sets `X-Forwarded-For`, `X-Forwarded-Proto`, `X-Forwarded-Host`, `X-Real-IP` and `Forwarded`
+/// for a request arriving on `conn`.
+///
+/// When the peer is a trusted proxy the values it sent are kept and this hop is appended to the
+/// forwarding chains; otherwise any client-supplied values are discarded. `X-Real-IP` always
+/// carries the resolved `client_ip`.
+pub fn apply_forwarding_headers(
+    headers: &mut HeaderMap,
+    conn: &ConnectionInfo,
+    client_ip: &IpAddr,
+    host: &str,
+    peer_is_trusted: bool
+) {
+    let peer_ip = canonical_ip(&conn.peer_ip);
+    let proto = if conn.is_tls { "https" } else { "http" };
+
+    if !peer_is_trusted {
+        for name in [X_FORWARDED_FOR, X_FORWARDED_PROTO, X_FORWARDED_HOST] {
+            headers.remove(name);
+        }
+        headers.remove(FORWARDED);
+    }
+
+    let forwarded_for = match joined_header(headers, X_FORWARDED_FOR) {
+        Some(existing) => format!("{}, {}", existing, peer_ip),
+        None => peer_ip.to_string(),
+    };
+    set_header(headers, X_FORWARDED_FOR, &forwarded_for);
+
+    if !headers.contains_key(X_FORWARDED_PROTO) {
+        set_header(headers, X_FORWARDED_PROTO, proto);
+    }
+    if !headers.contains_key(X_FORWARDED_HOST) {
+        set_header(headers, X_FORWARDED_HOST, host);
+    }
+    set_header(headers, X_REAL_IP, &client_ip.to_string());
+
+    let element = format!(
+        "for={};proto={};host={}",
+        forwarded_node(&peer_ip),
+        proto,
+        quote_if_needed(host)
+    );
+    let forwarded = match joined_header(headers, FORWARDED.as_str()) {
+        Some(existing) => format!("{}, {}", existing, element),
+        None => element,
+    };
+    set_header(headers, FORWARDED.as_str(), &forwarded);
+}
+
+/// Joins all values of a repeated header into a single comma-separated list.
+fn joined_header(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(", "))
+    }
+}
+
+fn set_header(headers: &mut HeaderMap, name: &'static str, value: &str) {
+    match HeaderValue::from_str(value) {
+        Ok(value) => {
+            headers.insert(name, value);
+        }
+        Err(_) => log::warn!("Not setting {} to invalid header value {:?}", name, value),
+    }
+}
+
+/// Formats an address as an RFC 7239 node, quoting and bracketing IPv6 addresses.
+fn forwarded_node(ip: &IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => v4.to_string(),
+        IpAddr::V6(v6) => format!("\"[{}]\"", v6),
+    }
+}
+
+fn quote_if_needed(value: &str) -> String {
+    let is_token = value
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c));
+    if is_token {
+        value.to_string()
+    } else {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    }
+}
+
+/// Applies the `remove`, `set` and `add` operations of `rules` to `headers`, in that order.
+pub fn apply_header_rules(headers: &mut HeaderMap, rules: &HeaderRules, vars: &HeaderVars) {
+    for name in &rules.remove {
+        headers.remove(name.as_str());
+    }
+
+    for (name, value) in &rules.set {
+        if let Some((name, value)) = parse_header(name, value, vars) {
+            headers.insert(name, value);
+        }
+    }
+
+    for (name, value) in &rules.add {
+        if let Some((name, value)) = parse_header(name, value, vars) {
+            headers.append(name, value);
+        }
+    }
+}
+
+fn parse_header(name: &str, template: &str, vars: &HeaderVars) -> Option<(HeaderName, HeaderValue)> {
+    let header_name = match HeaderName::from_bytes(name.as_bytes()) {
+        Ok(header_name) => header_name,
+        Err(_) => {
+            log::warn!("Skipping invalid header name in header rules: {:?}", name);
+            return None;
+        }
+    };
+
+    match HeaderValue::from_str(&render_template(template, vars)) {
+        Ok(value) => Some((header_name, value)),
+        Err(_) => {
+            log::warn!("Skipping invalid value for header {:?} in header rules", name);
+            None
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use std::net::IpAddr;
+
+use crate::utils::{ parse_ip_range, IpAccessRule, IpPrefixTree };
+
+/// A host's `allow` and `deny` lists, ready for lookups.
+pub struct IpAccessList {
+    /// Whether each listed network is allowed.
+    networks: IpPrefixTree<bool>,
+    allow_by_default: bool,
+}
+
+impl IpAccessList {
+    /// Builds the list of `rule`. A network in both lists is denied; invalid entries are
+    /// skipped, as the config validator reports them.
+    pub fn new(rule: &IpAccessRule) -> IpAccessList {
+        let mut networks = IpPrefixTree::default();
+        let entries = rule.allow
+            .iter()
+            .map(|entry| (entry, true))
+            .chain(rule.deny.iter().map(|entry| (entry, false)));
+        for (entry, allowed) in entries {
+            if let Some(network) = parse_ip_range(entry) {
+                networks.insert(&network, allowed);
+            }
+        }
+
+        IpAccessList { networks, allow_by_default: rule.allow.is_empty() }
+    }
+
+    pub fn allows(&self, ip: &IpAddr) -> bool {
+        self.networks.longest_match(ip).copied().unwrap_or(self.allow_by_default)
+    }
+}
+
+/// The access rule for `host`, falling back to the `*` rule.
+pub fn ip_access_rule<'a>(rules: &'a [IpAccessRule], host: &str) -> Option<&'a IpAccessRule> {
+    rules
+        .iter()
+        .find(|rule| rule.host == host)
+        .or_else(|| rules.iter().find(|rule| rule.host == "*"))
+}
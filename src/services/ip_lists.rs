@@ -0,0 +1,65 @@
+use std::collections::HashMap;
+use std::sync::{ Arc, RwLock };
+
+use lazy_static::lazy_static;
+
+use super::IpAccessList;
+use crate::utils::{ Configs, IpRanges };
+
+lazy_static! {
+    /// The lists of the configuration in use, along with that configuration.
+    static ref CURRENT: RwLock<Option<(Arc<Configs>, Arc<ConfigIpLists>)>> = RwLock::new(None);
+}
+
+/// The IP lists of a configuration, parsed into prefix trees once per configuration instead of
+/// on every request.
+pub struct ConfigIpLists {
+    pub trusted_proxies: IpRanges,
+    /// The access list of each host with an IP access rule.
+    access_lists: HashMap<String, IpAccessList>,
+    /// The `excluded_ip_list` of each rate-limit rule, in the order of the rules.
+    rate_limit_exclusions: Vec<IpRanges>,
+}
+
+impl ConfigIpLists {
+    pub fn new(configs: &Configs) -> ConfigIpLists {
+        let mut access_lists = HashMap::new();
+        for rule in configs.ip_access_rules.iter().flatten() {
+            // the first rule for a host wins, like `ip_access_rule`; the validator rejects duplicates
+            access_lists.entry(rule.host.clone()).or_insert_with(|| IpAccessList::new(rule));
+        }
+
+        ConfigIpLists {
+            trusted_proxies: IpRanges::parse(configs.trusted_proxies.as_deref().unwrap_or_default()),
+            access_lists,
+            rate_limit_exclusions: configs.rate_limit_rules
+                .iter()
+                .flatten()
+                .map(|rule| IpRanges::parse(&rule.excluded_ip_list))
+                .collect(),
+        }
+    }
+
+    /// The access list for `host`, falling back to the `*` rule.
+    pub fn access_list(&self, host: &str) -> Option<&IpAccessList> {
+        self.access_lists.get(host).or_else(|| self.access_lists.get("*"))
+    }
+
+    /// The clients excluded from the rate-limit rule at `index`.
+    pub fn rate_limit_exclusions(&self, index: usize) -> Option<&IpRanges> {
+        self.rate_limit_exclusions.get(index)
+    }
+}
+
+/// The IP lists of `configs`, built on first use after each configuration load.
+pub fn config_ip_lists(configs: &Arc<Configs>) -> Arc<ConfigIpLists> {
+    if let Some((current, lists)) = CURRENT.read().unwrap_or_else(|poisoned| poisoned.into_inner()).as_ref() {
+        if Arc::ptr_eq(current, configs) {
+            return lists.clone();
+        }
+    }
+
+    let lists = Arc::new(ConfigIpLists::new(configs));
+    *CURRENT.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some((configs.clone(), lists.clone()));
+    lists
+}
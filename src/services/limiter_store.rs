@@ -0,0 +1,198 @@
+use std::hash::{ BuildHasher, RandomState };
+use std::sync::atomic::{ AtomicU64, AtomicUsize, Ordering };
+use std::sync::{ Arc, Mutex, MutexGuard };
+use std::time::Duration;
+
+use lru::LruCache;
+
+use super::{
+    new_limiter,
+    record_rate_limiter_evictions,
+    set_rate_limiter_entries,
+    unix_now,
+    LimitOutcome,
+    LimitParams,
+    Limiter,
+    LimiterKey,
+};
+use crate::utils::{ current_configs, RateLimitAlgorithm };
+
+/// Number of independently locked parts of a store, so checks for different clients rarely
+/// wait for each other.
+const SHARDS: usize = 64;
+
+/// How often idle limiters are looked for, and the store's limits re-read from the configuration.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// In-memory limiters of the `local` strategy, bounded in size.
+///
+/// Limiters are spread over shards by client key, so all of a client's limiters share a shard
+/// and the admin API can find them with a single lookup. Each shard keeps its limiters in
+/// least-recently-used order: when the store is full the clients seen least recently are
+/// forgotten, and [`LimiterStore::remove_idle`] drops limiters nobody has used for a while.
+/// `max_entries` is split evenly between the shards, so it is rounded up to a multiple of their
+/// number.
+pub struct LimiterStore {
+    shards: Vec<Mutex<LruCache<LimiterKey, StoredLimiter>>>,
+    hasher: RandomState,
+    max_entries: AtomicUsize,
+    idle_timeout_ms: AtomicU64,
+}
+
+struct StoredLimiter {
+    limiter: Box<dyn Limiter>,
+    last_used: Duration,
+}
+
+/// A limiter's state, as the admin API shows it.
+#[derive(Debug, Clone)]
+pub struct LimiterSnapshot {
+    pub key: LimiterKey,
+    pub algorithm: RateLimitAlgorithm,
+    pub params: LimitParams,
+    pub remaining: u64,
+    pub idle: Duration,
+}
+
+impl LimiterStore {
+    pub fn new(max_entries: usize, idle_timeout: Duration) -> Self {
+        let store = LimiterStore {
+            shards: (0..SHARDS).map(|_| Mutex::new(LruCache::unbounded())).collect(),
+            hasher: RandomState::new(),
+            max_entries: AtomicUsize::new(0),
+            idle_timeout_ms: AtomicU64::new(0),
+        };
+        store.set_limits(max_entries, idle_timeout);
+        store
+    }
+
+    /// Changes the limits; a smaller `max_entries` takes effect as new clients arrive.
+    pub fn set_limits(&self, max_entries: usize, idle_timeout: Duration) {
+        self.max_entries.store(max_entries.max(1), Ordering::Relaxed);
+        self.idle_timeout_ms.store(idle_timeout.as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Checks a request made at `now` against the limiter for `key`. The limiter is created on
+    /// first use, and rebuilt when the rule's algorithm or limits have changed.
+    pub fn check(
+        &self,
+        key: &LimiterKey,
+        algorithm: RateLimitAlgorithm,
+        params: LimitParams,
+        now: Duration
+    ) -> LimitOutcome {
+        // limiters keep their limits clamped, so compare them the same way
+        let params = params.clamped();
+        let mut shard = self.shard(&key.key);
+        if let Some(stored) = shard.get_mut(key) {
+            // the rule changed on reload, so its old state no longer means anything
+            if stored.limiter.algorithm() != algorithm || stored.limiter.params() != params {
+                stored.limiter = new_limiter(algorithm, params);
+            }
+            stored.last_used = now;
+            return stored.limiter.check(now);
+        }
+
+        let capacity = self.max_entries.load(Ordering::Relaxed).div_ceil(SHARDS);
+        while shard.len() >= capacity && shard.pop_lru().is_some() {
+            record_rate_limiter_evictions("capacity", 1);
+        }
+
+        let mut limiter = new_limiter(algorithm, params);
+        let outcome = limiter.check(now);
+        shard.put(key.clone(), StoredLimiter { limiter, last_used: now });
+        outcome
+    }
+
+    /// Drops limiters that haven't been used for the idle timeout and have recovered
+    /// completely, so a client coming back gets the same answers. Returns how many were dropped.
+    pub fn remove_idle(&self, now: Duration) -> usize {
+        let idle_timeout = Duration::from_millis(self.idle_timeout_ms.load(Ordering::Relaxed));
+        let mut removed = 0;
+        for shard in &self.shards {
+            let mut shard = shard.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            // least recently used first, so the idle limiters come before every other one
+            let idle: Vec<LimiterKey> = shard
+                .iter()
+                .rev()
+                .take_while(|(_, stored)| now.saturating_sub(stored.last_used) >= idle_timeout)
+                .filter(|(_, stored)| stored.limiter.is_reset(now))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in &idle {
+                shard.pop(key);
+            }
+            removed += idle.len();
+        }
+        record_rate_limiter_evictions("idle", removed as u64);
+        removed
+    }
+
+    /// The limiters of a client key, under every rule or only under `rule`.
+    pub fn snapshot(&self, key: &str, rule: Option<&str>, now: Duration) -> Vec<LimiterSnapshot> {
+        self.shard(key)
+            .iter()
+            .filter(|(id, _)| id.key == key && rule.is_none_or(|rule| id.rule == rule))
+            .map(|(id, stored)| LimiterSnapshot {
+                key: id.clone(),
+                algorithm: stored.limiter.algorithm(),
+                params: stored.limiter.params(),
+                remaining: stored.limiter.remaining(now),
+                idle: now.saturating_sub(stored.last_used),
+            })
+            .collect()
+    }
+
+    /// Forgets the limiters of a client key, under every rule or only under `rule`. Returns
+    /// how many were removed.
+    pub fn remove(&self, key: &str, rule: Option<&str>) -> usize {
+        let mut shard = self.shard(key);
+        let matching: Vec<LimiterKey> = shard
+            .iter()
+            .filter(|(id, _)| id.key == key && rule.is_none_or(|rule| id.rule == rule))
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in &matching {
+            shard.pop(id);
+        }
+        matching.len()
+    }
+
+    /// Limiters currently kept.
+    pub fn len(&self) -> usize {
+        self.shards
+            .iter()
+            .map(|shard| shard.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).len())
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn shard(&self, key: &str) -> MutexGuard<'_, LruCache<LimiterKey, StoredLimiter>> {
+        let index = (self.hasher.hash_one(key) as usize) % SHARDS;
+        self.shards[index].lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// Periodically drops idle limiters from `store`, applying changes to the `rate_limiter`
+/// settings as it goes.
+pub fn spawn_limiter_sweeper(store: Arc<LimiterStore>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SWEEP_INTERVAL);
+        loop {
+            interval.tick().await;
+            if let Ok(configs) = current_configs() {
+                let settings = configs.rate_limiter.clone().unwrap_or_default();
+                store.set_limits(settings.max_entries, Duration::from_secs(settings.idle_timeout));
+            }
+
+            let removed = store.remove_idle(unix_now());
+            if removed > 0 {
+                log::debug!("Dropped {} idle rate limiters", removed);
+            }
+            set_rate_limiter_entries(store.len());
+        }
+    });
+}
@@ -0,0 +1,232 @@
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec_with_registry,
+    register_int_counter_vec_with_registry,
+    register_int_gauge_vec_with_registry,
+    register_int_gauge_with_registry,
+    HistogramVec,
+    IntCounterVec,
+    IntGauge,
+    IntGaugeVec,
+    Registry,
+    TextEncoder,
+};
+
+use super::RateLimitDecision;
+
+lazy_static! {
+    static ref REGISTRY: Registry = Registry::new_custom(Some("sheldx".to_string()), None).unwrap();
+
+    static ref REQUESTS_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "requests_total",
+        "Requests handled, by forwarding rule host, upstream route and response status",
+        &["host", "route", "status"],
+        REGISTRY
+    ).unwrap();
+
+    static ref REQUEST_DURATION_SECONDS: HistogramVec = register_histogram_vec_with_registry!(
+        "request_duration_seconds",
+        "Time from receiving a request to having its response ready",
+        &["host", "route", "status"],
+        REGISTRY
+    ).unwrap();
+
+    static ref UPSTREAM_CONNECT_ERRORS_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "upstream_connect_errors_total",
+        "Failed attempts to connect to an upstream, by reason",
+        &["upstream", "reason"],
+        REGISTRY
+    ).unwrap();
+
+    static ref UPSTREAM_RETRIES_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "upstream_retries_total",
+        "Connection attempts retried after a failure",
+        &["upstream"],
+        REGISTRY
+    ).unwrap();
+
+    static ref RATE_LIMIT_DECISIONS_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "rate_limit_decisions_total",
+        "Rate-limit checks, by outcome",
+        &["decision"],
+        REGISTRY
+    ).unwrap();
+
+    static ref RATE_LIMITER_ENTRIES: IntGauge = register_int_gauge_with_registry!(
+        "rate_limiter_entries",
+        "Limiters kept in memory for local rate-limit rules",
+        REGISTRY
+    ).unwrap();
+
+    static ref RATE_LIMITER_EVICTIONS_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "rate_limiter_evictions_total",
+        "Local limiters dropped, because they were idle or to make room for new clients",
+        &["reason"],
+        REGISTRY
+    ).unwrap();
+
+    static ref ACTIVE_CONNECTIONS: IntGaugeVec = register_int_gauge_vec_with_registry!(
+        "active_connections",
+        "Client connections currently open, by listener",
+        &["listener"],
+        REGISTRY
+    ).unwrap();
+
+    static ref UPSTREAM_IN_FLIGHT_REQUESTS: IntGaugeVec = register_int_gauge_vec_with_registry!(
+        "upstream_in_flight_requests",
+        "Requests in flight to upstreams with a concurrency limit",
+        &["upstream"],
+        REGISTRY
+    ).unwrap();
+
+    static ref UPSTREAM_QUEUED_REQUESTS: IntGaugeVec = register_int_gauge_vec_with_registry!(
+        "upstream_queued_requests",
+        "Requests waiting for a free slot of an upstream",
+        &["upstream"],
+        REGISTRY
+    ).unwrap();
+
+    static ref UPSTREAM_REJECTIONS_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "upstream_rejections_total",
+        "Requests turned away because an upstream was at its concurrency limit, by reason",
+        &["upstream", "reason"],
+        REGISTRY
+    ).unwrap();
+
+    static ref CONNECTIONS_REFUSED_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "connections_refused_total",
+        "Client connections closed on accept for going over a connection limit, by listener and reason",
+        &["listener", "reason"],
+        REGISTRY
+    ).unwrap();
+
+    static ref CONNECTIONS_DELAYED_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "connections_delayed_total",
+        "Times a listener stopped accepting connections because it was at its limit",
+        &["listener"],
+        REGISTRY
+    ).unwrap();
+
+    static ref TLS_HANDSHAKE_FAILURES_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "tls_handshake_failures_total",
+        "TLS handshakes that failed, by listener",
+        &["listener"],
+        REGISTRY
+    ).unwrap();
+
+    static ref TRACE_SPANS_DROPPED_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "trace_spans_dropped_total",
+        "Finished spans that never reached the trace collector, by reason",
+        &["reason"],
+        REGISTRY
+    ).unwrap();
+
+    static ref CONFIG_RELOADS_TOTAL: IntCounterVec = register_int_counter_vec_with_registry!(
+        "config_reloads_total",
+        "Configuration reloads, by result",
+        &["result"],
+        REGISTRY
+    ).unwrap();
+}
+
+/// Records a finished request. `host` and `route` should come from configuration, not from the
+/// request, so clients can't blow up the number of series.
+pub fn record_request(host: &str, route: &str, status: &str, duration: Duration) {
+    REQUESTS_TOTAL.with_label_values(&[host, route, status]).inc();
+    REQUEST_DURATION_SECONDS.with_label_values(&[host, route, status]).observe(duration.as_secs_f64());
+}
+
+pub fn record_upstream_connect_error(upstream: &str, reason: &str) {
+    UPSTREAM_CONNECT_ERRORS_TOTAL.with_label_values(&[upstream, reason]).inc();
+}
+
+pub fn record_upstream_retry(upstream: &str) {
+    UPSTREAM_RETRIES_TOTAL.with_label_values(&[upstream]).inc();
+}
+
+pub fn record_rate_limit_decision(decision: RateLimitDecision) {
+    RATE_LIMIT_DECISIONS_TOTAL.with_label_values(&[decision.as_str()]).inc();
+}
+
+pub fn record_rate_limiter_evictions(reason: &str, count: u64) {
+    RATE_LIMITER_EVICTIONS_TOTAL.with_label_values(&[reason]).inc_by(count);
+}
+
+pub fn set_rate_limiter_entries(count: usize) {
+    RATE_LIMITER_ENTRIES.set(count as i64);
+}
+
+pub fn record_upstream_rejection(upstream: &str, reason: &str) {
+    UPSTREAM_REJECTIONS_TOTAL.with_label_values(&[upstream, reason]).inc();
+}
+
+/// Counts a request as in flight to `upstream` until the returned guard is dropped.
+pub fn track_in_flight(upstream: &str) -> GaugeGuard {
+    GaugeGuard::new(UPSTREAM_IN_FLIGHT_REQUESTS.with_label_values(&[upstream]))
+}
+
+/// Counts a request as waiting for `upstream` until the returned guard is dropped.
+pub fn track_queued(upstream: &str) -> GaugeGuard {
+    GaugeGuard::new(UPSTREAM_QUEUED_REQUESTS.with_label_values(&[upstream]))
+}
+
+pub fn record_tls_handshake_failure(listener: &str) {
+    TLS_HANDSHAKE_FAILURES_TOTAL.with_label_values(&[listener]).inc();
+}
+
+pub fn record_spans_dropped(reason: &str, count: usize) {
+    TRACE_SPANS_DROPPED_TOTAL.with_label_values(&[reason]).inc_by(count as u64);
+}
+
+pub fn record_config_reload(success: bool) {
+    let result = if success { "success" } else { "failure" };
+    CONFIG_RELOADS_TOTAL.with_label_values(&[result]).inc();
+}
+
+/// Counts a connection as active on `listener` until the returned guard is dropped.
+pub fn track_connection(listener: &str) -> GaugeGuard {
+    GaugeGuard::new(ACTIVE_CONNECTIONS.with_label_values(&[listener]))
+}
+
+pub fn record_connection_refused(listener: &str, reason: &str) {
+    CONNECTIONS_REFUSED_TOTAL.with_label_values(&[listener, reason]).inc();
+}
+
+pub fn record_connection_delayed(listener: &str) {
+    CONNECTIONS_DELAYED_TOTAL.with_label_values(&[listener]).inc();
+}
+
+/// Connections currently open on `listener`.
+pub fn active_connections(listener: &str) -> i64 {
+    ACTIVE_CONNECTIONS.with_label_values(&[listener]).get()
+}
+
+/// Keeps a gauge incremented while it is alive.
+pub struct GaugeGuard {
+    gauge: prometheus::IntGauge,
+}
+
+impl GaugeGuard {
+    fn new(gauge: prometheus::IntGauge) -> Self {
+        gauge.inc();
+        GaugeGuard { gauge }
+    }
+}
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.gauge.dec();
+    }
+}
+
+/// Renders every metric in the Prometheus text exposition format.
+pub fn gather_metrics() -> String {
+    TextEncoder::new()
+        .encode_to_string(&REGISTRY.gather())
+        .unwrap_or_else(|e| {
+            log::error!("Failed to encode metrics: {}", e);
+            String::new()
+        })
+}
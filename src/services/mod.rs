@@ -1,4 +1,30 @@
+mod client_ip;
+mod concurrency;
+mod connection_limits;
+mod headers;
+mod ip_access;
+mod ip_lists;
+mod limiter_store;
+mod metrics;
 mod rate_limit;
+mod rate_limit_keys;
+mod rate_limiters;
+mod redis_rate_limit;
+mod tracing;
+mod upstreams;
 
 
-pub use rate_limit::*;
\ No newline at end of file
+pub use client_ip::*;
+pub use concurrency::*;
+pub use connection_limits::*;
+pub use headers::*;
+pub use ip_access::*;
+pub use ip_lists::*;
+pub use limiter_store::*;
+pub use metrics::*;
+pub use rate_limit::*;
+pub use rate_limit_keys::*;
+pub use rate_limiters::*;
+pub use redis_rate_limit::*;
+pub use tracing::*;
+pub use upstreams::*;
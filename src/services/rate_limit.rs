@@ -9,23 +9,137 @@ exclude = ["/health"]  # Paths to exclude from rate limiting
 strategy = "hashmap"  # Choose between "hashmap" or "redis" for the rate-limiting strategy
 */
 
+use std::cmp::Reverse;
+use std::net::IpAddr;
 use std::time::Duration;
-use hyper::Request;
-use ratelimit::Ratelimiter;
+use async_trait::async_trait;
+use hyper::header::{ HeaderValue, RETRY_AFTER };
+use hyper::{ HeaderMap, Request };
 use crate::{
-    handlers::ProxyError, server::RateLimiterMap, utils::{extract_host, Configs}
+    handlers::ProxyError, server::RateLimiterMap, utils::{extract_host, rate_limit_rule_ids, Configs, RateLimitRule, RateLimitStrategy, RedisFailureMode}
 };
+use super::{ rate_limit_key, unix_now, ConfigIpLists, LimitOutcome, LimitParams, LimiterKey, RedisBackend };
+
+/// Where the state of a rule's limiters is kept. Every backend supports every algorithm, with
+/// the same semantics.
+#[async_trait]
+pub trait RateLimitBackend: Send + Sync {
+    /// Checks a request by `key` against `rule`, counting it if it is allowed.
+    async fn check(&self, rule: &RateLimitRule, key: &LimiterKey) -> Result<LimitOutcome, String>;
+}
+
+/// Keeps limiters in memory, so each SheldX instance counts requests on its own.
+pub struct LocalBackend<'a> {
+    pub rate_limiter_map: &'a RateLimiterMap,
+}
+
+#[async_trait]
+impl RateLimitBackend for LocalBackend<'_> {
+    async fn check(&self, rule: &RateLimitRule, key: &LimiterKey) -> Result<LimitOutcome, String> {
+        Ok(self.rate_limiter_map.check(key, rule.algorithm, LimitParams::from(rule), unix_now()))
+    }
+}
+
+/// Outcome of the rate-limit check for a single request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitDecision {
+    /// A rule matched and the request was within its limit.
+    Allowed,
+    /// A rule matched and the request went over its limit.
+    Limited,
+    /// A rule matched but the path or client IP is excluded from it.
+    Excluded,
+    /// No rule applies to the request.
+    NoRule,
+    /// The rule is kept in Redis, which couldn't be reached. The request was let through or
+    /// rejected depending on `redis.failure_mode`.
+    Unavailable,
+}
+
+impl RateLimitDecision {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitDecision::Allowed => "allowed",
+            RateLimitDecision::Limited => "limited",
+            RateLimitDecision::Excluded => "excluded",
+            RateLimitDecision::NoRule => "none",
+            RateLimitDecision::Unavailable => "unavailable",
+        }
+    }
+}
 
 pub struct RateLimitResponse {
     pub response: String,
     pub status_code: u16,
     pub seconds: u64,
+    pub decision: RateLimitDecision,
+    /// The quota of the rule closest to limiting the client, when a rule counted the request.
+    pub quota: Option<RateLimitQuota>,
+}
+
+/// What a client has left under a rule, as told in the `RateLimit-*` response headers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimitQuota {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Time until the full quota is available again.
+    pub reset: Duration,
+    /// The rule's limits, as `RateLimit-Policy` describes them.
+    pub policy: String,
+}
+
+impl RateLimitQuota {
+    /// The quota of `rule` for a client its limiter gave `outcome`.
+    pub fn new(rule: &RateLimitRule, outcome: &LimitOutcome) -> Self {
+        let params = LimitParams::from(rule);
+        let mut policy = format!("{};w={}", params.limit, ceil_secs(params.window));
+        if params.quota(rule.algorithm) != params.limit {
+            policy.push_str(&format!(";burst={}", params.burst));
+        }
+        RateLimitQuota {
+            limit: params.quota(rule.algorithm),
+            remaining: outcome.remaining,
+            reset: outcome.reset_after,
+            policy,
+        }
+    }
+}
+
+impl RateLimitResponse {
+    /// Sets the `RateLimit-Limit`, `RateLimit-Remaining`, `RateLimit-Reset` and
+    /// `RateLimit-Policy` headers of the IETF rate-limit headers draft, and `Retry-After` when
+    /// the request was limited.
+    pub fn set_headers(&self, headers: &mut HeaderMap) {
+        if let Some(quota) = &self.quota {
+            headers.insert("ratelimit-limit", HeaderValue::from(quota.limit));
+            headers.insert("ratelimit-remaining", HeaderValue::from(quota.remaining));
+            headers.insert("ratelimit-reset", HeaderValue::from(ceil_secs(quota.reset)));
+            if let Ok(policy) = HeaderValue::from_str(&quota.policy) {
+                headers.insert("ratelimit-policy", policy);
+            }
+        }
+        if self.decision == RateLimitDecision::Limited {
+            headers.insert(RETRY_AFTER, HeaderValue::from(self.seconds));
+        }
+    }
+}
+
+/// Rounds up, so clients waiting that long don't come back too early.
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_millis().div_ceil(1000) as u64
+}
+
+/// Orders outcomes by how close they are to limiting the client: limited ones first, by how
+/// long they last, then allowed ones with the fewest requests left.
+fn restrictiveness(outcome: &LimitOutcome) -> (bool, Duration, Reverse<u64>, Duration) {
+    (!outcome.allowed, outcome.retry_after, Reverse(outcome.remaining), outcome.reset_after)
 }
-pub async fn enforce_rate_limit(
-    req: &Request<hyper::body::Incoming>,
+pub async fn enforce_rate_limit<B>(
+    req: &Request<B>,
     client_ip: &str,
     rate_limiter_map: &RateLimiterMap,
-    config: &Configs
+    config: &Configs,
+    ip_lists: &ConfigIpLists
 ) -> Result<RateLimitResponse, ProxyError> {
     log::debug!("Client IP: {:?}", client_ip);
 
@@ -34,78 +148,119 @@ pub async fn enforce_rate_limit(
         Err(_) => return Err(ProxyError::HostError),
     };
     let path = req.uri().path();
+    let client_addr = client_ip.parse::<IpAddr>().ok();
 
-    // Check if there are rate limit rules
-    if let Some(rate_limit_rules) = &config.rate_limit_rules {
-        // Try to find a rule that matches the host
-        let rule = rate_limit_rules.iter()
-            .find(|rule| rule.host == host)
-            // If no specific rule matches, fall back to the wildcard rule
-            .or_else(|| rate_limit_rules.iter().find(|rule| rule.host == "*"));
-
-        log::debug!("Rate limit rule: {:?}", rule);
-
-        if let Some(rule) = rule {
-            // Check if the request path is in the exclude list
-            if rule.excluded_paths.iter().any(|p| path.starts_with(p)) {
-                return Ok(RateLimitResponse {
-                    response: String::new(),
-                    status_code: 200,
-                    seconds: 0,
-                });
-            }
+    // Rules for the host are stacked, and the wildcard rules only apply to hosts without any
+    let rate_limit_rules = config.rate_limit_rules.as_deref().unwrap_or_default();
+    let rule_ids = rate_limit_rule_ids(rate_limit_rules);
+    let mut rules: Vec<(usize, String, &RateLimitRule)> = rule_ids
+        .iter()
+        .cloned()
+        .zip(rate_limit_rules)
+        .enumerate()
+        .filter(|(_, (_, rule))| rule.host == host)
+        .map(|(index, (id, rule))| (index, id, rule))
+        .collect();
+    if rules.is_empty() {
+        rules = rule_ids
+            .into_iter()
+            .zip(rate_limit_rules)
+            .enumerate()
+            .filter(|(_, (_, rule))| rule.host == "*")
+            .map(|(index, (id, rule))| (index, id, rule))
+            .collect();
+    }
+
+    if rules.is_empty() {
+        // No matching rule found, apply default behavior if needed
+        log::info!("No rate limit rule found for host: {} so no rate limit applied", host);
+        return Ok(RateLimitResponse {
+            response: String::new(),
+            status_code: 200,
+            seconds: 0,
+            decision: RateLimitDecision::NoRule,
+            quota: None,
+        });
+    }
+
+    // Rules count the request in the order they are written, until one of them rejects it; the
+    // rules after that one aren't charged for a request that never reaches the upstream
+    let redis = config.redis.clone().unwrap_or_default();
+    let mut decision = RateLimitDecision::Excluded;
+    let mut retry_after = Duration::ZERO;
+    let mut tightest: Option<(LimitOutcome, RateLimitQuota)> = None;
+    for (index, id, rule) in rules {
+        log::debug!("Rate limit rule {}: {:?}", id, rule);
+
+        // Check if the request path or the client IP is excluded from the rule
+        if rule.excluded_paths.iter().any(|p| path.starts_with(p)) ||
+            client_addr.is_some_and(|ip| {
+                ip_lists.rate_limit_exclusions(index).is_some_and(|excluded| excluded.contains(&ip))
+            })
+        {
+            continue;
+        }
 
-            // Check if the IP is in the excluded list
-            if rule.excluded_ip_list.contains(&client_ip.to_string()) {
-                return Ok(RateLimitResponse {
-                    response: String::new(),
-                    status_code: 200,
-                    seconds: 0,
-                });
+        let backend: Box<dyn RateLimitBackend + '_> = match rule.strategy {
+            RateLimitStrategy::Local => Box::new(LocalBackend { rate_limiter_map }),
+            RateLimitStrategy::Redis => Box::new(RedisBackend { config: &redis }),
+        };
+        let key = LimiterKey { rule: id, key: rate_limit_key(req, &rule.key, client_ip) };
+        log::debug!("Rate limit key: {:?}", key);
+
+        let result = backend.check(rule, &key).await;
+        if let Ok(outcome) = &result {
+            if tightest.as_ref().is_none_or(|(current, _)| restrictiveness(outcome) > restrictiveness(current)) {
+                tightest = Some((*outcome, RateLimitQuota::new(rule, outcome)));
             }
+        }
 
-            // Enforce the rate limit based on the rule's settings
-            let mut rate_limiters = rate_limiter_map.lock().await;
-            let rate_limiter = rate_limiters
-                .entry(client_ip.to_string())
-                .or_insert_with(|| {
-                    Ratelimiter::builder(rule.limit, Duration::from_secs(rule.duration))
-                        .max_tokens(rule.max_tokens)
-                        .initial_available(rule.limit)
-                        .build()
-                        .unwrap()
-                });
-
-            match rate_limiter.try_wait() {
-                Ok(()) => Ok(RateLimitResponse {
-                    response: String::new(),
-                    status_code: 200,
-                    seconds: 0,
-                }),
-                Err(seconds) => Ok(RateLimitResponse {
-                    response: format!(
-                        "Rate limit exceeded. Try again in {} seconds",
-                        seconds.as_secs()
-                    ),
-                    status_code: 429,
-                    seconds: seconds.as_secs(),
-                }),
+        match result {
+            Ok(outcome) if outcome.allowed => {
+                if decision == RateLimitDecision::Excluded {
+                    decision = RateLimitDecision::Allowed;
+                }
+            }
+            Ok(outcome) => {
+                decision = RateLimitDecision::Limited;
+                retry_after = outcome.retry_after;
+                break;
+            }
+            Err(e) => {
+                log::warn!("Failed to check the Redis rate limit {} for {}: {}", key.rule, host, e);
+                decision = RateLimitDecision::Unavailable;
+                // a request refused while Redis is down isn't counted by the rules after this one
+                if redis.failure_mode == RedisFailureMode::Closed {
+                    break;
+                }
             }
-        } else {
-            // No matching rule found, apply default behavior if needed
-            log::info!("No rate limit rule found for host: {} so no rate limit applied", host);
+        }
+    }
+
+    let quota = tightest.map(|(_, quota)| quota);
+    match decision {
+        RateLimitDecision::Limited => {
+            let seconds = ceil_secs(retry_after);
+            Ok(RateLimitResponse {
+                response: format!("Rate limit exceeded. Try again in {} seconds", seconds),
+                status_code: 429,
+                seconds,
+                decision,
+                quota,
+            })
+        }
+        _ => {
+            let status_code = match (decision, redis.failure_mode) {
+                (RateLimitDecision::Unavailable, RedisFailureMode::Closed) => 503,
+                _ => 200,
+            };
             Ok(RateLimitResponse {
                 response: String::new(),
-                status_code: 200,
+                status_code,
                 seconds: 0,
+                decision,
+                quota,
             })
         }
-    } else {
-        // No rate limit rules specified, apply default behavior if needed
-        Ok(RateLimitResponse {
-            response: String::new(),
-            status_code: 200,
-            seconds: 0,
-        })
     }
 }
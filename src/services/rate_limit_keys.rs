@@ -0,0 +1,76 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper::header::{ AUTHORIZATION, COOKIE };
+use hyper::Request;
+
+use crate::utils::RateLimitKey;
+
+/// Key shared by every client of a rule keyed by `global`.
+const GLOBAL_KEY: &str = "global";
+
+/// Builds the key a request is counted under from a rule's `key` list.
+///
+/// Each part is tagged with where it came from, as in `ip:203.0.113.9` or
+/// `header:x-api-key:abc`, so a header holding some client's IP doesn't share that client's
+/// bucket. Values taken from the request have `%` and `|` escaped, so one part can't pass for
+/// several. Parts the request doesn't have, such as a missing header or cookie, fall back to the
+/// client IP, so those requests are still limited per client rather than sharing one bucket.
+pub fn rate_limit_key<B>(req: &Request<B>, keys: &[RateLimitKey], client_ip: &str) -> String {
+    keys.iter()
+        .map(|key| key_part(req, key).unwrap_or_else(|| format!("ip:{}", client_ip)))
+        .collect::<Vec<_>>()
+        .join("|")
+}
+
+fn key_part<B>(req: &Request<B>, key: &RateLimitKey) -> Option<String> {
+    match key {
+        RateLimitKey::Ip => None,
+        RateLimitKey::Header(name) => req
+            .headers()
+            .get(name.as_str())
+            .map(|value| String::from_utf8_lossy(value.as_bytes()).trim().to_string())
+            .filter(|value| !value.is_empty())
+            .map(|value| format!("header:{}:{}", name, escape(&value))),
+        RateLimitKey::BearerSubject => bearer_subject(req).map(|subject| format!("sub:{}", escape(&subject))),
+        RateLimitKey::Cookie(name) => cookie(req, name).map(|value| format!("cookie:{}:{}", name, escape(&value))),
+        RateLimitKey::Path => Some(format!("path:{}", escape(req.uri().path()))),
+        RateLimitKey::Method => Some(format!("method:{}", req.method())),
+        RateLimitKey::Global => Some(GLOBAL_KEY.to_string()),
+    }
+}
+
+/// Escapes the characters that would make a value ambiguous inside a key.
+fn escape(value: &str) -> String {
+    value.replace('%', "%25").replace('|', "%7C")
+}
+
+/// The `sub` claim of a JWT in the `Authorization: Bearer` header, read without verifying the
+/// signature.
+fn bearer_subject<B>(req: &Request<B>) -> Option<String> {
+    let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("bearer") {
+        return None;
+    }
+
+    let payload = token.trim().split('.').nth(1)?;
+    let claims = URL_SAFE_NO_PAD.decode(payload.trim_end_matches('=')).ok()?;
+    let claims: serde_json::Value = serde_json::from_slice(&claims).ok()?;
+    claims
+        .get("sub")?
+        .as_str()
+        .filter(|subject| !subject.is_empty())
+        .map(str::to_string)
+}
+
+fn cookie<B>(req: &Request<B>, name: &str) -> Option<String> {
+    req.headers()
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(cookie, _)| *cookie == name)
+        .map(|(_, value)| value.trim_matches('"').to_string())
+        .filter(|value| !value.is_empty())
+}
@@ -0,0 +1,409 @@
+use std::collections::VecDeque;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+use crate::utils::{ RateLimitAlgorithm, RateLimitRule };
+
+/// The limits of a rule, as every algorithm reads them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitParams {
+    /// Requests allowed per `window`.
+    pub limit: u64,
+    pub window: Duration,
+    /// Requests that may be made at once after a quiet period. Only the token bucket and GCRA
+    /// allow bursts; the window algorithms never go over `limit` per window.
+    pub burst: u64,
+}
+
+/// Shortest window a limiter works with.
+const MIN_WINDOW: Duration = Duration::from_millis(1);
+
+impl LimitParams {
+    /// These limits raised to what every algorithm can work with: at least one request in a
+    /// window of at least a millisecond. The validator rejects rules below that, so this only
+    /// keeps limiters built some other way from dividing by zero.
+    pub fn clamped(self) -> LimitParams {
+        LimitParams {
+            limit: self.limit.max(1),
+            window: self.window.max(MIN_WINDOW),
+            burst: self.burst.max(1),
+        }
+    }
+
+    /// Most requests a client can make at once: `burst` for the algorithms that allow bursts,
+    /// `limit` for the others.
+    pub fn quota(&self, algorithm: RateLimitAlgorithm) -> u64 {
+        match algorithm {
+            RateLimitAlgorithm::TokenBucket | RateLimitAlgorithm::Gcra => self.burst,
+            _ => self.limit,
+        }
+    }
+}
+
+impl From<&RateLimitRule> for LimitParams {
+    fn from(rule: &RateLimitRule) -> Self {
+        LimitParams {
+            limit: rule.limit,
+            window: Duration::from_secs(rule.duration),
+            burst: rule.max_tokens,
+        }.clamped()
+    }
+}
+
+/// Identifies the limiter of one client under one rule.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct LimiterKey {
+    /// ID of the rule, from `rate_limit_rule_ids`.
+    pub rule: String,
+    /// What the rule counts requests by, from `rate_limit_key`.
+    pub key: String,
+}
+
+/// What a limiter decided about one request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LimitOutcome {
+    pub allowed: bool,
+    /// Requests that could still be made right away.
+    pub remaining: u64,
+    /// How long until a request would be allowed again, when this one wasn't.
+    pub retry_after: Duration,
+    /// How long until the client is back to its full quota, if it makes no more requests.
+    pub reset_after: Duration,
+}
+
+impl LimitOutcome {
+    fn allowed(remaining: u64, reset_after: Duration) -> Self {
+        LimitOutcome { allowed: true, remaining, retry_after: Duration::ZERO, reset_after }
+    }
+
+    fn limited(retry_after: Duration, reset_after: Duration) -> Self {
+        LimitOutcome { allowed: false, remaining: 0, retry_after, reset_after }
+    }
+}
+
+/// Rate-limit state of one key.
+///
+/// Times are durations since the Unix epoch, the clock Redis scripts read as well, so fixed
+/// windows start at the same moments whichever backend keeps the state.
+pub trait Limiter: Send {
+    fn algorithm(&self) -> RateLimitAlgorithm;
+
+    fn params(&self) -> LimitParams;
+
+    /// Checks a request made at `now`, counting it if it is allowed.
+    fn check(&mut self, now: Duration) -> LimitOutcome;
+
+    /// Requests that could be made at `now` without being limited.
+    fn remaining(&self, now: Duration) -> u64;
+
+    /// Whether the limiter is back to the state of a new one at `now`, so dropping it changes
+    /// nothing.
+    fn is_reset(&self, now: Duration) -> bool;
+}
+
+/// A limiter for a key that hasn't made any requests yet.
+pub fn new_limiter(algorithm: RateLimitAlgorithm, params: LimitParams) -> Box<dyn Limiter> {
+    let params = params.clamped();
+    match algorithm {
+        RateLimitAlgorithm::TokenBucket => Box::new(TokenBucket { params, tokens: params.burst as f64, updated: None }),
+        RateLimitAlgorithm::FixedWindow => Box::new(FixedWindow { params, window: 0, count: 0 }),
+        RateLimitAlgorithm::SlidingWindowLog => Box::new(SlidingWindowLog { params, log: VecDeque::new() }),
+        RateLimitAlgorithm::SlidingWindowCounter =>
+            Box::new(SlidingWindowCounter { params, window: 0, current: 0, previous: 0 }),
+        RateLimitAlgorithm::Gcra => Box::new(Gcra { params, tat: None }),
+    }
+}
+
+/// The current time, as limiters expect it.
+pub fn unix_now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default()
+}
+
+/// Requests per second a rule allows on average.
+fn rate(params: &LimitParams) -> f64 {
+    params.limit as f64 / params.window.as_secs_f64()
+}
+
+fn window_index(now: Duration, window: Duration) -> u128 {
+    now.as_nanos() / window.as_nanos().max(1)
+}
+
+/// Holds up to `burst` tokens and gains `limit` every `window`, continuously. New buckets
+/// start full and each request takes a token.
+struct TokenBucket {
+    params: LimitParams,
+    tokens: f64,
+    updated: Option<Duration>,
+}
+
+impl TokenBucket {
+    fn tokens_at(&self, now: Duration) -> f64 {
+        match self.updated {
+            Some(updated) => {
+                let refill = now.saturating_sub(updated).as_secs_f64() * rate(&self.params);
+                (self.tokens + refill).min(self.params.burst as f64)
+            }
+            None => self.tokens,
+        }
+    }
+}
+
+impl Limiter for TokenBucket {
+    fn algorithm(&self) -> RateLimitAlgorithm {
+        RateLimitAlgorithm::TokenBucket
+    }
+
+    fn params(&self) -> LimitParams {
+        self.params
+    }
+
+    fn check(&mut self, now: Duration) -> LimitOutcome {
+        self.tokens = self.tokens_at(now);
+        self.updated = Some(now);
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+
+        let rate = rate(&self.params);
+        let reset_after = Duration::from_secs_f64(((self.params.burst as f64) - self.tokens).max(0.0) / rate);
+        if allowed {
+            LimitOutcome::allowed(self.tokens as u64, reset_after)
+        } else {
+            LimitOutcome::limited(Duration::from_secs_f64((1.0 - self.tokens) / rate), reset_after)
+        }
+    }
+
+    fn remaining(&self, now: Duration) -> u64 {
+        self.tokens_at(now) as u64
+    }
+
+    fn is_reset(&self, now: Duration) -> bool {
+        self.tokens_at(now) >= self.params.burst as f64
+    }
+}
+
+/// Counts requests in consecutive windows of `window`, allowing `limit` in each.
+struct FixedWindow {
+    params: LimitParams,
+    window: u128,
+    count: u64,
+}
+
+impl Limiter for FixedWindow {
+    fn algorithm(&self) -> RateLimitAlgorithm {
+        RateLimitAlgorithm::FixedWindow
+    }
+
+    fn params(&self) -> LimitParams {
+        self.params
+    }
+
+    fn check(&mut self, now: Duration) -> LimitOutcome {
+        let window = window_index(now, self.params.window);
+        if window != self.window {
+            self.window = window;
+            self.count = 0;
+        }
+
+        let window_end = self.params.window.as_nanos() * (window + 1);
+        let reset_after = Duration::from_nanos((window_end - now.as_nanos()) as u64);
+        if self.count < self.params.limit {
+            self.count += 1;
+            LimitOutcome::allowed(self.params.limit - self.count, reset_after)
+        } else {
+            LimitOutcome::limited(reset_after, reset_after)
+        }
+    }
+
+    fn remaining(&self, now: Duration) -> u64 {
+        if window_index(now, self.params.window) == self.window {
+            self.params.limit - self.count
+        } else {
+            self.params.limit
+        }
+    }
+
+    fn is_reset(&self, now: Duration) -> bool {
+        self.count == 0 || window_index(now, self.params.window) != self.window
+    }
+}
+
+/// Remembers when each request was made and allows `limit` in any `window`.
+struct SlidingWindowLog {
+    params: LimitParams,
+    log: VecDeque<Duration>,
+}
+
+impl SlidingWindowLog {
+    fn in_window(&self, now: Duration) -> impl Iterator<Item = &Duration> {
+        let start = now.saturating_sub(self.params.window);
+        self.log.iter().filter(move |time| **time > start)
+    }
+}
+
+impl Limiter for SlidingWindowLog {
+    fn algorithm(&self) -> RateLimitAlgorithm {
+        RateLimitAlgorithm::SlidingWindowLog
+    }
+
+    fn params(&self) -> LimitParams {
+        self.params
+    }
+
+    fn check(&mut self, now: Duration) -> LimitOutcome {
+        let start = now.saturating_sub(self.params.window);
+        while self.log.front().is_some_and(|time| *time <= start) {
+            self.log.pop_front();
+        }
+
+        if (self.log.len() as u64) < self.params.limit {
+            self.log.push_back(now);
+            return LimitOutcome::allowed(self.params.limit - (self.log.len() as u64), self.params.window);
+        }
+
+        let oldest = self.log.front().copied().unwrap_or(now);
+        let newest = self.log.back().copied().unwrap_or(now);
+        LimitOutcome::limited(
+            (oldest + self.params.window).saturating_sub(now),
+            (newest + self.params.window).saturating_sub(now)
+        )
+    }
+
+    fn remaining(&self, now: Duration) -> u64 {
+        self.params.limit.saturating_sub(self.in_window(now).count() as u64)
+    }
+
+    fn is_reset(&self, now: Duration) -> bool {
+        self.in_window(now).next().is_none()
+    }
+}
+
+/// Approximates a sliding window from the counts of the current and previous fixed windows,
+/// weighting the previous one by how much of it the sliding window still covers.
+struct SlidingWindowCounter {
+    params: LimitParams,
+    window: u128,
+    current: u64,
+    previous: u64,
+}
+
+impl SlidingWindowCounter {
+    /// The (current, previous) counts as of `now`.
+    fn counts_at(&self, now: Duration) -> (u64, u64) {
+        let window = window_index(now, self.params.window);
+        if window == self.window {
+            (self.current, self.previous)
+        } else if window == self.window + 1 {
+            (0, self.current)
+        } else {
+            (0, 0)
+        }
+    }
+
+    /// Requests counted in the window ending at `now`, and how far `now` is into its fixed window.
+    fn estimate(&self, now: Duration, current: u64, previous: u64) -> (f64, f64) {
+        let window = self.params.window.as_secs_f64();
+        let elapsed = (now.as_nanos() % self.params.window.as_nanos().max(1)) as f64 / 1e9;
+        ((previous as f64) * (1.0 - elapsed / window) + (current as f64), elapsed)
+    }
+}
+
+impl Limiter for SlidingWindowCounter {
+    fn algorithm(&self) -> RateLimitAlgorithm {
+        RateLimitAlgorithm::SlidingWindowCounter
+    }
+
+    fn params(&self) -> LimitParams {
+        self.params
+    }
+
+    fn check(&mut self, now: Duration) -> LimitOutcome {
+        let (current, previous) = self.counts_at(now);
+        self.window = window_index(now, self.params.window);
+        self.current = current;
+        self.previous = previous;
+
+        let limit = self.params.limit as f64;
+        let window = self.params.window.as_secs_f64();
+        let (estimate, elapsed) = self.estimate(now, current, previous);
+        if estimate + 1.0 <= limit {
+            self.current += 1;
+            // the current window counts until the end of the next one
+            let reset_after = Duration::from_secs_f64(2.0 * window - elapsed);
+            return LimitOutcome::allowed((limit - estimate - 1.0) as u64, reset_after);
+        }
+
+        let reset_after = Duration::from_secs_f64(if current > 0 { 2.0 * window - elapsed } else { window - elapsed });
+        // wait until enough of the previous window has slid out, or into the next window
+        let wait = if current < self.params.limit && previous > 0 {
+            window - elapsed - (window * (limit - (current as f64) - 1.0)) / (previous as f64)
+        } else {
+            window - elapsed + window * (1.0 - (limit - 1.0) / (current as f64))
+        };
+        LimitOutcome::limited(Duration::from_secs_f64(wait.max(0.0)), reset_after)
+    }
+
+    fn remaining(&self, now: Duration) -> u64 {
+        let (current, previous) = self.counts_at(now);
+        let (estimate, _) = self.estimate(now, current, previous);
+        (self.params.limit as f64 - estimate).max(0.0) as u64
+    }
+
+    fn is_reset(&self, now: Duration) -> bool {
+        self.counts_at(now) == (0, 0)
+    }
+}
+
+/// Generic cell rate algorithm: requests are due one `window / limit` apart, and may arrive
+/// up to `burst - 1` intervals early. Behaves like a token bucket, tracking only the
+/// theoretical arrival time of the next request.
+struct Gcra {
+    params: LimitParams,
+    /// In nanoseconds since the Unix epoch.
+    tat: Option<u128>,
+}
+
+impl Gcra {
+    /// Nanoseconds between requests.
+    fn interval(&self) -> u128 {
+        (self.params.window.as_nanos() / (self.params.limit as u128)).max(1)
+    }
+}
+
+impl Limiter for Gcra {
+    fn algorithm(&self) -> RateLimitAlgorithm {
+        RateLimitAlgorithm::Gcra
+    }
+
+    fn params(&self) -> LimitParams {
+        self.params
+    }
+
+    fn check(&mut self, now: Duration) -> LimitOutcome {
+        let now = now.as_nanos();
+        let interval = self.interval();
+        let tat = self.tat.map_or(now, |tat| tat.max(now));
+        let next_tat = tat + interval;
+        let allow_at = next_tat.saturating_sub(interval * (self.params.burst as u128));
+
+        if now < allow_at {
+            return LimitOutcome::limited(
+                Duration::from_nanos((allow_at - now) as u64),
+                Duration::from_nanos((tat - now) as u64)
+            );
+        }
+        self.tat = Some(next_tat);
+        LimitOutcome::allowed(((now - allow_at) / interval) as u64, Duration::from_nanos((next_tat - now) as u64))
+    }
+
+    fn remaining(&self, now: Duration) -> u64 {
+        let now = now.as_nanos();
+        let tat = self.tat.map_or(now, |tat| tat.max(now));
+        let used = (tat - now).div_ceil(self.interval()) as u64;
+        self.params.burst.saturating_sub(used)
+    }
+
+    fn is_reset(&self, now: Duration) -> bool {
+        self.tat.is_none_or(|tat| tat <= now.as_nanos())
+    }
+}
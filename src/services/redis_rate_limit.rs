@@ -0,0 +1,158 @@
+use std::sync::{ Arc, Mutex };
+use std::time::{ Duration, Instant };
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use redis::aio::{ ConnectionManager, ConnectionManagerConfig };
+use redis::{ Client, IntoConnectionInfo, Script };
+use tokio::sync::OnceCell;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+use super::{ LimitOutcome, LimitParams, LimiterKey, RateLimitBackend };
+use crate::utils::{ redact_url_password, RateLimitAlgorithm, RateLimitRule, RedisConfig };
+
+/// How long to wait after a failed connection attempt before trying again. Until then checks
+/// fail straight away instead of waiting for Redis on every request.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref TOKEN_BUCKET: Script = Script::new(include_str!("redis_scripts/token_bucket.lua"));
+    static ref FIXED_WINDOW: Script = Script::new(include_str!("redis_scripts/fixed_window.lua"));
+    static ref SLIDING_WINDOW_LOG: Script = Script::new(include_str!("redis_scripts/sliding_window_log.lua"));
+    static ref SLIDING_WINDOW_COUNTER: Script = Script::new(include_str!("redis_scripts/sliding_window_counter.lua"));
+    static ref GCRA: Script = Script::new(include_str!("redis_scripts/gcra.lua"));
+    static ref CONNECTION: Mutex<Option<RedisConnection>> = Mutex::new(None);
+}
+
+/// The shared connection, along with the settings it was made with so it can be replaced
+/// when they change on reload.
+struct RedisConnection {
+    config: RedisConfig,
+    /// The connection, or when the attempt to make it failed. A failed attempt is retried
+    /// [`RECONNECT_INTERVAL`] later.
+    attempt: Arc<OnceCell<Result<ConnectionManager, Instant>>>,
+}
+
+impl RedisConnection {
+    fn is_retry_due(&self) -> bool {
+        matches!(self.attempt.get(), Some(Err(failed_at)) if failed_at.elapsed() >= RECONNECT_INTERVAL)
+    }
+}
+
+/// Keeps limiters in Redis, so every SheldX instance using the same server shares them.
+pub struct RedisBackend<'a> {
+    pub config: &'a RedisConfig,
+}
+
+#[async_trait]
+impl RateLimitBackend for RedisBackend<'_> {
+    async fn check(&self, rule: &RateLimitRule, key: &LimiterKey) -> Result<LimitOutcome, String> {
+        check_redis_rate_limit(self.config, rule, key).await
+    }
+}
+
+/// Counts a request for `key` against `rule`, atomically, in Redis.
+///
+/// The Redis key includes the rule's algorithm and limits, so changing them starts from fresh
+/// state, as it does for local limiters, and the old keys expire.
+pub async fn check_redis_rate_limit(
+    config: &RedisConfig,
+    rule: &RateLimitRule,
+    key: &LimiterKey
+) -> Result<LimitOutcome, String> {
+    let mut connection = redis_connection(config).await?;
+    let redis_key = format!(
+        "{}{}:{}-{}-{}-{}:{}",
+        config.key_prefix,
+        key.rule,
+        rule.algorithm.as_str(),
+        rule.limit,
+        rule.duration,
+        rule.max_tokens,
+        key.key
+    );
+
+    let script = match rule.algorithm {
+        RateLimitAlgorithm::TokenBucket => &*TOKEN_BUCKET,
+        RateLimitAlgorithm::FixedWindow => &*FIXED_WINDOW,
+        RateLimitAlgorithm::SlidingWindowLog => &*SLIDING_WINDOW_LOG,
+        RateLimitAlgorithm::SlidingWindowCounter => &*SLIDING_WINDOW_COUNTER,
+        RateLimitAlgorithm::Gcra => &*GCRA,
+    };
+    // every script takes the same arguments; the ID only matters to the sliding window log
+    let params = LimitParams::from(rule);
+    let mut invocation = script.key(&redis_key);
+    invocation
+        .arg(params.limit)
+        .arg(u64::try_from(params.window.as_millis()).unwrap_or(u64::MAX))
+        .arg(params.burst)
+        .arg(Uuid::new_v4().to_string());
+
+    let (allowed, remaining, wait_ms, reset_ms): (i64, i64, i64, i64) = timeout(
+        Duration::from_millis(config.timeout_ms),
+        invocation.invoke_async(&mut connection)
+    ).await
+        .map_err(|_| format!("no answer within {}ms", config.timeout_ms))?
+        .map_err(|e| e.to_string())?;
+
+    Ok(LimitOutcome {
+        allowed: allowed == 1,
+        remaining: remaining.max(0) as u64,
+        retry_after: Duration::from_millis(wait_ms.max(0) as u64),
+        reset_after: Duration::from_millis(reset_ms.max(0) as u64),
+    })
+}
+
+/// A connection to the configured Redis server, connecting first if needed. The connection
+/// manager reconnects on its own once a connection has been made.
+///
+/// Requests arriving while a connection is being made wait for that attempt instead of each
+/// making their own, and the lock on the shared connection is never held while waiting.
+async fn redis_connection(config: &RedisConfig) -> Result<ConnectionManager, String> {
+    let attempt = {
+        let mut connection = CONNECTION.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        match connection.as_ref() {
+            Some(current) if current.config == *config && !current.is_retry_due() => current.attempt.clone(),
+            _ => {
+                let attempt = Arc::new(OnceCell::new());
+                *connection = Some(RedisConnection { config: config.clone(), attempt: attempt.clone() });
+                attempt
+            }
+        }
+    };
+
+    let manager = attempt.get_or_init(|| async {
+        let url = redact_url_password(&config.url).unwrap_or_else(|| config.url.clone());
+        log::info!("Connecting to Redis at {}", url);
+        connect(config).await.map_err(|e| {
+            log::error!("Failed to connect to Redis at {}: {}", url, e);
+            Instant::now()
+        })
+    }).await;
+    manager.clone().map_err(|_| "Redis is unreachable".to_string())
+}
+
+async fn connect(config: &RedisConfig) -> Result<ConnectionManager, String> {
+    let mut info = config.url
+        .as_str()
+        .into_connection_info()
+        .map_err(|e| e.to_string())?;
+    if let Some(username) = &config.username {
+        info.redis.username = Some(username.clone());
+    }
+    if let Some(password) = &config.password {
+        info.redis.password = Some(password.clone());
+    }
+    if let Some(db) = config.db {
+        info.redis.db = db;
+    }
+
+    let client = Client::open(info).map_err(|e| e.to_string())?;
+    let timeout = Duration::from_millis(config.timeout_ms);
+    let manager_config = ConnectionManagerConfig::new()
+        .set_connection_timeout(timeout)
+        .set_response_timeout(timeout)
+        .set_number_of_retries(1);
+    ConnectionManager::new_with_config(client, manager_config).await.map_err(|e| e.to_string())
+}
@@ -0,0 +1,33 @@
+-- Fixed window: at most ARGV[1] requests in each window of ARGV[2] milliseconds, counted from
+-- the Unix epoch.
+--
+-- KEYS[1]: the current window and its count
+-- Returns {allowed (0 or 1), requests left, milliseconds until a request is allowed,
+--          milliseconds until the full limit is available again}
+
+-- needed before Redis 5 to write after reading the clock
+if redis.replicate_commands then
+  redis.replicate_commands()
+end
+
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + tonumber(time[2]) / 1000
+
+local index = math.floor(now / window)
+local state = redis.call('HMGET', KEYS[1], 'window', 'count')
+local count = 0
+if tonumber(state[1]) == index then
+  count = tonumber(state[2]) or 0
+end
+
+local reset = (index + 1) * window - now
+if count < limit then
+  count = count + 1
+  redis.call('HSET', KEYS[1], 'window', index, 'count', count)
+  redis.call('PEXPIRE', KEYS[1], math.ceil(reset))
+  return {1, limit - count, 0, math.ceil(reset)}
+end
+
+return {0, 0, math.ceil(reset), math.ceil(reset)}
@@ -0,0 +1,30 @@
+-- Generic cell rate algorithm: requests are due ARGV[2] / ARGV[1] milliseconds apart and may
+-- arrive up to ARGV[3] - 1 intervals early, which allows bursts of ARGV[3] requests.
+--
+-- KEYS[1]: the theoretical arrival time of the next request, in milliseconds
+-- Returns {allowed (0 or 1), requests left, milliseconds until a request is allowed,
+--          milliseconds until the full limit is available again}
+
+-- needed before Redis 5 to write after reading the clock
+if redis.replicate_commands then
+  redis.replicate_commands()
+end
+
+local interval = tonumber(ARGV[2]) / tonumber(ARGV[1])
+local burst = tonumber(ARGV[3])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + tonumber(time[2]) / 1000
+
+local tat = tonumber(redis.call('GET', KEYS[1]))
+if tat == nil or tat < now then
+  tat = now
+end
+local next_tat = tat + interval
+local allow_at = next_tat - burst * interval
+if now < allow_at then
+  return {0, 0, math.ceil(allow_at - now), math.ceil(tat - now)}
+end
+
+-- once the arrival time has passed the key is the same as a missing one
+redis.call('SET', KEYS[1], string.format('%.3f', next_tat), 'PX', math.ceil(next_tat - now))
+return {1, math.floor((now - allow_at) / interval), 0, math.ceil(next_tat - now)}
@@ -0,0 +1,53 @@
+-- Sliding window counter: at most ARGV[1] requests in any ARGV[2] milliseconds, estimated from
+-- the counts of the current and previous fixed windows. The previous count is weighted by how
+-- much of that window the sliding window still covers.
+--
+-- KEYS[1]: the current window and the two counts
+-- Returns {allowed (0 or 1), requests left, milliseconds until a request is allowed,
+--          milliseconds until the full limit is available again}
+
+-- needed before Redis 5 to write after reading the clock
+if redis.replicate_commands then
+  redis.replicate_commands()
+end
+
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + tonumber(time[2]) / 1000
+
+local index = math.floor(now / window)
+local state = redis.call('HMGET', KEYS[1], 'window', 'current', 'previous')
+local stored = tonumber(state[1])
+local current = tonumber(state[2]) or 0
+local previous = tonumber(state[3]) or 0
+if stored == index - 1 then
+  previous = current
+  current = 0
+elseif stored ~= index then
+  previous = 0
+  current = 0
+end
+
+local elapsed = now - index * window
+local estimate = previous * (1 - elapsed / window) + current
+if estimate + 1 <= limit then
+  current = current + 1
+  redis.call('HSET', KEYS[1], 'window', index, 'current', current, 'previous', previous)
+  -- the current count matters until the end of the next window
+  redis.call('PEXPIRE', KEYS[1], math.ceil(2 * window - elapsed))
+  return {1, math.floor(limit - estimate - 1), 0, math.ceil(2 * window - elapsed)}
+end
+
+local reset = window - elapsed
+if current > 0 then
+  reset = 2 * window - elapsed
+end
+-- wait until enough of the previous window has slid out, or into the next window
+local wait
+if current < limit and previous > 0 then
+  wait = window - elapsed - window * (limit - current - 1) / previous
+else
+  wait = window - elapsed + window * (1 - (limit - 1) / current)
+end
+return {0, 0, math.ceil(math.max(0, wait)), math.ceil(reset)}
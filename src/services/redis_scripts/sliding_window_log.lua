@@ -0,0 +1,28 @@
+-- Sliding window log: at most ARGV[1] requests in any ARGV[2] milliseconds.
+--
+-- KEYS[1]: sorted set of request times
+-- ARGV[4]: unique ID of this request
+-- Returns {allowed (0 or 1), requests left, milliseconds until a request is allowed,
+--          milliseconds until the full limit is available again}
+
+-- needed before Redis 5 to write after reading the clock
+if redis.replicate_commands then
+  redis.replicate_commands()
+end
+
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + tonumber(time[2]) / 1000
+
+redis.call('ZREMRANGEBYSCORE', KEYS[1], '-inf', now - window)
+local count = redis.call('ZCARD', KEYS[1])
+if count < limit then
+  redis.call('ZADD', KEYS[1], now, ARGV[4])
+  redis.call('PEXPIRE', KEYS[1], window)
+  return {1, limit - count - 1, 0, window}
+end
+
+local oldest = redis.call('ZRANGE', KEYS[1], 0, 0, 'WITHSCORES')
+local newest = redis.call('ZRANGE', KEYS[1], -1, -1, 'WITHSCORES')
+return {0, 0, math.ceil(tonumber(oldest[2]) + window - now), math.ceil(tonumber(newest[2]) + window - now)}
@@ -0,0 +1,40 @@
+-- Token bucket: ARGV[1] tokens are added every ARGV[2] milliseconds, continuously, up to
+-- ARGV[3] tokens. Each request takes one token; new buckets start full.
+--
+-- KEYS[1]: the bucket
+-- Returns {allowed (0 or 1), tokens left, milliseconds until a token is available,
+--          milliseconds until the bucket is full again}
+
+-- needed before Redis 5 to write after reading the clock
+if redis.replicate_commands then
+  redis.replicate_commands()
+end
+
+local rate = tonumber(ARGV[1]) / tonumber(ARGV[2])
+local capacity = tonumber(ARGV[3])
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(state[1])
+local updated = tonumber(state[2])
+if tokens == nil or updated == nil then
+  tokens = capacity
+  updated = now
+end
+tokens = math.min(capacity, tokens + math.max(0, now - updated) * rate)
+
+local allowed = 0
+local wait = 0
+if tokens >= 1 then
+  tokens = tokens - 1
+  allowed = 1
+else
+  wait = math.ceil((1 - tokens) / rate)
+end
+
+redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+-- once the bucket would be full again it is the same as a new one
+redis.call('PEXPIRE', KEYS[1], math.ceil((capacity - tokens) / rate) + 1000)
+
+return {allowed, math.floor(tokens), wait, math.ceil((capacity - tokens) / rate)}
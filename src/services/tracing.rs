@@ -0,0 +1,398 @@
+/*
+Minimal distributed tracing: W3C trace context propagation plus an OTLP/HTTP (JSON) exporter.
+
+[tracing]
+enabled = true
+otlp_endpoint = "http://127.0.0.1:4318/v1/traces"
+service_name = "sheldx"
+sample_ratio = 1.0
+*/
+
+use std::sync::OnceLock;
+use std::time::{ Duration, SystemTime, UNIX_EPOCH };
+
+use http_body_util::Full;
+use hyper::body::Bytes;
+use hyper::header::{ HeaderMap, HeaderValue };
+use hyper::{ Method, Request, Uri };
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use serde_json::{ json, Value };
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::mpsc::{ channel, Receiver, Sender };
+use tokio::time::timeout;
+
+use super::record_spans_dropped;
+use crate::utils::TracingConfig;
+
+const TRACEPARENT: &str = "traceparent";
+const TRACESTATE: &str = "tracestate";
+
+/// Spans sent to the collector in one request, at most.
+const MAX_EXPORT_BATCH: usize = 512;
+/// How long finished spans may wait before being exported.
+const EXPORT_INTERVAL: Duration = Duration::from_secs(5);
+/// Finished spans that may wait for the exporter. Spans finished while it is full are dropped,
+/// so a slow collector can't make the proxy use ever more memory.
+const QUEUE_CAPACITY: usize = 8 * MAX_EXPORT_BATCH;
+/// How long the collector gets to accept a batch before it is dropped.
+const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+struct Tracer {
+    spans: Sender<FinishedSpan>,
+    sample_ratio: f64,
+}
+
+static TRACER: OnceLock<Tracer> = OnceLock::new();
+
+/// Identifies a span and carries the trace-wide sampling decision and vendor state.
+#[derive(Debug, Clone)]
+pub struct SpanContext {
+    pub trace_id: [u8; 16],
+    pub span_id: [u8; 8],
+    pub sampled: bool,
+    pub trace_state: Option<String>,
+}
+
+impl SpanContext {
+    /// Reads the `traceparent` and `tracestate` headers. Malformed or all-zero IDs are ignored,
+    /// as the W3C spec requires.
+    pub fn from_headers(headers: &HeaderMap) -> Option<SpanContext> {
+        let traceparent = headers.get(TRACEPARENT)?.to_str().ok()?.trim();
+        let mut parts = traceparent.split('-');
+        let version = parts.next()?;
+        let trace_id = parts.next()?;
+        let span_id = parts.next()?;
+        let flags = parts.next()?;
+
+        if version.len() != 2 || version == "ff" || (version == "00" && parts.next().is_some()) {
+            return None;
+        }
+
+        let trace_id: [u8; 16] = decode_hex(trace_id)?.try_into().ok()?;
+        let span_id: [u8; 8] = decode_hex(span_id)?.try_into().ok()?;
+        let flags = decode_hex(flags)?;
+        if flags.len() != 1 || trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+
+        let trace_state = headers
+            .get(TRACESTATE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        Some(SpanContext { trace_id, span_id, sampled: flags[0] & 0x01 == 0x01, trace_state })
+    }
+
+    pub fn traceparent(&self) -> String {
+        format!(
+            "00-{}-{}-{}",
+            encode_hex(&self.trace_id),
+            encode_hex(&self.span_id),
+            if self.sampled { "01" } else { "00" }
+        )
+    }
+
+    /// Writes this context into `headers` so the next hop joins the same trace.
+    pub fn inject(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(&self.traceparent()) {
+            headers.insert(TRACEPARENT, value);
+        }
+        match self.trace_state.as_deref().map(HeaderValue::from_str) {
+            Some(Ok(value)) => {
+                headers.insert(TRACESTATE, value);
+            }
+            _ => {
+                headers.remove(TRACESTATE);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SpanKind {
+    Internal = 1,
+    Server = 2,
+    Client = 3,
+}
+
+#[derive(Debug, Clone)]
+pub enum AttributeValue {
+    String(String),
+    Int(i64),
+    Bool(bool),
+}
+
+impl From<&str> for AttributeValue {
+    fn from(value: &str) -> Self {
+        AttributeValue::String(value.to_string())
+    }
+}
+
+impl From<String> for AttributeValue {
+    fn from(value: String) -> Self {
+        AttributeValue::String(value)
+    }
+}
+
+impl From<i64> for AttributeValue {
+    fn from(value: i64) -> Self {
+        AttributeValue::Int(value)
+    }
+}
+
+impl From<bool> for AttributeValue {
+    fn from(value: bool) -> Self {
+        AttributeValue::Bool(value)
+    }
+}
+
+/// A timed operation. The span is recorded when it is dropped, so early returns still
+/// produce complete traces.
+pub struct Span {
+    data: SpanData,
+}
+
+struct SpanData {
+    context: SpanContext,
+    parent_span_id: Option<[u8; 8]>,
+    name: String,
+    kind: SpanKind,
+    start: SystemTime,
+    attributes: Vec<(String, AttributeValue)>,
+    error: Option<String>,
+}
+
+struct FinishedSpan {
+    span: SpanData,
+    end: SystemTime,
+}
+
+impl Span {
+    /// Starts the first span of a request, continuing `parent` when the caller sent a trace
+    /// context and starting a new, possibly unsampled, trace otherwise.
+    pub fn root(name: &str, kind: SpanKind, parent: Option<SpanContext>) -> Span {
+        match parent {
+            Some(parent) => Span::child(&parent, name, kind),
+            None => {
+                let context = SpanContext {
+                    trace_id: random_id(),
+                    span_id: random_id(),
+                    sampled: should_sample(),
+                    trace_state: None,
+                };
+                Span::new(context, None, name, kind)
+            }
+        }
+    }
+
+    pub fn child(parent: &SpanContext, name: &str, kind: SpanKind) -> Span {
+        let context = SpanContext { span_id: random_id(), ..parent.clone() };
+        Span::new(context, Some(parent.span_id), name, kind)
+    }
+
+    fn new(context: SpanContext, parent_span_id: Option<[u8; 8]>, name: &str, kind: SpanKind) -> Span {
+        let data = SpanData {
+            context,
+            parent_span_id,
+            name: name.to_string(),
+            kind,
+            start: SystemTime::now(),
+            attributes: Vec::new(),
+            error: None,
+        };
+        Span { data }
+    }
+
+    pub fn context(&self) -> &SpanContext {
+        &self.data.context
+    }
+
+    pub fn set_attribute(&mut self, key: &str, value: impl Into<AttributeValue>) {
+        self.data.attributes.push((key.to_string(), value.into()));
+    }
+
+    pub fn set_error(&mut self, message: impl ToString) {
+        self.data.error = Some(message.to_string());
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(tracer) = TRACER.get() else {
+            return;
+        };
+        if !self.data.context.sampled {
+            return;
+        }
+
+        let span = SpanData {
+            context: self.data.context.clone(),
+            parent_span_id: self.data.parent_span_id,
+            name: std::mem::take(&mut self.data.name),
+            kind: self.data.kind,
+            start: self.data.start,
+            attributes: std::mem::take(&mut self.data.attributes),
+            error: self.data.error.take(),
+        };
+        // the exporter only goes away at shutdown, when losing spans is fine
+        if let Err(TrySendError::Full(_)) = tracer.spans.try_send(FinishedSpan { span, end: SystemTime::now() }) {
+            record_spans_dropped("queue_full", 1);
+        }
+    }
+}
+
+/// Whether spans are being exported.
+pub fn tracing_enabled() -> bool {
+    TRACER.get().is_some()
+}
+
+/// Starts exporting spans to the collector in `config`.
+pub fn init_tracing(config: &TracingConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let endpoint: Uri = config.otlp_endpoint.parse()?;
+    if endpoint.scheme_str() != Some("http") {
+        return Err(format!("Unsupported OTLP endpoint {:?}, only http:// is supported", config.otlp_endpoint).into());
+    }
+
+    let (spans, receiver) = channel(QUEUE_CAPACITY);
+    let tracer = Tracer { spans, sample_ratio: config.sample_ratio.clamp(0.0, 1.0) };
+    if TRACER.set(tracer).is_err() {
+        return Err("Tracing is already initialized".into());
+    }
+
+    tokio::spawn(export_spans(receiver, endpoint, config.service_name.clone()));
+    log::info!("Exporting traces to {}", config.otlp_endpoint);
+    Ok(())
+}
+
+fn should_sample() -> bool {
+    let sample_ratio = TRACER.get().map(|tracer| tracer.sample_ratio).unwrap_or(0.0);
+    rand::random::<f64>() < sample_ratio
+}
+
+/// Random bytes for a trace or span ID. All zeros is not a valid ID, so it is never returned.
+fn random_id<const N: usize>() -> [u8; N] {
+    loop {
+        let id: [u8; N] = rand::random();
+        if id != [0; N] {
+            return id;
+        }
+    }
+}
+
+async fn export_spans(mut receiver: Receiver<FinishedSpan>, endpoint: Uri, service_name: String) {
+    let client = Client::builder(TokioExecutor::new()).build_http::<Full<Bytes>>();
+    let mut batch = Vec::new();
+    let mut ticker = tokio::time::interval(EXPORT_INTERVAL);
+
+    loop {
+        tokio::select! {
+            span = receiver.recv() => {
+                match span {
+                    Some(span) => batch.push(span),
+                    None => break,
+                }
+                if batch.len() < MAX_EXPORT_BATCH {
+                    continue;
+                }
+            }
+            _ = ticker.tick() => {}
+        }
+
+        if batch.is_empty() {
+            continue;
+        }
+
+        let body = otlp_request(&service_name, &batch).to_string();
+        let exported = batch.len();
+        batch.clear();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(endpoint.clone())
+            .header("Content-Type", "application/json")
+            .body(Full::from(Bytes::from(body)))
+            .unwrap();
+
+        match timeout(EXPORT_TIMEOUT, client.request(request)).await {
+            Ok(Ok(response)) if response.status().is_success() => {
+                continue;
+            }
+            Ok(Ok(response)) => log::warn!("Trace collector rejected spans with status {}", response.status()),
+            Ok(Err(e)) => log::warn!("Failed to export spans to {}: {}", endpoint, e),
+            Err(_) => log::warn!("Gave up exporting spans to {} after {:?}", endpoint, EXPORT_TIMEOUT),
+        }
+        record_spans_dropped("export_failed", exported);
+    }
+}
+
+/// Builds an OTLP `ExportTraceServiceRequest` in its JSON encoding.
+fn otlp_request(service_name: &str, spans: &[FinishedSpan]) -> Value {
+    let spans: Vec<Value> = spans.iter().map(otlp_span).collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": [otlp_attribute("service.name", &AttributeValue::from(service_name))]
+            },
+            "scopeSpans": [{
+                "scope": { "name": "sheldx", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans
+            }]
+        }]
+    })
+}
+
+fn otlp_span(finished: &FinishedSpan) -> Value {
+    let span = &finished.span;
+    let mut value = json!({
+        "traceId": encode_hex(&span.context.trace_id),
+        "spanId": encode_hex(&span.context.span_id),
+        "name": span.name,
+        "kind": span.kind as i32,
+        "startTimeUnixNano": unix_nanos(span.start).to_string(),
+        "endTimeUnixNano": unix_nanos(finished.end).to_string(),
+        "attributes": span.attributes
+            .iter()
+            .map(|(key, value)| otlp_attribute(key, value))
+            .collect::<Vec<_>>(),
+        "status": match &span.error {
+            Some(message) => json!({ "code": 2, "message": message }),
+            None => json!({ "code": 0 }),
+        }
+    });
+
+    if let Some(parent_span_id) = &span.parent_span_id {
+        value["parentSpanId"] = json!(encode_hex(parent_span_id));
+    }
+    if let Some(trace_state) = &span.context.trace_state {
+        value["traceState"] = json!(trace_state);
+    }
+    value
+}
+
+fn otlp_attribute(key: &str, value: &AttributeValue) -> Value {
+    let value = match value {
+        AttributeValue::String(value) => json!({ "stringValue": value }),
+        AttributeValue::Int(value) => json!({ "intValue": value.to_string() }),
+        AttributeValue::Bool(value) => json!({ "boolValue": value }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|duration| duration.as_nanos()).unwrap_or(0)
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Decodes lowercase hex, which is the only form `traceparent` allows.
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c)) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+        .collect()
+}
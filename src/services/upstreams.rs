@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+
+use chrono::{ DateTime, Local };
+use lazy_static::lazy_static;
+
+lazy_static! {
+    /// What we have learned about each upstream target from proxied traffic.
+    static ref UPSTREAMS: RwLock<HashMap<String, UpstreamState>> = RwLock::new(HashMap::new());
+}
+
+/// Passive health of an upstream target, based on recent connection attempts.
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamState {
+    /// Drained targets get no new requests until they are undrained.
+    pub drained: bool,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub last_success: Option<DateTime<Local>>,
+    pub last_failure: Option<DateTime<Local>>,
+}
+
+impl UpstreamState {
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_failures == 0
+    }
+}
+
+pub fn record_upstream_success(target: &str) {
+    update_upstream(target, |state| {
+        state.consecutive_failures = 0;
+        state.last_success = Some(Local::now());
+    });
+}
+
+pub fn record_upstream_failure(target: &str, error: &str) {
+    update_upstream(target, |state| {
+        state.consecutive_failures += 1;
+        state.last_error = Some(error.to_string());
+        state.last_failure = Some(Local::now());
+    });
+}
+
+/// Stops (or resumes) sending new requests to `target`.
+pub fn set_upstream_drained(target: &str, drained: bool) {
+    update_upstream(target, |state| {
+        state.drained = drained;
+    });
+}
+
+pub fn is_upstream_drained(target: &str) -> bool {
+    upstream_state(target).is_some_and(|state| state.drained)
+}
+
+pub fn upstream_state(target: &str) -> Option<UpstreamState> {
+    let upstreams = UPSTREAMS.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    upstreams.get(target).cloned()
+}
+
+fn update_upstream(target: &str, update: impl FnOnce(&mut UpstreamState)) {
+    let mut upstreams = UPSTREAMS.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    update(upstreams.entry(target.to_string()).or_default());
+}
@@ -0,0 +1,227 @@
+use std::collections::HashMap;
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, BufWriter, Write };
+use std::iter;
+use std::os::unix::fs::MetadataExt;
+use std::path::{ Path, PathBuf };
+use std::sync::atomic::{ AtomicU64, Ordering };
+use std::sync::mpsc::{ sync_channel, SyncSender };
+use std::thread;
+
+use chrono::{ DateTime, Local };
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use super::{ render_placeholders, AccessLogConfig, AccessLogFormat };
+
+/// Lines that may wait for the writer before new ones are dropped.
+const QUEUE_CAPACITY: usize = 16_384;
+
+lazy_static! {
+    /// Queue of the thread writing access log lines, started on first use.
+    static ref WRITER: SyncSender<AccessLogLine> = spawn_writer();
+}
+
+/// Lines dropped since the writer last caught up.
+static DROPPED_LINES: AtomicU64 = AtomicU64::new(0);
+
+/// Paths that send access log lines to standard output instead of a file.
+const STDOUT_PATHS: [&str; 2] = ["-", "stdout"];
+
+/// One line of the access log, describing a single proxied request.
+#[derive(Debug, Clone, Serialize)]
+pub struct AccessLogEntry {
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub timestamp: DateTime<Local>,
+    pub request_id: String,
+    pub client_ip: String,
+    pub host: String,
+    pub method: String,
+    pub path: String,
+    pub protocol: String,
+    pub status: Option<u16>,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    pub upstream: Option<String>,
+    pub upstream_latency_ms: Option<f64>,
+    pub total_latency_ms: f64,
+    pub rate_limit: String,
+}
+
+fn serialize_timestamp<S: serde::Serializer>(
+    timestamp: &DateTime<Local>,
+    serializer: S
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&timestamp.to_rfc3339())
+}
+
+impl AccessLogEntry {
+    /// Formats the entry as a single line, without the trailing newline.
+    pub fn format(&self, format: &AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Common => self.render(COMMON_LOG_FORMAT),
+            AccessLogFormat::Combined => self.render(COMBINED_LOG_FORMAT),
+            AccessLogFormat::Json =>
+                serde_json::to_string(self).unwrap_or_else(|e| {
+                    log::error!("Failed to serialize access log entry: {}", e);
+                    String::new()
+                }),
+            AccessLogFormat::Custom(template) => self.render(template),
+        }
+    }
+
+    /// Replaces every `{{field}}` in `template` with the matching value, using `-` for missing ones.
+    /// Values are escaped so a client can't break out of the quotes around them or start a new
+    /// line.
+    fn render(&self, template: &str) -> String {
+        render_placeholders(template, |name| {
+            let value = match name {
+                "time_local" => self.timestamp.format("%d/%b/%Y:%H:%M:%S %z").to_string(),
+                "time_iso8601" => self.timestamp.to_rfc3339(),
+                "request_id" => self.request_id.clone(),
+                "client_ip" => self.client_ip.clone(),
+                "host" => self.host.clone(),
+                "method" => self.method.clone(),
+                "path" => self.path.clone(),
+                "protocol" => self.protocol.clone(),
+                "status" => optional(self.status),
+                "bytes_in" => self.bytes_in.to_string(),
+                "bytes_out" => self.bytes_out.to_string(),
+                "referer" => optional(self.referer.as_ref()),
+                "user_agent" => optional(self.user_agent.as_ref()),
+                "upstream" => optional(self.upstream.as_ref()),
+                "upstream_latency_ms" => optional(self.upstream_latency_ms.map(|ms| format!("{:.3}", ms))),
+                "total_latency_ms" => format!("{:.3}", self.total_latency_ms),
+                "rate_limit" => self.rate_limit.clone(),
+                _ => return None,
+            };
+            Some(escape(&value))
+        })
+    }
+}
+
+/// Escapes `"` and `\` with a backslash and other control or non-ASCII bytes as `\xHH`, as
+/// Apache does.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'"' => escaped.push_str("\\\""),
+            b'\\' => escaped.push_str("\\\\"),
+            0x20..=0x7e => escaped.push(byte as char),
+            _ => escaped.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+    escaped
+}
+
+const COMMON_LOG_FORMAT: &str =
+    "{{client_ip}} - - [{{time_local}}] \"{{method}} {{path}} {{protocol}}\" {{status}} {{bytes_out}}";
+
+const COMBINED_LOG_FORMAT: &str =
+    "{{client_ip}} - - [{{time_local}}] \"{{method}} {{path}} {{protocol}}\" {{status}} {{bytes_out}} \"{{referer}}\" \"{{user_agent}}\"";
+
+fn optional<T: ToString>(value: Option<T>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_else(|| "-".to_string())
+}
+
+/// Queues `entry` for every configured access log. Lines are written by a dedicated thread, so
+/// slow disks don't hold up request handling; when it falls behind by [`QUEUE_CAPACITY`] lines,
+/// further lines are dropped and counted. Write failures are logged and otherwise ignored so a
+/// full disk never takes down request handling.
+pub fn write_access_log(entry: &AccessLogEntry, access_logs: &[AccessLogConfig]) {
+    for access_log in access_logs {
+        let line = AccessLogLine { path: access_log.path.clone(), line: entry.format(&access_log.format) };
+        if WRITER.try_send(line).is_err() {
+            DROPPED_LINES.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+struct AccessLogLine {
+    path: String,
+    line: String,
+}
+
+fn spawn_writer() -> SyncSender<AccessLogLine> {
+    let (sender, receiver) = sync_channel::<AccessLogLine>(QUEUE_CAPACITY);
+    let spawned = thread::Builder::new()
+        .name("access-log".to_string())
+        .spawn(move || {
+            let mut files: HashMap<PathBuf, LogFile> = HashMap::new();
+            while let Ok(first) = receiver.recv() {
+                close_moved_files(&mut files);
+                // write whatever is queued, then flush once
+                for entry in iter::once(first).chain(receiver.try_iter()) {
+                    if let Err(e) = append_line(&mut files, &entry.path, &entry.line) {
+                        log::error!("Failed to write access log to {:?}: {}", entry.path, e);
+                    }
+                }
+                for (path, file) in files.iter_mut() {
+                    if let Err(e) = file.writer.flush() {
+                        log::error!("Failed to write access log to {:?}: {}", path, e);
+                    }
+                }
+                let _ = io::stdout().flush();
+
+                let dropped = DROPPED_LINES.swap(0, Ordering::Relaxed);
+                if dropped > 0 {
+                    log::warn!("Dropped {} access log lines while the access log writer was behind", dropped);
+                }
+            }
+        });
+    if let Err(e) = spawned {
+        log::error!("Failed to start the access log writer, access logs are disabled: {}", e);
+    }
+    sender
+}
+
+/// An open access log file, along with the file it was opened as.
+struct LogFile {
+    writer: BufWriter<File>,
+    /// Device and inode of the file.
+    id: (u64, u64),
+}
+
+/// Closes the files that were renamed or removed since they were opened, as log rotation does,
+/// so the next lines go to a new file at the configured path.
+fn close_moved_files(files: &mut HashMap<PathBuf, LogFile>) {
+    files.retain(|path, file| {
+        let current = fs::metadata(path).ok().map(|metadata| (metadata.dev(), metadata.ino()));
+        if current == Some(file.id) {
+            return true;
+        }
+
+        if let Err(e) = file.writer.flush() {
+            log::error!("Failed to write access log to {:?}: {}", path, e);
+        }
+        false
+    });
+}
+
+fn append_line(files: &mut HashMap<PathBuf, LogFile>, path: &str, line: &str) -> io::Result<()> {
+    if STDOUT_PATHS.contains(&path) {
+        return writeln!(io::stdout().lock(), "{}", line);
+    }
+
+    let path = PathBuf::from(path);
+    if !files.contains_key(&path) {
+        let file = open_log_file(&path)?;
+        let metadata = file.metadata()?;
+        files.insert(path.clone(), LogFile { writer: BufWriter::new(file), id: (metadata.dev(), metadata.ino()) });
+    }
+
+    let file = files.get_mut(&path).expect("access log file was just opened");
+    writeln!(file.writer, "{}", line)
+}
+
+fn open_log_file(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        if !parent.as_os_str().is_empty() && !parent.exists() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    OpenOptions::new().create(true).append(true).open(path)
+}
@@ -0,0 +1,367 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use toml::{ Table, Value };
+use toml_edit::ImDocument;
+use yaml_rust2::parser::{ MarkedEventReceiver, Parser };
+use yaml_rust2::scanner::{ Marker, TScalarStyle };
+use yaml_rust2::yaml::Hash;
+use yaml_rust2::{ Event, Yaml, YamlEmitter };
+
+use super::config_sources::join_field;
+use super::config_validator::span_of;
+
+static CONFIG_FORMAT: OnceLock<ConfigFormat> = OnceLock::new();
+
+/// A message about a file that couldn't be parsed, with the span it refers to.
+pub type ParseError = (String, Option<Range<usize>>);
+
+/// The formats a configuration file can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// The format named by the extension of `path`, if it names one.
+    pub fn from_extension(path: &Path) -> Option<ConfigFormat> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "toml" | "conf" => Some(ConfigFormat::Toml),
+            "yaml" | "yml" => Some(ConfigFormat::Yaml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// The format of the file at `path`. Files with other extensions are read as TOML.
+    pub fn from_path(path: &Path) -> ConfigFormat {
+        ConfigFormat::from_extension(path).unwrap_or(ConfigFormat::Toml)
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "toml" => Ok(ConfigFormat::Toml),
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(format!("unknown configuration format {:?}, expected toml, yaml or json", s)),
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigFormat::Toml => write!(f, "toml"),
+            ConfigFormat::Yaml => write!(f, "yaml"),
+            ConfigFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Reads the main configuration file as `format`, whatever its extension. Only the first call
+/// has an effect.
+pub fn set_config_format(format: ConfigFormat) {
+    let _ = CONFIG_FORMAT.set(format);
+}
+
+/// The format the main configuration file at `path` is read as.
+pub fn main_config_format(path: &Path) -> ConfigFormat {
+    CONFIG_FORMAT.get().copied().unwrap_or_else(|| ConfigFormat::from_path(path))
+}
+
+/// Where the fields of a configuration file were written.
+pub enum Positions {
+    Toml(ImDocument<String>),
+    /// Byte offsets by field path, for formats without a span-preserving document.
+    Fields(HashMap<String, usize>),
+    /// JSON text, whose field offsets are only worked out once a diagnostic needs them.
+    Json(String, OnceLock<HashMap<String, usize>>),
+}
+
+impl Positions {
+    /// Finds where `field` is written, falling back to its closest written parent.
+    pub fn span(&self, field: &str) -> Option<Range<usize>> {
+        match self {
+            Positions::Toml(document) => span_of(document.as_table(), field),
+            Positions::Fields(fields) => field_span(fields, field),
+            Positions::Json(text, fields) => {
+                // JSON is valid YAML, so the YAML reader can tell where the fields are
+                let fields = fields.get_or_init(|| read_yaml(text).map(|(_, fields)| fields).unwrap_or_default());
+                field_span(fields, field)
+            }
+        }
+    }
+}
+
+fn field_span(fields: &HashMap<String, usize>, mut field: &str) -> Option<Range<usize>> {
+    loop {
+        if let Some(offset) = fields.get(field) {
+            return Some(*offset..*offset);
+        }
+        field = &field[..field.rfind(['.', '['])?];
+    }
+}
+
+/// Parses a configuration file written in `format`.
+pub fn parse_config_text(text: &str, format: ConfigFormat) -> Result<(Table, Positions), ParseError> {
+    match format {
+        ConfigFormat::Toml => {
+            let document = ImDocument::parse(text.to_string()).map_err(|e| (e.message().to_string(), e.span()))?;
+            let table: Table = toml_edit::de
+                ::from_document(document.clone())
+                .map_err(|e| (e.message().to_string(), e.span()))?;
+            Ok((table, Positions::Toml(document)))
+        }
+        ConfigFormat::Yaml => {
+            let (table, fields) = read_yaml(text)?;
+            Ok((table, Positions::Fields(fields)))
+        }
+        ConfigFormat::Json => {
+            let value: serde_json::Value = serde_json::from_str(text).map_err(|e| {
+                let offset = offset_of(text, e.line(), e.column());
+                (e.to_string(), Some(offset..offset))
+            })?;
+            let table = match json_to_toml(value, "")? {
+                Some(Value::Table(table)) => table,
+                _ => {
+                    return Err(("The configuration must be a JSON object".to_string(), None));
+                }
+            };
+            Ok((table, Positions::Json(text.to_string(), OnceLock::new())))
+        }
+    }
+}
+
+/// Writes a configuration table in `format`.
+pub fn write_config_text(table: &Table, format: ConfigFormat) -> Result<String, String> {
+    match format {
+        ConfigFormat::Toml => toml::to_string(table).map_err(|e| e.to_string()),
+        ConfigFormat::Json => serde_json::to_string_pretty(table).map(|json| json + "\n").map_err(|e| e.to_string()),
+        ConfigFormat::Yaml => {
+            let mut yaml = String::new();
+            YamlEmitter::new(&mut yaml)
+                .dump(&toml_to_yaml(&Value::Table(table.clone())))
+                .map_err(|e| e.to_string())?;
+            Ok(yaml + "\n")
+        }
+    }
+}
+
+fn json_to_toml(value: serde_json::Value, field: &str) -> Result<Option<Value>, ParseError> {
+    Ok(
+        Some(match value {
+            serde_json::Value::Null => {
+                return Ok(None);
+            }
+            serde_json::Value::Bool(b) => Value::Boolean(b),
+            serde_json::Value::Number(n) =>
+                match n.as_i64() {
+                    Some(i) => Value::Integer(i),
+                    None => Value::Float(n.as_f64().unwrap_or_default()),
+                }
+            serde_json::Value::String(s) => Value::String(s),
+            serde_json::Value::Array(values) => {
+                let mut array = Vec::with_capacity(values.len());
+                for (i, value) in values.into_iter().enumerate() {
+                    let item = format!("{}[{}]", field, i);
+                    match json_to_toml(value, &item)? {
+                        Some(value) => array.push(value),
+                        None => {
+                            return Err((format!("{}: null is not allowed in a list", item), None));
+                        }
+                    }
+                }
+                Value::Array(array)
+            }
+            serde_json::Value::Object(fields) => {
+                let mut table = Table::new();
+                for (key, value) in fields {
+                    // a null field is treated as if it was left out
+                    if let Some(value) = json_to_toml(value, &join_field(field, &key))? {
+                        table.insert(key, value);
+                    }
+                }
+                Value::Table(table)
+            }
+        })
+    )
+}
+
+fn toml_to_yaml(value: &Value) -> Yaml {
+    match value {
+        Value::String(s) => Yaml::String(s.clone()),
+        Value::Integer(i) => Yaml::Integer(*i),
+        Value::Float(f) => Yaml::Real(format!("{:?}", f)),
+        Value::Boolean(b) => Yaml::Boolean(*b),
+        Value::Datetime(datetime) => Yaml::String(datetime.to_string()),
+        Value::Array(values) => Yaml::Array(values.iter().map(toml_to_yaml).collect()),
+        Value::Table(table) => {
+            let mut hash = Hash::new();
+            for (key, value) in table {
+                hash.insert(Yaml::String(key.clone()), toml_to_yaml(value));
+            }
+            Yaml::Hash(hash)
+        }
+    }
+}
+
+#[derive(Default)]
+struct YamlEvents(Vec<(Event, Marker)>);
+
+impl MarkedEventReceiver for YamlEvents {
+    fn on_event(&mut self, event: Event, marker: Marker) {
+        self.0.push((event, marker));
+    }
+}
+
+/// Reads a YAML document into a table, along with the offset of every field.
+fn read_yaml(text: &str) -> Result<(Table, HashMap<String, usize>), ParseError> {
+    let mut events = YamlEvents::default();
+    Parser::new_from_str(text)
+        .load(&mut events, false)
+        .map_err(|e| {
+            let offset = byte_offset(text, e.marker().index());
+            (e.info().to_string(), Some(offset..offset))
+        })?;
+
+    let mut reader = YamlReader {
+        text,
+        events: events.0.into_iter().peekable(),
+        fields: HashMap::new(),
+        anchors: HashMap::new(),
+    };
+    // skip the stream and document start
+    while matches!(reader.peek(), Some(Event::StreamStart | Event::DocumentStart)) {
+        reader.events.next();
+    }
+    if matches!(reader.peek(), None | Some(Event::StreamEnd)) {
+        return Ok((Table::new(), reader.fields));
+    }
+
+    match reader.read_node("")? {
+        Some(Value::Table(table)) => Ok((table, reader.fields)),
+        _ => Err(("The configuration must be a YAML mapping".to_string(), None)),
+    }
+}
+
+struct YamlReader<'a> {
+    text: &'a str,
+    events: std::iter::Peekable<std::vec::IntoIter<(Event, Marker)>>,
+    fields: HashMap<String, usize>,
+    anchors: HashMap<usize, Value>,
+}
+
+impl YamlReader<'_> {
+    fn peek(&mut self) -> Option<&Event> {
+        self.events.peek().map(|(event, _)| event)
+    }
+
+    fn error(&self, message: String, marker: Marker) -> ParseError {
+        let offset = byte_offset(self.text, marker.index());
+        (message, Some(offset..offset))
+    }
+
+    /// Reads the next node, returning `None` for a null.
+    fn read_node(&mut self, field: &str) -> Result<Option<Value>, ParseError> {
+        let Some((event, marker)) = self.events.next() else {
+            return Err(("Unexpected end of the YAML document".to_string(), None));
+        };
+
+        let (value, anchor) = match event {
+            Event::Scalar(scalar, style, anchor, tag) => {
+                let is_string = style != TScalarStyle::Plain || tag.is_some_and(|tag| tag.suffix == "str");
+                let value = if is_string {
+                    Some(Value::String(scalar))
+                } else {
+                    match Yaml::from_str(&scalar) {
+                        Yaml::Null => None,
+                        Yaml::Boolean(b) => Some(Value::Boolean(b)),
+                        Yaml::Integer(i) => Some(Value::Integer(i)),
+                        Yaml::Real(real) =>
+                            Some(real.parse().map(Value::Float).unwrap_or(Value::String(scalar))),
+                        _ => Some(Value::String(scalar)),
+                    }
+                };
+                (value, anchor)
+            }
+            Event::SequenceStart(anchor, _) => {
+                let mut array = Vec::new();
+                while !matches!(self.peek(), Some(Event::SequenceEnd) | None) {
+                    let item = format!("{}[{}]", field, array.len());
+                    let item_marker = self.events.peek().map(|(_, marker)| *marker).unwrap_or(marker);
+                    self.fields.insert(item.clone(), byte_offset(self.text, item_marker.index()));
+                    match self.read_node(&item)? {
+                        Some(value) => array.push(value),
+                        None => {
+                            return Err(self.error(format!("{}: null is not allowed in a list", item), item_marker));
+                        }
+                    }
+                }
+                self.events.next();
+                (Some(Value::Array(array)), anchor)
+            }
+            Event::MappingStart(anchor, _) => {
+                let mut table = Table::new();
+                while !matches!(self.peek(), Some(Event::MappingEnd) | None) {
+                    let Some((Event::Scalar(key, ..), key_marker)) = self.events.next() else {
+                        return Err(self.error("Mapping keys must be strings".to_string(), marker));
+                    };
+                    let key_field = join_field(field, &key);
+                    self.fields.insert(key_field.clone(), byte_offset(self.text, key_marker.index()));
+                    // a null field is treated as if it was left out
+                    if let Some(value) = self.read_node(&key_field)? {
+                        table.insert(key, value);
+                    }
+                }
+                self.events.next();
+                (Some(Value::Table(table)), anchor)
+            }
+            Event::Alias(anchor) => {
+                let value = self.anchors.get(&anchor).cloned();
+                return match value {
+                    Some(value) => Ok(Some(value)),
+                    None => Err(self.error("Unknown alias".to_string(), marker)),
+                };
+            }
+            _ => {
+                return Err(self.error("Unexpected YAML event".to_string(), marker));
+            }
+        };
+
+        if let (Some(value), true) = (&value, anchor > 0) {
+            self.anchors.insert(anchor, value.clone());
+        }
+        Ok(value)
+    }
+}
+
+/// The byte offset of the character at `index`.
+fn byte_offset(text: &str, index: usize) -> usize {
+    text.char_indices()
+        .nth(index)
+        .map_or(text.len(), |(offset, _)| offset)
+}
+
+/// The byte offset of a 1-based line and column.
+fn offset_of(text: &str, line: usize, column: usize) -> usize {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(line.saturating_sub(1))
+        .map(str::len)
+        .sum();
+    let column = text[line_start..]
+        .char_indices()
+        .nth(column.saturating_sub(1))
+        .map_or(0, |(offset, _)| offset);
+    (line_start + column).min(text.len())
+}
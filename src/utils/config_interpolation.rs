@@ -0,0 +1,163 @@
+use std::env;
+use std::fs;
+
+use serde_json::Value as JsonValue;
+use toml::{ Table, Value };
+
+use super::{ join_field, Configs };
+
+const REDACTED: &str = "<redacted>";
+
+/// The fields [`interpolate_table`] filled in and the ones it could not resolve.
+#[derive(Debug, Default)]
+pub struct Interpolation {
+    /// Fields that were given a non-empty value from the environment or from a file. They are
+    /// kept out of logs and the admin API, since that is where secrets are meant to come from.
+    pub fields: Vec<String>,
+    /// Fields that could not be resolved, with the reason.
+    pub errors: Vec<(String, String)>,
+}
+
+/// Replaces `${NAME}`, `${NAME:-default}` and `${file:/path}` in every string value of `table`.
+/// `$${` is written as a literal `${`.
+pub fn interpolate_table(table: &mut Table) -> Interpolation {
+    let mut interpolation = Interpolation::default();
+    for (key, value) in table.iter_mut() {
+        interpolate_value(value, key.clone(), &mut interpolation);
+    }
+    interpolation
+}
+
+fn interpolate_value(value: &mut Value, field: String, interpolation: &mut Interpolation) {
+    match value {
+        Value::String(text) if text.contains('$') => {
+            match interpolate(text) {
+                Ok(Some((resolved, filled_in))) => {
+                    if filled_in {
+                        interpolation.fields.push(field);
+                    }
+                    *text = resolved;
+                }
+                Ok(None) => {}
+                Err(message) => interpolation.errors.push((field, message)),
+            }
+        }
+        Value::Table(table) => {
+            for (key, value) in table.iter_mut() {
+                interpolate_value(value, format!("{}.{}", field, key), interpolation);
+            }
+        }
+        Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                interpolate_value(value, format!("{}[{}]", field, i), interpolation);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Resolves the placeholders in `text`, returning `None` when there were none. The flag tells
+/// whether any placeholder was filled in with a non-empty value, rather than only escapes.
+fn interpolate(text: &str) -> Result<Option<(String, bool)>, String> {
+    let mut resolved = String::with_capacity(text.len());
+    let mut rest = text;
+    let mut substituted = false;
+    let mut filled_in = false;
+
+    while let Some(start) = rest.find('$') {
+        resolved.push_str(&rest[..start]);
+        let placeholder = &rest[start..];
+
+        if let Some(after) = placeholder.strip_prefix("$${") {
+            resolved.push_str("${");
+            rest = after;
+            substituted = true;
+        } else if let Some(after) = placeholder.strip_prefix("${") {
+            let end = after.find('}').ok_or_else(|| format!("Unterminated placeholder in {:?}", text))?;
+            let value = resolve(&after[..end])?;
+            filled_in |= !value.is_empty();
+            resolved.push_str(&value);
+            rest = &after[end + 1..];
+            substituted = true;
+        } else {
+            resolved.push('$');
+            rest = &placeholder[1..];
+        }
+    }
+    resolved.push_str(rest);
+
+    Ok(substituted.then_some((resolved, filled_in)))
+}
+
+fn resolve(expression: &str) -> Result<String, String> {
+    if let Some(path) = expression.strip_prefix("file:") {
+        return fs
+            ::read_to_string(path)
+            .map(|content| content.trim_end_matches(['\r', '\n']).to_string())
+            .map_err(|e| format!("Failed to read ${{file:{}}}: {}", path, e));
+    }
+
+    let (name, default) = match expression.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (expression, None),
+    };
+    let is_valid_name =
+        name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_valid_name {
+        return Err(format!("Invalid variable name {:?} in ${{{}}}", name, expression));
+    }
+
+    match (env::var(name), default) {
+        (Ok(value), Some(default)) if value.is_empty() => Ok(default.to_string()),
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(default)) => Ok(default.to_string()),
+        (Err(_), None) => Err(format!("Environment variable {} is not set; use ${{{}:-default}} to give a default", name, name)),
+    }
+}
+
+impl Configs {
+    /// The configuration as JSON with secrets replaced by `<redacted>`: values filled in from
+    /// the environment or files, fields named like credentials, and passwords in URLs.
+    pub fn redacted(&self) -> JsonValue {
+        let mut value = serde_json::to_value(self).unwrap_or(JsonValue::Null);
+        redact(&mut value, "", false, &self.interpolated_fields);
+        value
+    }
+}
+
+fn redact(value: &mut JsonValue, field: &str, is_secret: bool, interpolated: &[String]) {
+    match value {
+        JsonValue::String(text) if is_secret || interpolated.iter().any(|known| known == field) => {
+            *text = REDACTED.to_string();
+        }
+        JsonValue::String(text) => {
+            if let Some(url) = redact_url_password(text) {
+                *text = url;
+            }
+        }
+        JsonValue::Object(fields) => {
+            for (key, value) in fields.iter_mut() {
+                redact(value, &join_field(field, key), is_secret_key(key), interpolated);
+            }
+        }
+        JsonValue::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                redact(value, &format!("{}[{}]", field, i), is_secret, interpolated);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// `url` with the password in its user info replaced, if it has one.
+pub fn redact_url_password(url: &str) -> Option<String> {
+    let (scheme, rest) = url.split_once("://")?;
+    let (user_info, host) = rest.split_once('@')?;
+    let (user, _) = user_info.split_once(':')?;
+    Some(format!("{}://{}:{}@{}", scheme, user, REDACTED, host))
+}
+
+fn is_secret_key(key: &str) -> bool {
+    ["token", "password", "secret"].iter().any(|secret| key == *secret || key.ends_with(&format!("_{}", secret)))
+}
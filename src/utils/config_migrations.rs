@@ -0,0 +1,185 @@
+use toml::{ Table, Value };
+use toml_edit::{ DocumentMut, Item };
+
+use super::ConfigDiagnostic;
+
+/// Version of the configuration format this build understands. Older files are upgraded when
+/// they are loaded.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// Top-level settings version 2 moved into `[upstream]`, as (old name, new name).
+const V1_UPSTREAM_FIELDS: [(&str, &str); 2] = [
+    ("connection_timeout", "connect_timeout"),
+    ("max_retries", "max_retries"),
+];
+
+/// Upgrades a merged configuration written for an older version to [`CONFIG_VERSION`],
+/// returning a deprecation warning for each field that was rewritten.
+pub fn migrate_config(table: &mut Table) -> Result<Vec<ConfigDiagnostic>, Vec<ConfigDiagnostic>> {
+    let version = file_version(table.get("version").map(|version| version.as_integer().ok_or(())))?;
+
+    let mut warnings = Vec::new();
+    if version < 2 {
+        migrate_v1(table, &mut warnings);
+    }
+
+    if !warnings.is_empty() && !table.contains_key("version") {
+        warnings.push(unversioned_warning());
+    }
+    table.insert("version".to_string(), Value::Integer(CONFIG_VERSION as i64));
+    Ok(warnings)
+}
+
+/// Upgrades a TOML configuration file to [`CONFIG_VERSION`], keeping its comments and layout,
+/// and returns the new text with a warning for each field that was rewritten. A file that is
+/// already at the current version comes back unchanged.
+pub fn migrate_config_text(text: &str) -> Result<(String, Vec<ConfigDiagnostic>), Vec<ConfigDiagnostic>> {
+    let mut document: DocumentMut = text
+        .parse()
+        .map_err(|e: toml_edit::TomlError| vec![ConfigDiagnostic::file_error(e.message().to_string())])?;
+    let version = file_version(document.get("version").map(|version| version.as_integer().ok_or(())))?;
+    if version == CONFIG_VERSION {
+        return Ok((text.to_string(), Vec::new()));
+    }
+
+    let mut warnings = Vec::new();
+    if version < 2 {
+        migrate_v1_document(&mut document, &mut warnings);
+    }
+
+    match document.get_mut("version") {
+        Some(item) => {
+            if let Some(value) = item.as_value_mut() {
+                let decor = value.decor().clone();
+                *value = toml_edit::Value::from(CONFIG_VERSION as i64);
+                *value.decor_mut() = decor;
+            }
+        }
+        None => {
+            document.insert("version", toml_edit::value(CONFIG_VERSION as i64));
+        }
+    }
+
+    Ok((document.to_string(), warnings))
+}
+
+/// The version a file is written for, from its `version` field: missing, an integer, or
+/// something else.
+fn file_version(version: Option<Result<i64, ()>>) -> Result<u32, Vec<ConfigDiagnostic>> {
+    match version {
+        None => Ok(1),
+        Some(Ok(version)) if (1..=CONFIG_VERSION as i64).contains(&version) => Ok(version as u32),
+        Some(Ok(version)) if version > CONFIG_VERSION as i64 => {
+            Err(
+                vec![
+                    ConfigDiagnostic::error(
+                        "version",
+                        format!("Version {} is newer than this sheldx supports ({})", version, CONFIG_VERSION)
+                    )
+                ]
+            )
+        }
+        Some(_) => Err(vec![ConfigDiagnostic::error("version", format!("Must be a version from 1 to {}", CONFIG_VERSION))]),
+    }
+}
+
+fn unversioned_warning() -> ConfigDiagnostic {
+    ConfigDiagnostic::warning(
+        "version",
+        format!("Not set, so the file was read as version 1; set version = {} once it is updated", CONFIG_VERSION)
+    )
+}
+
+fn moved_warning(old: &str, new: &str) -> ConfigDiagnostic {
+    ConfigDiagnostic::warning(old, format!("Deprecated, use upstream.{} instead", new))
+}
+
+/// The version 2 spelling of a version 1 rate-limit strategy, with the warning for rule `i`.
+fn renamed_strategy(i: usize, strategy: &str) -> Option<(&'static str, ConfigDiagnostic)> {
+    let renamed = match strategy {
+        "HashMap" | "hashmap" | "Local" => "local",
+        "Redis" => "redis",
+        _ => {
+            return None;
+        }
+    };
+    let warning = ConfigDiagnostic::warning(
+        format!("rate_limit_rules[{}].strategy", i),
+        format!("{:?} is deprecated, use {:?} instead", strategy, renamed)
+    );
+    Some((renamed, warning))
+}
+
+/// Version 2 moved the upstream connection settings into `[upstream]` and spelled rate-limit
+/// strategies in lowercase.
+fn migrate_v1(table: &mut Table, warnings: &mut Vec<ConfigDiagnostic>) {
+    for (old, new) in V1_UPSTREAM_FIELDS {
+        let Some(value) = table.remove(old) else {
+            continue;
+        };
+        let upstream = table
+            .entry("upstream")
+            .or_insert_with(|| Value::Table(Table::new()));
+        if let Value::Table(upstream) = upstream {
+            upstream.entry(new).or_insert(value);
+        }
+        warnings.push(moved_warning(old, new));
+    }
+
+    let Some(Value::Array(rules)) = table.get_mut("rate_limit_rules") else {
+        return;
+    };
+    for (i, rule) in rules.iter_mut().enumerate() {
+        let Some(Value::String(strategy)) = rule.get_mut("strategy") else {
+            continue;
+        };
+        if let Some((renamed, warning)) = renamed_strategy(i, strategy) {
+            warnings.push(warning);
+            *strategy = renamed.to_string();
+        }
+    }
+}
+
+/// [`migrate_v1`] for a TOML document, moving comments along with the fields they belong to.
+fn migrate_v1_document(document: &mut DocumentMut, warnings: &mut Vec<ConfigDiagnostic>) {
+    for (old, new) in V1_UPSTREAM_FIELDS {
+        let Some((old_key, item)) = document.remove_entry(old) else {
+            continue;
+        };
+        let upstream = document.entry("upstream").or_insert_with(toml_edit::table);
+        if let Some(upstream) = upstream.as_table_like_mut() {
+            if !upstream.contains_key(new) {
+                upstream.insert(new, item);
+                if let Some(mut key) = upstream.key_mut(new) {
+                    *key.leaf_decor_mut() = old_key.leaf_decor().clone();
+                }
+            }
+        }
+        warnings.push(moved_warning(old, new));
+    }
+
+    let strategies: Vec<(usize, &mut toml_edit::Value)> = match document.get_mut("rate_limit_rules") {
+        Some(Item::ArrayOfTables(rules)) =>
+            rules
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, rule)| Some((i, rule.get_mut("strategy")?.as_value_mut()?)))
+                .collect(),
+        Some(Item::Value(toml_edit::Value::Array(rules))) =>
+            rules
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(i, rule)| Some((i, rule.as_inline_table_mut()?.get_mut("strategy")?)))
+                .collect(),
+        _ => Vec::new(),
+    };
+    for (i, value) in strategies {
+        let Some((renamed, warning)) = value.as_str().and_then(|strategy| renamed_strategy(i, strategy)) else {
+            continue;
+        };
+        let decor = value.decor().clone();
+        *value = toml_edit::Value::from(renamed);
+        *value.decor_mut() = decor;
+        warnings.push(warning);
+    }
+}
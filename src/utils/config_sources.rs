@@ -0,0 +1,312 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{ Path, PathBuf };
+
+use toml::{ Table, Value };
+
+use super::{ interpolate_table, main_config_format, parse_config_text, ConfigDiagnostic, ConfigFormat, Positions };
+
+/// A configuration file that was read, with the positions of its fields.
+pub struct ConfigSource {
+    pub path: PathBuf,
+    text: String,
+    positions: Positions,
+    /// Fields filled in from the environment or from files, as written in this file.
+    interpolated: Vec<String>,
+}
+
+/// The main configuration file merged with its includes.
+///
+/// Files are merged in a fixed order: the main file first, then the matches of each `include`
+/// pattern in the order the patterns are listed, each pattern's matches sorted by path. Arrays
+/// are appended in that order and tables are merged key by key. A key with a plain value may
+/// only be set by one file, unless every file sets it to the same value.
+pub struct MergedConfig {
+    pub table: Table,
+    pub sources: Vec<ConfigSource>,
+    /// Where merged fields came from, as (merged path, source index, path in that source).
+    origins: Vec<(String, usize, String)>,
+}
+
+impl MergedConfig {
+    /// Finds the file, line and column `field` was written at.
+    pub fn locate(&self, mut diagnostic: ConfigDiagnostic) -> ConfigDiagnostic {
+        if let Some(related) = diagnostic.related.take() {
+            diagnostic.message = format!("{} (see {})", diagnostic.message, self.describe(&related));
+        }
+        match diagnostic.field.clone() {
+            Some(field) => {
+                let (source, local_field, span) = self.position(&field);
+                // name the field as it is written in its own file
+                diagnostic.field = Some(local_field);
+                source.place(diagnostic, span)
+            }
+            None => diagnostic,
+        }
+    }
+
+    /// The merged paths of the fields that were filled in from the environment or from files.
+    pub fn interpolated_fields(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .enumerate()
+            .flat_map(|(index, source)| source.interpolated.iter().map(move |local| (index, local)))
+            .filter_map(|(index, local)| {
+                self.origins
+                    .iter()
+                    .filter(|(_, source, origin)| *source == index && is_field_prefix(origin, local))
+                    .max_by_key(|(_, _, origin)| origin.len())
+                    .map(|(merged, _, origin)| format!("{}{}", merged, &local[origin.len()..]))
+            })
+            .collect()
+    }
+
+    /// Where `field` was written, as `file:line:column`.
+    fn describe(&self, field: &str) -> String {
+        let (source, _, span) = self.position(field);
+        match span {
+            Some(span) => {
+                let (line, column) = line_column(&source.text, span.start);
+                format!("{}:{}:{}", source.path.display(), line, column)
+            }
+            None => source.path.display().to_string(),
+        }
+    }
+
+    fn position(&self, field: &str) -> (&ConfigSource, String, Option<Range<usize>>) {
+        let origin = self.origins
+            .iter()
+            .filter(|(merged, _, _)| is_field_prefix(merged, field))
+            .max_by_key(|(merged, _, _)| merged.len());
+        match origin {
+            Some((merged, source, local)) => {
+                let source = &self.sources[*source];
+                let local_field = format!("{}{}", local, &field[merged.len()..]);
+                let span = source.positions.span(&local_field);
+                (source, local_field, span)
+            }
+            None => (&self.sources[0], field.to_string(), None),
+        }
+    }
+}
+
+impl ConfigSource {
+    /// Fills in the file, line and column of `diagnostic` from `span`.
+    pub fn place(&self, diagnostic: ConfigDiagnostic, span: Option<Range<usize>>) -> ConfigDiagnostic {
+        place(&self.path, &self.text, diagnostic, span)
+    }
+}
+
+fn place(path: &Path, text: &str, mut diagnostic: ConfigDiagnostic, span: Option<Range<usize>>) -> ConfigDiagnostic {
+    diagnostic.file = Some(path.to_path_buf());
+    if let Some(span) = span {
+        let (line, column) = line_column(text, span.start);
+        diagnostic.line = Some(line);
+        diagnostic.column = Some(column);
+    }
+    diagnostic
+}
+
+/// Reads the configuration file at `path` and every file it includes, and merges them. Each
+/// file is read in the format named by its extension.
+pub fn read_config_sources(path: &Path) -> Result<MergedConfig, Vec<ConfigDiagnostic>> {
+    let (main, table) = read_source(path, main_config_format(path))?;
+    let includes = include_patterns(&main, &table)?;
+
+    let mut merged = MergedConfig {
+        origins: table
+            .keys()
+            .map(|key| (key.clone(), 0, key.clone()))
+            .collect(),
+        table,
+        sources: vec![main],
+    };
+
+    let base = path.parent().unwrap_or(Path::new("."));
+    let mut diagnostics = Vec::new();
+    for (pattern, field) in includes {
+        let files = match expand_include(base, &pattern) {
+            Ok(files) => files,
+            Err(message) => {
+                let (source, _, span) = merged.position(&field);
+                diagnostics.push(source.place(ConfigDiagnostic::error(field, message), span));
+                continue;
+            }
+        };
+
+        for file in files {
+            if merged.sources.iter().any(|source| source.path == file) {
+                continue;
+            }
+            let (source, table) = match read_source(&file, ConfigFormat::from_path(&file)) {
+                Ok(source) => source,
+                Err(errors) => {
+                    diagnostics.extend(errors);
+                    continue;
+                }
+            };
+            if table.contains_key("include") {
+                let span = source.positions.span("include");
+                let diagnostic = ConfigDiagnostic::error(
+                    "include",
+                    "Only the main configuration file may include other files"
+                );
+                diagnostics.push(source.place(diagnostic, span));
+                continue;
+            }
+
+            let index = merged.sources.len();
+            merged.sources.push(source);
+            let mut conflicts = Vec::new();
+            merge_table(&mut merged.table, table, "", index, "", &mut merged.origins, &mut conflicts);
+            for (local, previous) in conflicts {
+                let source = &merged.sources[index];
+                let span = source.positions.span(&local);
+                let message = format!("Already set to a different value in {}", merged.describe(&previous));
+                diagnostics.push(source.place(ConfigDiagnostic::error(local, message), span));
+            }
+        }
+    }
+
+    if diagnostics.is_empty() {
+        Ok(merged)
+    } else {
+        Err(diagnostics)
+    }
+}
+
+/// Reads a single configuration file as written, without resolving `include` or `${...}`.
+pub fn read_config_file(path: &Path, format: ConfigFormat) -> Result<Table, Vec<ConfigDiagnostic>> {
+    parse_source(path, format).map(|(_, table)| table)
+}
+
+fn read_source(path: &Path, format: ConfigFormat) -> Result<(ConfigSource, Table), Vec<ConfigDiagnostic>> {
+    let (mut source, mut table) = parse_source(path, format)?;
+
+    let interpolation = interpolate_table(&mut table);
+    source.interpolated = interpolation.fields;
+    let unresolved: Vec<ConfigDiagnostic> = interpolation.errors
+        .into_iter()
+        .map(|(field, message)| {
+            let span = source.positions.span(&field);
+            source.place(ConfigDiagnostic::error(field, message), span)
+        })
+        .collect();
+    if !unresolved.is_empty() {
+        return Err(unresolved);
+    }
+
+    Ok((source, table))
+}
+
+fn parse_source(path: &Path, format: ConfigFormat) -> Result<(ConfigSource, Table), Vec<ConfigDiagnostic>> {
+    let text = fs::read_to_string(path).map_err(|e| {
+        let message = format!("Failed to read the configuration file: {}", e);
+        vec![place(path, "", ConfigDiagnostic::file_error(message), None)]
+    })?;
+    let (table, positions) = parse_config_text(&text, format).map_err(|(message, span)| {
+        vec![place(path, &text, ConfigDiagnostic::file_error(message), span)]
+    })?;
+
+    Ok((ConfigSource { path: path.to_path_buf(), text, positions, interpolated: Vec::new() }, table))
+}
+
+/// The `include` patterns of the main file, with the field each one was written at.
+fn include_patterns(main: &ConfigSource, table: &Table) -> Result<Vec<(String, String)>, Vec<ConfigDiagnostic>> {
+    let invalid = |field: &str| {
+        let span = main.positions.span(field);
+        vec![main.place(ConfigDiagnostic::error(field, "include must be a list of file paths or glob patterns"), span)]
+    };
+
+    match table.get("include") {
+        None => Ok(Vec::new()),
+        Some(Value::Array(patterns)) =>
+            patterns
+                .iter()
+                .enumerate()
+                .map(|(i, pattern)| {
+                    let field = format!("include[{}]", i);
+                    match pattern {
+                        Value::String(pattern) => Ok((pattern.clone(), field)),
+                        _ => Err(invalid(&field)),
+                    }
+                })
+                .collect(),
+        Some(_) => Err(invalid("include")),
+    }
+}
+
+/// Resolves an include pattern relative to the directory of the main file. Patterns without
+/// wildcards must name an existing file; patterns with wildcards may match nothing.
+fn expand_include(base: &Path, pattern: &str) -> Result<Vec<PathBuf>, String> {
+    let pattern_path = base.join(pattern);
+    let pattern_str = pattern_path.to_string_lossy();
+
+    if !pattern.contains(['*', '?', '[']) {
+        return if pattern_path.is_file() {
+            Ok(vec![pattern_path])
+        } else {
+            Err(format!("Included file {:?} not found", pattern_str))
+        };
+    }
+
+    let mut files: Vec<PathBuf> = glob::glob(&pattern_str)
+        .map_err(|e| format!("Invalid include pattern {:?}: {}", pattern, e))?
+        .filter_map(Result::ok)
+        .filter(|path| path.is_file())
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Merges `source` into `target`, recording where each new field came from. Keys set to
+/// different plain values are reported in `conflicts` as (path in the source, merged path of
+/// the earlier value).
+fn merge_table(
+    target: &mut Table,
+    source: Table,
+    merged_prefix: &str,
+    index: usize,
+    local_prefix: &str,
+    origins: &mut Vec<(String, usize, String)>,
+    conflicts: &mut Vec<(String, String)>
+) {
+    for (key, value) in source {
+        let merged = join_field(merged_prefix, &key);
+        let local = join_field(local_prefix, &key);
+
+        match (target.get_mut(&key), value) {
+            (None, value) => {
+                origins.push((merged, index, local));
+                target.insert(key, value);
+            }
+            (Some(Value::Table(existing)), Value::Table(value)) => {
+                merge_table(existing, value, &merged, index, &local, origins, conflicts);
+            }
+            (Some(Value::Array(existing)), Value::Array(values)) => {
+                for (i, value) in values.into_iter().enumerate() {
+                    origins.push((format!("{}[{}]", merged, existing.len()), index, format!("{}[{}]", local, i)));
+                    existing.push(value);
+                }
+            }
+            (Some(existing), value) if *existing == value => {}
+            (Some(_), _) => conflicts.push((local, merged)),
+        }
+    }
+}
+
+pub(super) fn join_field(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) }
+}
+
+/// Whether `field` is `prefix` or one of the fields under it.
+fn is_field_prefix(prefix: &str, field: &str) -> bool {
+    field.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+}
+
+fn line_column(text: &str, offset: usize) -> (usize, usize) {
+    let before = &text[..offset.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or_default().chars().count() + 1;
+    (line, column)
+}
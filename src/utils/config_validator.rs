@@ -0,0 +1,502 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{ Path, PathBuf };
+use std::time::Duration;
+
+use redis::IntoConnectionInfo;
+use serde::Serialize;
+use toml_edit::{ Item, Table, TableLike, Value };
+
+use super::{
+    migrate_config,
+    parse_ip_range,
+    rate_limit_rule_ids,
+    read_config_sources,
+    Configs,
+    MergedConfig,
+    RateLimitKey,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    /// Problems that don't stop the proxy from running, such as keys it ignores.
+    Warning,
+}
+
+/// A problem found in the configuration, located as precisely as possible.
+#[derive(Debug, Clone, Serialize)]
+pub struct ConfigDiagnostic {
+    pub severity: Severity,
+    pub file: Option<PathBuf>,
+    /// 1-based line and column of the offending key or value.
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+    /// Path of the offending field, e.g. `rate_limit_rules[1].max_tokens`.
+    pub field: Option<String>,
+    pub message: String,
+    /// Another field the problem involves, such as the earlier of two duplicate rules.
+    #[serde(skip)]
+    pub related: Option<String>,
+}
+
+impl ConfigDiagnostic {
+    pub fn error(field: impl Into<String>, message: impl Into<String>) -> ConfigDiagnostic {
+        ConfigDiagnostic {
+            severity: Severity::Error,
+            file: None,
+            line: None,
+            column: None,
+            field: Some(field.into()),
+            message: message.into(),
+            related: None,
+        }
+    }
+
+    /// A problem with the file as a whole rather than with one field.
+    pub fn file_error(message: impl Into<String>) -> ConfigDiagnostic {
+        ConfigDiagnostic { field: None, ..ConfigDiagnostic::error("", message) }
+    }
+
+    pub fn warning(field: impl Into<String>, message: impl Into<String>) -> ConfigDiagnostic {
+        ConfigDiagnostic { severity: Severity::Warning, ..ConfigDiagnostic::error(field, message) }
+    }
+}
+
+impl fmt::Display for ConfigDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}", file.display())?;
+            if let (Some(line), Some(column)) = (self.line, self.column) {
+                write!(f, ":{}:{}", line, column)?;
+            }
+            write!(f, ": ")?;
+        }
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: ", severity)?;
+        if let Some(field) = &self.field {
+            write!(f, "{}: ", field)?;
+        }
+        write!(f, "{}", self.message)
+    }
+}
+
+/// The result of validating a configuration file.
+#[derive(Debug)]
+pub struct ConfigReport {
+    /// The parsed configuration, when the file could be read and parsed at all.
+    pub configs: Option<Configs>,
+    pub diagnostics: Vec<ConfigDiagnostic>,
+}
+
+impl ConfigReport {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diagnostic| diagnostic.severity == Severity::Error)
+    }
+}
+
+/// Reads, parses and checks the configuration file at `path` and its includes, reporting every
+/// problem found instead of stopping at the first one.
+pub fn validate_config_file(path: &Path) -> ConfigReport {
+    let merged = match read_config_sources(path) {
+        Ok(merged) => merged,
+        Err(diagnostics) => {
+            return ConfigReport { configs: None, diagnostics };
+        }
+    };
+
+    let (configs, mut diagnostics) = match parse_configs(&merged) {
+        Ok(parsed) => parsed,
+        Err(diagnostics) => {
+            return ConfigReport { configs: None, diagnostics };
+        }
+    };
+
+    diagnostics.extend(configs.validate().into_iter().map(|diagnostic| merged.locate(diagnostic)));
+    ConfigReport { configs: Some(configs), diagnostics }
+}
+
+/// Turns merged configuration files into [`Configs`], upgrading older versions first, along
+/// with warnings for deprecated fields and for the keys that were ignored.
+pub fn parse_configs(merged: &MergedConfig) -> Result<(Configs, Vec<ConfigDiagnostic>), Vec<ConfigDiagnostic>> {
+    let mut table = merged.table.clone();
+    let mut diagnostics: Vec<ConfigDiagnostic> = migrate_config(&mut table)
+        .map_err(|errors| {
+            errors
+                .into_iter()
+                .map(|diagnostic| merged.locate(diagnostic))
+                .collect::<Vec<_>>()
+        })?
+        .into_iter()
+        .map(|diagnostic| merged.locate(diagnostic))
+        .collect();
+
+    let mut unknown_keys = Vec::new();
+    let parsed: Result<Configs, _> = serde_path_to_error::deserialize(
+        serde_ignored::Deserializer::new(toml::Value::Table(table), &mut |path| {
+            unknown_keys.push(ignored_path(&path))
+        })
+    );
+
+    diagnostics.extend(
+        unknown_keys
+            .into_iter()
+            .map(|field| merged.locate(ConfigDiagnostic::warning(field, "Unknown key, it is ignored")))
+    );
+
+    match parsed {
+        Ok(mut configs) => {
+            configs.interpolated_fields = merged.interpolated_fields();
+            Ok((configs, diagnostics))
+        }
+        Err(e) => {
+            let field = e.path().to_string();
+            let message = e.into_inner().message().to_string();
+            let diagnostic = match field.as_str() {
+                "." => merged.locate(ConfigDiagnostic::file_error(message)),
+                _ => merged.locate(ConfigDiagnostic::error(field, message)),
+            };
+            diagnostics.push(diagnostic);
+            Err(diagnostics)
+        }
+    }
+}
+
+impl Configs {
+    /// Checks the values that parse fine but can't work, such as unreachable rules or
+    /// rate limits the limiter would reject.
+    pub fn validate(&self) -> Vec<ConfigDiagnostic> {
+        let mut diagnostics = Vec::new();
+
+        if self.is_tls_enabled {
+            for (field, path) in [("cert_path", &self.cert_path), ("key_path", &self.key_path)] {
+                if path.is_empty() {
+                    diagnostics.push(ConfigDiagnostic::error(field, "TLS is enabled but no file is given"));
+                } else if !Path::new(path).is_file() {
+                    diagnostics.push(
+                        ConfigDiagnostic::error(
+                            field,
+                            format!(
+                                "File {:?} not found. Please see how to set up a TLS certificate at https://docs.sheldx.io/docs/setup-tls-certificate",
+                                path
+                            )
+                        )
+                    );
+                }
+            }
+        }
+
+        let forwarding_rules = self.forwarding_rules.as_deref().unwrap_or_default();
+        let mut hosts = HashMap::new();
+        for (i, rule) in forwarding_rules.iter().enumerate() {
+            if rule.host.is_empty() {
+                diagnostics.push(ConfigDiagnostic::error(format!("forwarding_rules[{}].host", i), "Host must not be empty"));
+            } else if let Some(first) = hosts.get(&normalize_host(&rule.host)) {
+                diagnostics.push(ConfigDiagnostic {
+                    related: Some(format!("forwarding_rules[{}].host", first)),
+                    ..ConfigDiagnostic::error(
+                        format!("forwarding_rules[{}].host", i),
+                        format!("{:?} is already matched by an earlier forwarding rule, so this rule is never used", rule.host)
+                    )
+                });
+            } else {
+                hosts.insert(normalize_host(&rule.host), i);
+            }
+
+            if let Err(message) = check_target(&rule.target) {
+                diagnostics.push(ConfigDiagnostic::error(format!("forwarding_rules[{}].target", i), message));
+            }
+            if let Some(concurrency) = &rule.concurrency {
+                if concurrency.max_in_flight == 0 {
+                    diagnostics.push(
+                        ConfigDiagnostic::error(format!("forwarding_rules[{}].concurrency.max_in_flight", i), "Must be at least 1")
+                    );
+                }
+                if concurrency.queue_size > 0 && concurrency.queue_timeout_ms == 0 {
+                    diagnostics.push(
+                        ConfigDiagnostic::error(
+                            format!("forwarding_rules[{}].concurrency.queue_timeout_ms", i),
+                            "Must be at least 1 millisecond when requests are queued"
+                        )
+                    );
+                }
+            }
+        }
+
+        // rules for the same host are stacked, but each needs its own ID to keep its limiters apart
+        let rate_limit_rules = self.rate_limit_rules.as_deref().unwrap_or_default();
+        let mut ids = HashMap::new();
+        for (i, (rule, id)) in rate_limit_rules.iter().zip(rate_limit_rule_ids(rate_limit_rules)).enumerate() {
+            let field = |name: &str| format!("rate_limit_rules[{}].{}", i, name);
+
+            if let Some(first) = ids.get(&id) {
+                diagnostics.push(ConfigDiagnostic {
+                    related: Some(format!("rate_limit_rules[{}]", first)),
+                    ..ConfigDiagnostic::error(
+                        field(if rule.name.is_some() { "name" } else { "host" }),
+                        format!("Rule ID {:?} is already used by an earlier rate-limit rule; give one of them a different name", id)
+                    )
+                });
+            } else {
+                ids.insert(id, i);
+            }
+
+            if rule.key.is_empty() {
+                diagnostics.push(ConfigDiagnostic::error(field("key"), "Must list at least one key"));
+            } else if rule.key.len() > 1 && rule.key.contains(&RateLimitKey::Global) {
+                diagnostics.push(
+                    ConfigDiagnostic::error(field("key"), "global is a single key for every client and can't be combined with others")
+                );
+            }
+
+            if rule.limit == 0 {
+                diagnostics.push(ConfigDiagnostic::error(field("limit"), "Limit must be at least 1"));
+            }
+            if rule.duration == 0 {
+                diagnostics.push(ConfigDiagnostic::error(field("duration"), "Duration must be at least 1 second"));
+            } else if Duration::from_secs(rule.duration).as_nanos() > (u64::MAX as u128) {
+                diagnostics.push(ConfigDiagnostic::error(field("duration"), "Duration is too long"));
+            }
+            if rule.max_tokens < rule.limit {
+                diagnostics.push(
+                    ConfigDiagnostic::error(
+                        field("max_tokens"),
+                        format!("max_tokens ({}) must be at least limit ({})", rule.max_tokens, rule.limit)
+                    )
+                );
+            }
+
+            for (j, entry) in rule.excluded_ip_list.iter().enumerate() {
+                if parse_ip_range(entry).is_none() {
+                    diagnostics.push(
+                        ConfigDiagnostic::error(
+                            format!("rate_limit_rules[{}].excluded_ip_list[{}]", i, j),
+                            format!("{:?} is not an IP address or CIDR range", entry)
+                        )
+                    );
+                }
+            }
+        }
+
+        let ip_access_rules = self.ip_access_rules.as_deref().unwrap_or_default();
+        let mut hosts = HashMap::new();
+        for (i, rule) in ip_access_rules.iter().enumerate() {
+            if let Some(first) = hosts.get(&normalize_host(&rule.host)) {
+                diagnostics.push(ConfigDiagnostic {
+                    related: Some(format!("ip_access_rules[{}].host", first)),
+                    ..ConfigDiagnostic::error(
+                        format!("ip_access_rules[{}].host", i),
+                        format!("{:?} is already matched by an earlier IP access rule, so this rule is never used", rule.host)
+                    )
+                });
+            } else {
+                hosts.insert(normalize_host(&rule.host), i);
+            }
+
+            for (list, entries) in [("allow", &rule.allow), ("deny", &rule.deny)] {
+                for (j, entry) in entries.iter().enumerate() {
+                    if parse_ip_range(entry).is_none() {
+                        diagnostics.push(
+                            ConfigDiagnostic::error(
+                                format!("ip_access_rules[{}].{}[{}]", i, list, j),
+                                format!("{:?} is not an IP address or CIDR range", entry)
+                            )
+                        );
+                    }
+                }
+            }
+        }
+
+        for (i, entry) in self.trusted_proxies.as_deref().unwrap_or_default().iter().enumerate() {
+            if parse_ip_range(entry).is_none() {
+                diagnostics.push(
+                    ConfigDiagnostic::error(
+                        format!("trusted_proxies[{}]", i),
+                        format!("{:?} is not an IP address or CIDR range", entry)
+                    )
+                );
+            }
+        }
+
+        if let Some(metrics) = self.metrics.as_ref().filter(|metrics| metrics.enabled) {
+            if metrics.listen.parse::<SocketAddr>().is_err() {
+                diagnostics.push(
+                    ConfigDiagnostic::error("metrics.listen", format!("{:?} is not an IP:port address", metrics.listen))
+                );
+            }
+        }
+
+        if let Some(redis) = &self.redis {
+            if let Err(e) = redis.url.as_str().into_connection_info() {
+                diagnostics.push(ConfigDiagnostic::error("redis.url", format!("Invalid Redis URL: {}", e)));
+            }
+            if redis.timeout_ms == 0 {
+                diagnostics.push(ConfigDiagnostic::error("redis.timeout_ms", "Must be at least 1 millisecond"));
+            }
+        }
+
+        if let Some(upstream) = &self.upstream {
+            if upstream.connect_timeout == 0 {
+                diagnostics.push(ConfigDiagnostic::error("upstream.connect_timeout", "Must be at least 1 second"));
+            }
+            if upstream.max_retries == 0 {
+                diagnostics.push(ConfigDiagnostic::error("upstream.max_retries", "Must be at least 1"));
+            }
+        }
+
+        if let Some(rate_limiter) = &self.rate_limiter {
+            if rate_limiter.max_entries == 0 {
+                diagnostics.push(ConfigDiagnostic::error("rate_limiter.max_entries", "Must be at least 1"));
+            }
+            if rate_limiter.idle_timeout == 0 {
+                diagnostics.push(ConfigDiagnostic::error("rate_limiter.idle_timeout", "Must be at least 1 second"));
+            }
+        }
+
+        if let Some(connection_limits) = &self.connection_limits {
+            if connection_limits.max_connections == Some(0) {
+                diagnostics.push(ConfigDiagnostic::error("connection_limits.max_connections", "Must be at least 1"));
+            }
+            if connection_limits.max_connections_per_ip == Some(0) {
+                diagnostics.push(ConfigDiagnostic::error("connection_limits.max_connections_per_ip", "Must be at least 1"));
+            }
+        }
+
+        if let Some(request_limits) = &self.request_limits {
+            if request_limits.header_read_timeout == 0 {
+                diagnostics.push(ConfigDiagnostic::error("request_limits.header_read_timeout", "Must be at least 1 second"));
+            }
+            if request_limits.keep_alive_timeout == 0 {
+                diagnostics.push(ConfigDiagnostic::error("request_limits.keep_alive_timeout", "Must be at least 1 second"));
+            }
+            if request_limits.max_header_size < 8192 {
+                diagnostics.push(ConfigDiagnostic::error("request_limits.max_header_size", "Must be at least 8192 bytes"));
+            }
+            if request_limits.max_headers == 0 {
+                diagnostics.push(ConfigDiagnostic::error("request_limits.max_headers", "Must be at least 1"));
+            }
+            if request_limits.request_timeout == Some(0) {
+                diagnostics.push(ConfigDiagnostic::error("request_limits.request_timeout", "Must be at least 1 second"));
+            }
+        }
+
+        if let Some(admin) = self.admin.as_ref().filter(|admin| admin.enabled) {
+            if admin.token.is_empty() {
+                diagnostics.push(ConfigDiagnostic::error("admin.token", "The admin API requires a non-empty token"));
+            }
+            if admin.socket.is_none() && admin.listen.parse::<SocketAddr>().is_err() {
+                diagnostics.push(
+                    ConfigDiagnostic::error("admin.listen", format!("{:?} is not an IP:port address", admin.listen))
+                );
+            }
+        }
+
+        diagnostics
+    }
+}
+
+/// Host rules are matched against the Host header, which is case-insensitive.
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Checks that `target` is something `TcpStream::connect` accepts: `ip:port` or `hostname:port`.
+fn check_target(target: &str) -> Result<(), String> {
+    if target.parse::<SocketAddr>().is_ok() {
+        return Ok(());
+    }
+
+    let invalid = || format!("{:?} is not a host:port socket address, e.g. 127.0.0.1:3000", target);
+    let (host, port) = target.rsplit_once(':').ok_or_else(invalid)?;
+    let is_hostname =
+        !host.is_empty() &&
+        host.split('.').all(|label| {
+            !label.is_empty() &&
+                !label.starts_with('-') &&
+                !label.ends_with('-') &&
+                label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        });
+    match port.parse::<u16>() {
+        Ok(port) if port != 0 && is_hostname => Ok(()),
+        _ => Err(invalid()),
+    }
+}
+
+/// Formats a path reported by `serde_ignored` like the field paths used elsewhere.
+fn ignored_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => format!("{}[{}]", ignored_path(parent), index),
+        serde_ignored::Path::Map { parent, key } => {
+            let parent = ignored_path(parent);
+            if parent.is_empty() { key.clone() } else { format!("{}.{}", parent, key) }
+        }
+        | serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => ignored_path(parent),
+    }
+}
+
+enum Segment<'a> {
+    Key(&'a str),
+    Index(usize),
+}
+
+fn segments(field: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    for part in field.split('.') {
+        let (key, indexes) = part.split_once('[').map_or((part, ""), |(key, rest)| (key, rest));
+        if !key.is_empty() {
+            segments.push(Segment::Key(key));
+        }
+        segments.extend(
+            indexes
+                .split('[')
+                .filter_map(|index| index.trim_end_matches(']').parse().ok())
+                .map(Segment::Index)
+        );
+    }
+    segments
+}
+
+/// Finds where `field` is written, falling back to its closest written parent.
+pub(super) fn span_of(root: &Table, field: &str) -> Option<Range<usize>> {
+    span_in_table(root, &segments(field))
+}
+
+fn span_in_table(table: &dyn TableLike, segments: &[Segment]) -> Option<Range<usize>> {
+    let Some((Segment::Key(key), rest)) = segments.split_first() else {
+        return None;
+    };
+    let (key, item) = table.get_key_value(key)?;
+    span_in_item(item, rest).or_else(|| item.span()).or_else(|| key.span())
+}
+
+fn span_in_item(item: &Item, segments: &[Segment]) -> Option<Range<usize>> {
+    match (item, segments.first()?) {
+        (Item::Table(table), Segment::Key(_)) => span_in_table(table, segments),
+        (Item::ArrayOfTables(tables), Segment::Index(index)) => {
+            let table = tables.get(*index)?;
+            span_in_table(table, &segments[1..]).or_else(|| table.span())
+        }
+        (Item::Value(value), _) => span_in_value(value, segments),
+        _ => None,
+    }
+}
+
+fn span_in_value(value: &Value, segments: &[Segment]) -> Option<Range<usize>> {
+    match (value, segments.first()?) {
+        (Value::InlineTable(table), Segment::Key(_)) => span_in_table(table, segments),
+        (Value::Array(array), Segment::Index(index)) => {
+            let value = array.get(*index)?;
+            span_in_value(value, &segments[1..]).or_else(|| value.span())
+        }
+        _ => None,
+    }
+}
@@ -1,7 +1,29 @@
+use schemars::JsonSchema;
 use serde::{ Deserialize, Serialize };
 use thiserror::Error;
+use std::collections::HashMap;
 use std::fs;
 use std::path::PathBuf;
+use std::sync::{ Arc, OnceLock, RwLock };
+
+use super::{
+    main_config_format,
+    parse_configs,
+    read_config_sources,
+    write_config_text,
+    ConfigDiagnostic,
+    ConfigFormat,
+    Severity,
+    CONFIG_VERSION,
+};
+
+/// Configuration file used when no other path is given.
+pub const DEFAULT_CONFIG_PATH: &str = "/etc/sheldx/configs/main.conf";
+
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// The configuration requests are served with, replaced on reload.
+static CURRENT_CONFIGS: RwLock<Option<Arc<Configs>>> = RwLock::new(None);
 
 #[derive(Error, Debug)]
 pub enum ConfigError {
@@ -11,22 +33,580 @@ pub enum ConfigError {
         "Configuration file parse error this may be due to invalid TOML syntax or invalid configuration"
     )]
     ConfigFileParseError,
+    #[error("Configuration file {0:?} not found")]
+    ConfigFileNotFound(PathBuf),
+}
+
+/// Header changes applied to a message passing through a forwarding rule.
+///
+/// Values may reference `{{client_ip}}`, `{{host}}`, `{{scheme}}`, `{{request_id}}`,
+/// `{{tls_version}}`, `{{tls_cipher}}` and `{{tls_sni}}`; unknown variables are left untouched.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema, Default)]
+pub struct HeaderRules {
+    /// Headers appended next to any existing values.
+    #[serde(default)]
+    pub add: HashMap<String, String>,
+    /// Headers that replace any existing values.
+    #[serde(default)]
+    pub set: HashMap<String, String>,
+    /// Headers removed before `add` and `set` are applied.
+    #[serde(default)]
+    pub remove: Vec<String>,
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
 pub struct ForwardingRule {
     pub host: String,
     pub target: String,
+    /// Changes applied to the request before it is sent upstream.
+    pub request_headers: Option<HeaderRules>,
+    /// Changes applied to the upstream response before it is returned to the client.
+    pub response_headers: Option<HeaderRules>,
+    /// Caps on the requests sent to the target at the same time.
+    pub concurrency: Option<ConcurrencyConfig>,
+}
+
+/// How many requests a forwarding rule's target handles at once, and what happens to the rest.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct ConcurrencyConfig {
+    /// Most requests in flight to the target at once.
+    pub max_in_flight: usize,
+    /// Requests that may wait for a free slot once the target is at `max_in_flight`. Further
+    /// requests get a 503 straight away.
+    #[serde(default)]
+    pub queue_size: usize,
+    /// Milliseconds a queued request waits for a slot before getting a 503.
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    1000
+}
+
+/// Which client IPs may use a host. The most specific matching entry of `allow` and `deny`
+/// decides; clients matching neither are let in only when `allow` is empty.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct IpAccessRule {
+    /// Host the rule applies to, or `*` for hosts without a rule of their own.
+    pub host: String,
+    /// IPs or CIDR ranges let in.
+    #[serde(default)]
+    pub allow: Vec<String>,
+    /// IPs or CIDR ranges turned away with a 403.
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+/// How request IDs are picked and propagated.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct RequestIdConfig {
+    /// Header carrying the ID to the upstream and back to the client.
+    #[serde(default = "default_request_id_header")]
+    pub header: String,
+    /// Reuse the ID sent by the client instead of generating a new one.
+    #[serde(default)]
+    pub trust_incoming: bool,
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> Self {
+        RequestIdConfig {
+            header: default_request_id_header(),
+            trust_incoming: false,
+        }
+    }
+}
+
+fn default_request_id_header() -> String {
+    "x-request-id".to_string()
+}
+
+/// Line format of an access log: `common`, `combined`, `json`, or a custom template using
+/// `{{field}}` placeholders such as `{{client_ip}}`, `{{status}}` or `{{total_latency_ms}}`.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema, Default, PartialEq)]
+#[serde(from = "String", into = "String")]
+pub enum AccessLogFormat {
+    Common,
+    #[default]
+    Combined,
+    Json,
+    Custom(String),
+}
+
+impl From<String> for AccessLogFormat {
+    fn from(format: String) -> Self {
+        match format.as_str() {
+            "common" => AccessLogFormat::Common,
+            "combined" => AccessLogFormat::Combined,
+            "json" => AccessLogFormat::Json,
+            _ => AccessLogFormat::Custom(format),
+        }
+    }
+}
+
+impl From<AccessLogFormat> for String {
+    fn from(format: AccessLogFormat) -> Self {
+        match format {
+            AccessLogFormat::Common => "common".to_string(),
+            AccessLogFormat::Combined => "combined".to_string(),
+            AccessLogFormat::Json => "json".to_string(),
+            AccessLogFormat::Custom(template) => template,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct AccessLogConfig {
+    /// File the log is appended to, or `stdout`.
+    pub path: String,
+    #[serde(default)]
+    pub format: AccessLogFormat,
+}
+
+/// Application log settings. Levels are re-applied when the configuration is reloaded; targets
+/// only change on restart.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct LoggingConfig {
+    /// Default level: `error`, `warn`, `info`, `debug` or `trace`.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    /// Per-module levels, e.g. `{ "sheldx::services" = "debug", "hyper" = "warn" }`.
+    #[serde(default)]
+    pub modules: HashMap<String, String>,
+    /// Log to standard output. Defaults to `show_logs_on_console`.
+    pub console: Option<bool>,
+    /// Colorize console output. Files, syslog and journald never get colors.
+    #[serde(default = "default_true")]
+    pub color: bool,
+    /// File to log to, if any.
+    pub file: Option<String>,
+    #[serde(default)]
+    pub syslog: bool,
+    #[serde(default)]
+    pub journald: bool,
+    /// Rotation of `file`; the file grows forever when unset.
+    pub rotation: Option<LogRotationConfig>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: default_log_level(),
+            modules: HashMap::new(),
+            console: None,
+            color: true,
+            file: None,
+            syslog: false,
+            journald: false,
+            rotation: None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct LogRotationConfig {
+    /// Rotate once the file reaches this many megabytes.
+    pub max_size_mb: Option<u64>,
+    /// Rotate when the hour or day changes.
+    #[serde(default)]
+    pub interval: RotationInterval,
+    /// Number of rotated files kept next to the active one.
+    #[serde(default = "default_log_retention")]
+    pub retention: usize,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RotationInterval {
+    #[default]
+    Never,
+    Hourly,
+    Daily,
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+fn default_log_retention() -> usize {
+    7
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// Optional listener exposing Prometheus metrics.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct MetricsConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// Address the metrics listener binds to.
+    #[serde(default = "default_metrics_listen")]
+    pub listen: String,
+    #[serde(default = "default_metrics_path")]
+    pub path: String,
+}
+
+fn default_metrics_listen() -> String {
+    "127.0.0.1:9100".to_string()
+}
+
+fn default_metrics_path() -> String {
+    "/metrics".to_string()
+}
+
+/// Export of request spans to an OpenTelemetry collector over OTLP/HTTP.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct TracingConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_otlp_endpoint")]
+    pub otlp_endpoint: String,
+    #[serde(default = "default_service_name")]
+    pub service_name: String,
+    /// Share of new traces that are recorded. Requests that arrive with a trace context follow
+    /// the caller's sampling decision instead.
+    #[serde(default = "default_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+fn default_otlp_endpoint() -> String {
+    "http://127.0.0.1:4318/v1/traces".to_string()
+}
+
+fn default_service_name() -> String {
+    "sheldx".to_string()
+}
+
+fn default_sample_ratio() -> f64 {
+    1.0
+}
+
+/// Admin API for inspecting and controlling a running proxy.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct AdminConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// TCP address the admin API binds to. Should be a loopback address.
+    #[serde(default = "default_admin_listen")]
+    pub listen: String,
+    /// Unix socket to serve the admin API on instead of `listen`.
+    pub socket: Option<String>,
+    /// Bearer token every admin request must present.
+    pub token: String,
+}
+
+fn default_admin_listen() -> String {
+    "127.0.0.1:9901".to_string()
+}
+
+/// How connections to upstreams are made.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct UpstreamConfig {
+    /// Seconds to wait for a connection to an upstream before trying again.
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    /// Connection attempts made before giving up on an upstream.
+    #[serde(default = "default_max_retries")]
+    pub max_retries: u8,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            connect_timeout: default_connect_timeout(),
+            max_retries: default_max_retries(),
+        }
+    }
+}
+
+fn default_connect_timeout() -> u64 {
+    5
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+fn default_max_retries() -> u8 {
+    3
+}
+
+/// Bounds on the memory used by `local` rate-limit rules.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
+pub struct RateLimiterConfig {
+    /// Most limiters kept at once, one per client and rule. When full, the clients seen least
+    /// recently are forgotten and start over.
+    #[serde(default = "default_rate_limiter_max_entries")]
+    pub max_entries: usize,
+    /// Seconds a limiter is kept without being used, once its client is back to a full limit.
+    #[serde(default = "default_rate_limiter_idle_timeout")]
+    pub idle_timeout: u64,
+}
+
+impl Default for RateLimiterConfig {
+    fn default() -> Self {
+        RateLimiterConfig {
+            max_entries: default_rate_limiter_max_entries(),
+            idle_timeout: default_rate_limiter_idle_timeout(),
+        }
+    }
+}
+
+fn default_rate_limiter_max_entries() -> usize {
+    100_000
+}
+
+fn default_rate_limiter_idle_timeout() -> u64 {
+    300
+}
+
+/// Caps on the client connections kept open at once.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema, Default, PartialEq, Eq)]
+pub struct ConnectionLimitsConfig {
+    /// Most connections open at once on each listener.
+    pub max_connections: Option<usize>,
+    /// Most connections open at once from a single client IP, over every listener. Trusted
+    /// proxies are exempt, as they carry many clients' connections.
+    pub max_connections_per_ip: Option<usize>,
+    /// What a listener does with new connections once it has `max_connections` open.
+    #[serde(default)]
+    pub when_full: ConnectionOverflow,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionOverflow {
+    /// Stop accepting until a connection closes, leaving new ones waiting in the backlog.
+    #[default]
+    Delay,
+    /// Accept and close new connections straight away.
+    Refuse,
+}
+
+/// Limits on how clients send requests, so slow or oversized ones can't tie up connections.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema, PartialEq, Eq)]
+pub struct RequestLimitsConfig {
+    /// Seconds a client has to finish the TLS handshake, and to send a request's headers.
+    #[serde(default = "default_header_read_timeout")]
+    pub header_read_timeout: u64,
+    /// Seconds a connection is kept open between requests.
+    #[serde(default = "default_keep_alive_timeout")]
+    pub keep_alive_timeout: u64,
+    /// Most bytes of request line and headers; larger requests get a 431. At least 8192.
+    #[serde(default = "default_max_header_size")]
+    pub max_header_size: usize,
+    /// Most headers in a request; requests with more get a 431.
+    #[serde(default = "default_max_headers")]
+    pub max_headers: usize,
+    /// Most bytes of a request body; larger requests get a 413. Unlimited by default.
+    pub max_body_size: Option<usize>,
+    /// Seconds to handle a request in full, including the upstream's response. Requests taking
+    /// longer get a 504. Unlimited by default.
+    pub request_timeout: Option<u64>,
+}
+
+impl Default for RequestLimitsConfig {
+    fn default() -> Self {
+        RequestLimitsConfig {
+            header_read_timeout: default_header_read_timeout(),
+            keep_alive_timeout: default_keep_alive_timeout(),
+            max_header_size: default_max_header_size(),
+            max_headers: default_max_headers(),
+            max_body_size: None,
+            request_timeout: None,
+        }
+    }
+}
+
+fn default_header_read_timeout() -> u64 {
+    30
+}
+
+fn default_keep_alive_timeout() -> u64 {
+    60
+}
+
+fn default_max_header_size() -> usize {
+    64 * 1024
+}
+
+fn default_max_headers() -> usize {
+    100
+}
+
+/// Where rate-limit state is kept: in this process, or in Redis so that every instance
+/// shares the same limits.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
 pub enum RateLimitStrategy {
-    local,
+    Local,
     Redis,
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+/// How requests are counted against a rule.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAlgorithm {
+    /// `limit` tokens are added every `duration` seconds, up to `max_tokens`; each request
+    /// takes one.
+    #[default]
+    TokenBucket,
+    /// At most `limit` requests in each `duration` seconds, counted from the Unix epoch.
+    FixedWindow,
+    /// At most `limit` requests in any `duration` seconds. Remembers every request, so memory
+    /// grows with `limit`.
+    #[serde(alias = "sliding_window")]
+    SlidingWindowLog,
+    /// Like the sliding window log, estimated from the counts of the current and previous
+    /// windows.
+    SlidingWindowCounter,
+    /// Requests are spaced `duration / limit` seconds apart, with bursts of up to `max_tokens`.
+    /// Allows the same requests as the token bucket with less state.
+    Gcra,
+}
+
+impl RateLimitAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateLimitAlgorithm::TokenBucket => "token_bucket",
+            RateLimitAlgorithm::FixedWindow => "fixed_window",
+            RateLimitAlgorithm::SlidingWindowLog => "sliding_window_log",
+            RateLimitAlgorithm::SlidingWindowCounter => "sliding_window_counter",
+            RateLimitAlgorithm::Gcra => "gcra",
+        }
+    }
+}
+
+/// What happens to requests under a Redis-backed rule while Redis can't be reached.
+#[derive(Debug, Deserialize, Clone, Copy, Serialize, JsonSchema, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedisFailureMode {
+    /// Let requests through without limiting them.
+    #[default]
+    Open,
+    /// Reject requests with a 503.
+    Closed,
+}
+
+/// Redis server shared by the rules using the `redis` strategy.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema, PartialEq)]
+pub struct RedisConfig {
+    /// Connection URL, e.g. `redis://127.0.0.1:6379` or `rediss://` for TLS.
+    #[serde(default = "default_redis_url")]
+    pub url: String,
+    /// Username for Redis ACLs, overriding the one in `url`.
+    pub username: Option<String>,
+    /// Password, overriding the one in `url`.
+    pub password: Option<String>,
+    /// Database number, overriding the one in `url`.
+    pub db: Option<i64>,
+    /// Prefix of every key sheldx writes.
+    #[serde(default = "default_redis_key_prefix")]
+    pub key_prefix: String,
+    /// Milliseconds to wait for Redis before treating it as unreachable.
+    #[serde(default = "default_redis_timeout_ms")]
+    pub timeout_ms: u64,
+    #[serde(default)]
+    pub failure_mode: RedisFailureMode,
+}
+
+impl Default for RedisConfig {
+    fn default() -> Self {
+        RedisConfig {
+            url: default_redis_url(),
+            username: None,
+            password: None,
+            db: None,
+            key_prefix: default_redis_key_prefix(),
+            timeout_ms: default_redis_timeout_ms(),
+            failure_mode: RedisFailureMode::default(),
+        }
+    }
+}
+
+fn default_redis_url() -> String {
+    "redis://127.0.0.1:6379".to_string()
+}
+
+fn default_redis_key_prefix() -> String {
+    "sheldx:ratelimit:".to_string()
+}
+
+fn default_redis_timeout_ms() -> u64 {
+    100
+}
+
+/// Part of the key a rate-limit rule counts requests under: `ip`, `header:<name>`,
+/// `bearer_subject`, `cookie:<name>`, `path`, `method` or `global`.
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema, PartialEq, Eq, Hash)]
+#[serde(try_from = "String", into = "String")]
+pub enum RateLimitKey {
+    /// The client IP, after resolving trusted proxies.
+    Ip,
+    /// A request header, such as an API key. Names are stored in lowercase.
+    Header(String),
+    /// The `sub` claim of a JWT bearer token. The signature is not verified, so this is only
+    /// meaningful behind something that rejects invalid tokens.
+    BearerSubject,
+    Cookie(String),
+    Path,
+    Method,
+    /// A single key shared by every client of the rule.
+    Global,
+}
+
+impl TryFrom<String> for RateLimitKey {
+    type Error = String;
+
+    fn try_from(key: String) -> Result<Self, Self::Error> {
+        let named = |name: &str| -> Result<String, String> {
+            if name.is_empty() || hyper::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                return Err(format!("{:?} is not a valid name in {:?}", name, key));
+            }
+            Ok(name.to_string())
+        };
+
+        match key.split_once(':') {
+            Some(("header", name)) => Ok(RateLimitKey::Header(named(name)?.to_ascii_lowercase())),
+            Some(("cookie", name)) => Ok(RateLimitKey::Cookie(named(name)?)),
+            Some(_) => Err(format!("Unknown rate-limit key {:?}; expected header:<name> or cookie:<name>", key)),
+            None =>
+                match key.as_str() {
+                    "ip" => Ok(RateLimitKey::Ip),
+                    "bearer_subject" => Ok(RateLimitKey::BearerSubject),
+                    "path" => Ok(RateLimitKey::Path),
+                    "method" => Ok(RateLimitKey::Method),
+                    "global" => Ok(RateLimitKey::Global),
+                    _ =>
+                        Err(
+                            format!(
+                                "Unknown rate-limit key {:?}; expected ip, header:<name>, bearer_subject, cookie:<name>, path, method or global",
+                                key
+                            )
+                        ),
+                }
+        }
+    }
+}
+
+impl From<RateLimitKey> for String {
+    fn from(key: RateLimitKey) -> Self {
+        match key {
+            RateLimitKey::Ip => "ip".to_string(),
+            RateLimitKey::Header(name) => format!("header:{}", name),
+            RateLimitKey::BearerSubject => "bearer_subject".to_string(),
+            RateLimitKey::Cookie(name) => format!("cookie:{}", name),
+            RateLimitKey::Path => "path".to_string(),
+            RateLimitKey::Method => "method".to_string(),
+            RateLimitKey::Global => "global".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
 pub struct RateLimitRule {
+    /// Identifies the rule's limiters, so they survive reloads and reordering. Defaults to the
+    /// host, with `#2`, `#3`... appended for further rules of the same host.
+    pub name: Option<String>,
     pub host: String,
     pub limit: u64,
     pub duration: u64,
@@ -34,125 +614,221 @@ pub struct RateLimitRule {
     pub excluded_paths: Vec<String>,
     pub excluded_ip_list: Vec<String>,
     pub strategy: RateLimitStrategy,
+    #[serde(default)]
+    pub algorithm: RateLimitAlgorithm,
+    /// What requests are counted by. Listing several keys counts each combination separately.
+    #[serde(default = "default_rate_limit_key")]
+    pub key: Vec<RateLimitKey>,
+}
+
+fn default_rate_limit_key() -> Vec<RateLimitKey> {
+    vec![RateLimitKey::Ip]
 }
 
-#[derive(Debug, Deserialize, Clone, Serialize)]
+/// The ID of each rule, in order: its name, or its host numbered among the rules for that host.
+pub fn rate_limit_rule_ids(rules: &[RateLimitRule]) -> Vec<String> {
+    let mut seen: HashMap<&str, usize> = HashMap::new();
+    rules
+        .iter()
+        .map(|rule| {
+            let count = seen.entry(rule.host.as_str()).or_default();
+            *count += 1;
+            match &rule.name {
+                Some(name) => name.clone(),
+                None if *count == 1 => rule.host.clone(),
+                None => format!("{}#{}", rule.host, count),
+            }
+        })
+        .collect()
+}
+
+#[derive(Debug, Deserialize, Clone, Serialize, JsonSchema)]
 pub struct Configs {
+    /// Version of the configuration format. Files without one are read as version 1 and
+    /// upgraded.
+    pub version: Option<u32>,
+    /// Files merged into this one, as paths or glob patterns relative to its directory.
+    pub include: Option<Vec<String>>,
     pub cert_path: String,
     pub key_path: String,
     pub is_tls_enabled: bool,
-    pub connection_timeout: Option<u64>,
-    pub max_retries: Option<u8>,
     pub show_logs_on_console: bool,
     pub forwarding_rules: Option<Vec<ForwardingRule>>,
     pub static_files_directory: Option<String>,
     pub rate_limit_rules: Option<Vec<RateLimitRule>>, // Updated to support multiple rules
+    pub ip_access_rules: Option<Vec<IpAccessRule>>,
+    /// Proxies (IPs or CIDR ranges) whose forwarding headers are trusted and extended
+    /// instead of replaced.
+    pub trusted_proxies: Option<Vec<String>>,
+    /// Headers, in order of preference, that carry the real client IP when the request comes
+    /// from a trusted proxy. Defaults to `["x-forwarded-for"]`.
+    pub client_ip_headers: Option<Vec<String>>,
+    pub request_id: Option<RequestIdConfig>,
+    pub access_logs: Option<Vec<AccessLogConfig>>,
+    pub logging: Option<LoggingConfig>,
+    pub metrics: Option<MetricsConfig>,
+    pub tracing: Option<TracingConfig>,
+    pub admin: Option<AdminConfig>,
+    pub upstream: Option<UpstreamConfig>,
+    pub redis: Option<RedisConfig>,
+    pub rate_limiter: Option<RateLimiterConfig>,
+    pub connection_limits: Option<ConnectionLimitsConfig>,
+    pub request_limits: Option<RequestLimitsConfig>,
+    /// Fields filled in from the environment or from files, which [`Configs::redacted`] hides.
+    #[serde(skip)]
+    pub interpolated_fields: Vec<String>,
 }
 
-// Check configurations
-impl Configs {
-    pub fn _check(&self) -> Result<(), ConfigError> {
-        if !PathBuf::from(&self.cert_path).exists() {
-            log::error!(
-                "Certificate file not found at {:?}. Please see how to set up a TLS certificate at https://docs.sheldx.io/docs/setup-tls-certificate",
-                self.cert_path
-            );
-        }
-
-        if !PathBuf::from(&self.key_path).exists() {
-            log::error!(
-                "Key file not found at {:?}. Please see how to set up a TLS certificate at https://docs.sheldx.io/docs/setup-tls-certificate",
-                self.key_path
-            );
-        }
+/// Uses `path` instead of [`DEFAULT_CONFIG_PATH`] for the rest of the process. Only the first
+/// call has an effect.
+pub fn set_config_path(path: PathBuf) {
+    let _ = CONFIG_PATH.set(path);
+}
 
-        if self.is_tls_enabled && (self.cert_path.is_empty() || self.key_path.is_empty()) {
-            log::error!("TLS is enabled but certificate or key path is not provided");
-        }
+/// The configuration file being used.
+pub fn config_path() -> PathBuf {
+    CONFIG_PATH.get().cloned().unwrap_or_else(|| PathBuf::from(DEFAULT_CONFIG_PATH))
+}
 
-        Ok(())
+/// The configuration in use. It is read from disk on first use only; after that it changes
+/// when [`set_current_configs`] is called on reload.
+pub fn current_configs() -> Result<Arc<Configs>, ConfigError> {
+    if let Some(configs) = CURRENT_CONFIGS.read().unwrap_or_else(|poisoned| poisoned.into_inner()).as_ref() {
+        return Ok(Arc::clone(configs));
     }
+
+    let configs = Arc::new(load_configs()?);
+    let mut current = CURRENT_CONFIGS.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+    Ok(Arc::clone(current.get_or_insert(configs)))
 }
 
+/// Serves new requests with `configs`. Requests already being handled finish with the
+/// configuration they started with.
+pub fn set_current_configs(configs: Configs) -> Arc<Configs> {
+    let configs = Arc::new(configs);
+    *CURRENT_CONFIGS.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::clone(&configs));
+    configs
+}
+
+/// Reads the configuration file and everything it includes from disk. Use [`current_configs`]
+/// for the configuration in use.
 pub fn load_configs() -> Result<Configs, ConfigError> {
-    let config_dir = PathBuf::from("/etc/sheldx/configs");
-    let config_path = config_dir.join("main.conf");
+    let config_path = config_path();
 
-    log::debug!("Trying to load config file from {:?}", config_path);
-    log::info!("Loading updated configuration file from {:?}", config_path);  
+    log::debug!("Loading configuration file from {:?}", config_path);
 
     // Check if configuration file exists
     if !config_path.exists() {
-        // Create default config file
-        log::warn!("Configuration file not found, creating default configuration file");
-        create_default_config().map_err(|_| ConfigError::ConfigFileReadError)?;
+        log::error!(
+            "Configuration file {:?} not found. Create one with `sheldx print-default-config`, or start with `sheldx run --create-default-config`",
+            config_path
+        );
+        return Err(ConfigError::ConfigFileNotFound(config_path));
     }
 
-    log::info!("Configuration file found at {:?}", config_path);
-
-    // Read configuration file
-    let config_string = fs
-        ::read_to_string(&config_path)
-        .map_err(|_| ConfigError::ConfigFileReadError)?;
-    log::debug!("Configuration file updated: {:?}", config_string);
-    let config: Result<Configs, toml::de::Error> = toml::from_str(&config_string);
+    // Read the configuration file and everything it includes, and refuse it if it can't work
+    let parsed = read_config_sources(&config_path).and_then(|merged| {
+        log::debug!(
+            "Configuration files: {:?}",
+            merged.sources.iter().map(|source| &source.path).collect::<Vec<_>>()
+        );
+        let (configs, _) = parse_configs(&merged)?;
+        let errors: Vec<ConfigDiagnostic> = configs
+            .validate()
+            .into_iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .map(|diagnostic| merged.locate(diagnostic))
+            .collect();
+        if errors.is_empty() { Ok(configs) } else { Err(errors) }
+    });
 
-    match config {
+    match parsed {
         Ok(config) => Ok(config),
-        Err(e) => {
-            log::error!("Failed to parse configuration file: {:?}", e);
+        Err(diagnostics) => {
+            for diagnostic in diagnostics {
+                log::error!("Invalid configuration: {}", diagnostic);
+            }
             Err(ConfigError::ConfigFileParseError)
         }
     }
 }
 
+/// The configuration written when no configuration file exists.
+pub fn default_configs() -> Configs {
+    Configs {
+        version: Some(CONFIG_VERSION),
+        include: None,
+        cert_path: String::from(""),
+        key_path: String::from(""),
+        is_tls_enabled: false,
+        show_logs_on_console: true,
+        forwarding_rules: None,
+        static_files_directory: Some(String::from("/etc/sheldx/static/index.html")),
+        rate_limit_rules: Some(
+            vec![
+                RateLimitRule {
+                    name: None,
+                    host: "api.example.com".to_string(),
+                    limit: 10,
+                    duration: 60,
+                    max_tokens: 1000,
+                    excluded_paths: vec!["/health".to_string()],
+                    excluded_ip_list: vec!["192.168.1.1".to_string()],
+                    strategy: RateLimitStrategy::Local,
+                    algorithm: RateLimitAlgorithm::TokenBucket,
+                    key: vec![RateLimitKey::Ip],
+                },
+                RateLimitRule {
+                    name: None,
+                    host: "public.example.com".to_string(),
+                    limit: 5,
+                    duration: 60,
+                    max_tokens: 500,
+                    excluded_paths: vec!["/status".to_string()],
+                    excluded_ip_list: vec![],
+                    strategy: RateLimitStrategy::Redis,
+                    algorithm: RateLimitAlgorithm::SlidingWindowLog,
+                    key: vec![RateLimitKey::Header("x-api-key".to_string())],
+                }
+            ]
+        ),
+        ip_access_rules: None,
+        trusted_proxies: None,
+        client_ip_headers: None,
+        request_id: None,
+        access_logs: None,
+        logging: None,
+        metrics: None,
+        tracing: None,
+        admin: None,
+        upstream: Some(UpstreamConfig::default()),
+        redis: None,
+        rate_limiter: None,
+        connection_limits: None,
+        request_limits: None,
+        interpolated_fields: Vec::new(),
+    }
+}
+
+/// The default configuration written in `format`.
+pub fn default_config_text(format: ConfigFormat) -> Result<String, String> {
+    let table = toml::Table::try_from(default_configs()).map_err(|e| e.to_string())?;
+    write_config_text(&table, format)
+}
+
 pub fn create_default_config() -> Result<(), ConfigError> {
-    let config_dir = PathBuf::from("/etc/sheldx/configs");
-    let config_path = config_dir.join("main.conf");
+    let config_path = config_path();
 
     // Ensure the path exists
-    if !config_dir.exists() {
-        fs::create_dir_all(&config_dir).map_err(|_| ConfigError::ConfigFileReadError)?;
+    if let Some(config_dir) = config_path.parent().filter(|dir| !dir.exists()) {
+        fs::create_dir_all(config_dir).map_err(|_| ConfigError::ConfigFileReadError)?;
     }
 
     // Create default configuration
     if !config_path.exists() {
-        let default_config = Configs {
-            cert_path: String::from(""),
-            key_path: String::from(""),
-            is_tls_enabled: false,
-            show_logs_on_console: true,
-            connection_timeout: Some(10),
-            max_retries: Some(3),
-            forwarding_rules: None,
-            static_files_directory: Some(String::from("/etc/sheldx/static/index.html")),
-            rate_limit_rules: Some(
-                vec![
-                    RateLimitRule {
-                        host: "api.example.com".to_string(),
-                        limit: 10,
-                        duration: 60,
-                        max_tokens: 1000,
-                        excluded_paths: vec!["/health".to_string()],
-                        excluded_ip_list: vec!["192.168.1.1".to_string()],
-                        strategy: RateLimitStrategy::local,
-                    },
-                    RateLimitRule {
-                        host: "public.example.com".to_string(),
-                        limit: 5,
-                        duration: 60,
-                        max_tokens: 500,
-                        excluded_paths: vec!["/status".to_string()],
-                        excluded_ip_list: vec![],
-                        strategy: RateLimitStrategy::Redis,
-                    }
-                ]
-            ),
-        };
-
-        let default_config_string = toml
-            ::to_string(&default_config)
-            .map_err(|_| ConfigError::ConfigFileParseError)?;
+        let default_config_string = default_config_text(main_config_format(&config_path)).map_err(
+            |_| ConfigError::ConfigFileParseError
+        )?;
         fs
             ::write(&config_path, default_config_string)
             .map_err(|_| ConfigError::ConfigFileReadError)?;
@@ -1,7 +1,10 @@
 use std::convert::Infallible;
 
 use http_body_util::Full;
-use hyper::{ body::Bytes, Response };
+use hyper::header::ACCEPT;
+use hyper::{ body::Bytes, HeaderMap, Response };
+
+use super::current_request_id;
 
 pub struct HttpMessageError {
     pub status_code: u16,
@@ -21,6 +24,10 @@ pub fn http_error_response(
     };
 
     // create simple html response with the error message and status code and title and make it center of the page
+    // include the request id so users can quote it when reporting problems
+    let request_id = current_request_id()
+        .map(|id| format!("<p><small>Request ID: {}</small></p>", id))
+        .unwrap_or_default();
 
     let http = format!(
         r#"<html>
@@ -32,13 +39,15 @@ pub fn http_error_response(
                 <div style="text-align: center;">
                     <h1>{status_code}</h1>
                     <p>{message}</p>
+                    {request_id}
                 </div>
             </div>
         </body>
     </html>"#,
         title = error.title,
         status_code = error.status_code,
-        message = error.message
+        message = error.message,
+        request_id = request_id
     );
 
     let response = Response::builder()
@@ -49,3 +58,47 @@ pub fn http_error_response(
 
     Ok(response)
 }
+
+/// Whether the client's `Accept` header prefers JSON over HTML, as API clients' do. Browsers,
+/// and clients without a preference, get HTML.
+pub fn prefers_json(headers: &HeaderMap) -> bool {
+    let accept = headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+    accept_quality(&accept, "application", "json") > accept_quality(&accept, "text", "html")
+}
+
+/// The quality `accept` gives to `kind/subtype`, from the most specific range matching it.
+fn accept_quality(accept: &str, kind: &str, subtype: &str) -> f32 {
+    let suffix = format!("+{}", subtype);
+    let mut best: Option<(u8, f32)> = None;
+    for range in accept.to_ascii_lowercase().split(',') {
+        let mut params = range.split(';');
+        let media_type = params.next().unwrap_or_default().trim();
+        let Some((range_kind, range_subtype)) = media_type.split_once('/') else {
+            continue;
+        };
+
+        let specificity = if range_kind == kind && (range_subtype == subtype || range_subtype.ends_with(&suffix)) {
+            2
+        } else if range_kind == kind && range_subtype == "*" {
+            1
+        } else if range_kind == "*" && range_subtype == "*" {
+            0
+        } else {
+            continue;
+        };
+        let quality = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+
+        if best.is_none_or(|(current, _)| specificity > current) {
+            best = Some((specificity, quality));
+        }
+    }
+    best.map_or(0.0, |(_, quality)| quality)
+}
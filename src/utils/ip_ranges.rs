@@ -0,0 +1,156 @@
+use std::net::IpAddr;
+
+use ipnet::IpNet;
+
+/// A set of IP networks parsed from config entries such as `"10.0.0.0/8"` or `"192.168.1.1"`.
+#[derive(Debug, Clone, Default)]
+pub struct IpRanges {
+    networks: IpPrefixTree<()>,
+}
+
+impl IpRanges {
+    /// Parses `entries`, accepting both CIDR ranges and bare addresses.
+    /// Invalid entries are logged and skipped.
+    pub fn parse(entries: &[String]) -> IpRanges {
+        let mut networks = IpPrefixTree::default();
+        for entry in entries {
+            match parse_ip_range(entry) {
+                Some(network) => networks.insert(&network, ()),
+                None => log::warn!("Ignoring invalid IP address or CIDR range: {:?}", entry),
+            }
+        }
+
+        IpRanges { networks }
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.networks.longest_match(ip).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+}
+
+/// Binary trie of IP networks, keeping a value per network.
+///
+/// Lookups walk one node per bit of the address, so they take the same time however many
+/// networks are stored, and find the most specific network containing the address.
+#[derive(Debug, Clone)]
+pub struct IpPrefixTree<T> {
+    v4: PrefixNode<T>,
+    v6: PrefixNode<T>,
+}
+
+#[derive(Debug, Clone)]
+struct PrefixNode<T> {
+    value: Option<T>,
+    children: [Option<Box<PrefixNode<T>>>; 2],
+}
+
+impl<T> Default for PrefixNode<T> {
+    fn default() -> Self {
+        PrefixNode { value: None, children: [None, None] }
+    }
+}
+
+impl<T> Default for IpPrefixTree<T> {
+    fn default() -> Self {
+        IpPrefixTree { v4: PrefixNode::default(), v6: PrefixNode::default() }
+    }
+}
+
+impl<T> IpPrefixTree<T> {
+    /// Stores `value` for `network`, replacing the value of the same network if there was one.
+    pub fn insert(&mut self, network: &IpNet, value: T) {
+        let network = canonical_network(network).trunc();
+        let bits = address_bits(&network.addr());
+        let mut node = self.root_mut(&network.addr());
+        for index in 0..network.prefix_len() {
+            node = node.children[bit(bits, index)].get_or_insert_with(Default::default);
+        }
+        node.value = Some(value);
+    }
+
+    /// The value of the most specific network containing `ip`.
+    pub fn longest_match(&self, ip: &IpAddr) -> Option<&T> {
+        let ip = canonical_ip(ip);
+        let bits = address_bits(&ip);
+        let mut node = match ip {
+            IpAddr::V4(_) => &self.v4,
+            IpAddr::V6(_) => &self.v6,
+        };
+        let mut best = node.value.as_ref();
+        for index in 0..max_prefix_len(&ip) {
+            match &node.children[bit(bits, index)] {
+                Some(child) => node = child,
+                None => break,
+            }
+            best = node.value.as_ref().or(best);
+        }
+        best
+    }
+
+    pub fn is_empty(&self) -> bool {
+        [&self.v4, &self.v6]
+            .iter()
+            .all(|root| root.value.is_none() && root.children.iter().all(Option::is_none))
+    }
+
+    fn root_mut(&mut self, ip: &IpAddr) -> &mut PrefixNode<T> {
+        match ip {
+            IpAddr::V4(_) => &mut self.v4,
+            IpAddr::V6(_) => &mut self.v6,
+        }
+    }
+}
+
+/// The address as 128 bits, most significant first, so both families walk the tree alike.
+fn address_bits(ip: &IpAddr) -> u128 {
+    match ip {
+        IpAddr::V4(v4) => (u32::from(*v4) as u128) << 96,
+        IpAddr::V6(v6) => u128::from(*v6),
+    }
+}
+
+fn bit(bits: u128, index: u8) -> usize {
+    ((bits >> (127 - index)) & 1) as usize
+}
+
+fn max_prefix_len(ip: &IpAddr) -> u8 {
+    match ip {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    }
+}
+
+/// Parses a CIDR range, treating a bare address as a single-host network.
+pub fn parse_ip_range(entry: &str) -> Option<IpNet> {
+    let entry = entry.trim();
+    entry
+        .parse::<IpNet>()
+        .ok()
+        .or_else(|| entry.parse::<IpAddr>().ok().map(IpNet::from))
+}
+
+/// Maps IPv4-mapped IPv6 networks (`::ffff:a.b.c.d/n`, `n >= 96`) to the IPv4 networks they
+/// cover, so they match the addresses `canonical_ip` produces.
+fn canonical_network(network: &IpNet) -> IpNet {
+    match network {
+        IpNet::V6(v6) if v6.prefix_len() >= 96 => {
+            match v6.addr().to_ipv4_mapped() {
+                Some(v4) => IpNet::new(IpAddr::V4(v4), v6.prefix_len() - 96).unwrap_or(*network),
+                None => *network,
+            }
+        }
+        _ => *network,
+    }
+}
+
+/// Maps IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) back to plain IPv4.
+pub fn canonical_ip(ip: &IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*ip),
+        IpAddr::V4(_) => *ip,
+    }
+}
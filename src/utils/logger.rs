@@ -1,36 +1,225 @@
+use std::io::{ self, Write };
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{ OnceLock, RwLock };
+
 use chrono::Local;
 use colored::*;
-use fern::Dispatch;
-use log::{LevelFilter};
-
-pub fn init_logger() -> Result<(), Box<dyn std::error::Error>> {
-    let log_file_path = "output.log";
-
-    // Initialize the logger
-    Dispatch::new()
-        .format(|out, message, record| {
-            let formatted_message = match record.level() {
-                log::Level::Error => message.to_string().red().to_string(),
-                log::Level::Warn => message.to_string().yellow().to_string(),
-                log::Level::Info => message.to_string().green().to_string(),
-                log::Level::Debug => message.to_string().cyan().to_string(),
-                log::Level::Trace => message.to_string().dimmed().to_string(),
-            };
-            
-            out.finish(format_args!(
-                "{} [{}] {}",
-                Local::now().format("%Y-%m-%d %H:%M:%S"),
-                record.level(),
-                formatted_message
-            ))
-        })
-        .level(LevelFilter::Debug)  // Adjust log level as needed
-        .chain(std::io::stdout())
-        .chain(fern::log_file(log_file_path)?)  // Handle potential errors
-        .apply()?;
-    
+use fern::{ Dispatch, FormatCallback };
+use lazy_static::lazy_static;
+use log::{ LevelFilter, Metadata, Record };
+
+use super::{ current_request_id, LoggingConfig, RotatingFile };
+
+/// Socket journald listens on for native protocol messages.
+const JOURNALD_SOCKET: &str = "/run/systemd/journal/socket";
+
+lazy_static! {
+    /// Levels currently in effect, swapped out on reload without rebuilding the logger.
+    static ref LOG_LEVELS: RwLock<LogLevels> = RwLock::new(LogLevels::default());
+}
+
+/// Default level given on the command line, which wins over the configured one.
+static LEVEL_OVERRIDE: OnceLock<LevelFilter> = OnceLock::new();
+
+/// The default level plus per-module overrides, most specific module first.
+#[derive(Debug, Clone)]
+struct LogLevels {
+    default: LevelFilter,
+    modules: Vec<(String, LevelFilter)>,
+}
+
+impl Default for LogLevels {
+    fn default() -> Self {
+        LogLevels { default: LevelFilter::Info, modules: Vec::new() }
+    }
+}
+
+impl LogLevels {
+    fn from_config(config: &LoggingConfig) -> LogLevels {
+        let mut modules: Vec<(String, LevelFilter)> = config.modules
+            .iter()
+            .map(|(module, level)| (module.clone(), parse_level(level)))
+            .collect();
+        modules.sort_by_key(|(module, _)| std::cmp::Reverse(module.len()));
+
+        let default = LEVEL_OVERRIDE.get().copied().unwrap_or_else(|| parse_level(&config.level));
+        LogLevels { default, modules }
+    }
+
+    fn level_for(&self, target: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .find(|(module, _)| {
+                target == module || target.strip_prefix(module.as_str()).is_some_and(|rest| rest.starts_with("::"))
+            })
+            .map(|(_, level)| *level)
+            .unwrap_or(self.default)
+    }
+
+    fn max_level(&self) -> LevelFilter {
+        self.modules
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default, |max, level| max.max(level))
+    }
+}
+
+fn parse_level(level: &str) -> LevelFilter {
+    LevelFilter::from_str(level).unwrap_or_else(|_| {
+        eprintln!("Unknown log level {:?}, falling back to info", level);
+        LevelFilter::Info
+    })
+}
+
+fn is_enabled(metadata: &Metadata) -> bool {
+    let levels = LOG_LEVELS.read().unwrap_or_else(|poisoned| poisoned.into_inner());
+    metadata.level() <= levels.level_for(metadata.target())
+}
+
+/// Sets up logging to the targets in `config`. `show_logs_on_console` is used when the
+/// config doesn't say whether to log to the console.
+pub fn init_logger(
+    config: &LoggingConfig,
+    show_logs_on_console: bool
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut dispatch = Dispatch::new().filter(is_enabled);
+
+    if config.console.unwrap_or(show_logs_on_console) {
+        let console = if config.color {
+            Dispatch::new().format(format_colored)
+        } else {
+            Dispatch::new().format(format_plain)
+        };
+        dispatch = dispatch.chain(console.chain(std::io::stdout()));
+    }
+
+    if let Some(log_file_path) = &config.file {
+        let writer: Box<dyn Write + Send> = match &config.rotation {
+            Some(rotation) => Box::new(RotatingFile::open(Path::new(log_file_path), rotation)?),
+            None => Box::new(fern::log_file(log_file_path)?),
+        };
+        dispatch = dispatch.chain(Dispatch::new().format(format_plain).chain(writer));
+    }
+
+    if config.syslog {
+        let formatter = syslog::Formatter3164 {
+            facility: syslog::Facility::LOG_DAEMON,
+            hostname: None,
+            process: "sheldx".to_owned(),
+            pid: std::process::id(),
+        };
+        let logger = syslog::unix(formatter).map_err(|e| format!("Unable to connect to syslog: {}", e))?;
+        dispatch = dispatch.chain(Dispatch::new().format(format_bare).chain(logger));
+    }
+
+    if config.journald {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(JOURNALD_SOCKET)?;
+        dispatch = dispatch.chain(fern::Output::call(move |record| send_to_journald(&socket, record)));
+    }
+
+    dispatch.apply()?;
+    apply_log_levels(config);
+
     // Inform the user where the log file is located
-    println!("Logging initialized. Log file can be found at: {}", log_file_path);
+    if let Some(log_file_path) = &config.file {
+        log::info!("Logging initialized. Log file can be found at: {}", log_file_path);
+    }
     Ok(())
 }
 
+/// Uses `level` as the default log level instead of the configured one, including after
+/// reloads. Must be called before [`init_logger`].
+pub fn override_log_level(level: LevelFilter) {
+    let _ = LEVEL_OVERRIDE.set(level);
+}
+
+/// Replaces the active log levels, e.g. after the configuration was reloaded.
+pub fn apply_log_levels(config: &LoggingConfig) {
+    let levels = LogLevels::from_config(config);
+    log::set_max_level(levels.max_level());
+    *LOG_LEVELS.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = levels;
+}
+
+fn format_colored(out: FormatCallback, message: &std::fmt::Arguments, record: &Record) {
+    let formatted_message = match record.level() {
+        log::Level::Error => message.to_string().red().to_string(),
+        log::Level::Warn => message.to_string().yellow().to_string(),
+        log::Level::Info => message.to_string().green().to_string(),
+        log::Level::Debug => message.to_string().cyan().to_string(),
+        log::Level::Trace => message.to_string().dimmed().to_string(),
+    };
+    format_plain(out, &format_args!("{}", formatted_message), record)
+}
+
+fn format_plain(out: FormatCallback, message: &std::fmt::Arguments, record: &Record) {
+    match current_request_id() {
+        Some(request_id) => out.finish(format_args!(
+            "{} [{}] [{}] {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            record.level(),
+            request_id,
+            message
+        )),
+        None => out.finish(format_args!(
+            "{} [{}] {}",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            record.level(),
+            message
+        )),
+    }
+}
+
+/// Leaves timestamps and levels to the receiving logger, as syslog records its own.
+fn format_bare(out: FormatCallback, message: &std::fmt::Arguments, _record: &Record) {
+    match current_request_id() {
+        Some(request_id) => out.finish(format_args!("[{}] {}", request_id, message)),
+        None => out.finish(format_args!("{}", message)),
+    }
+}
+
+/// Sends `record` using the journald native protocol, which keeps multi-line messages intact and
+/// attaches the request ID as a separate field.
+fn send_to_journald(socket: &UnixDatagram, record: &Record) {
+    let priority = match record.level() {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    };
+
+    let mut fields = vec![
+        ("PRIORITY", priority.to_string()),
+        ("SYSLOG_IDENTIFIER", "sheldx".to_string()),
+        ("TARGET", record.target().to_string()),
+        ("MESSAGE", record.args().to_string())
+    ];
+    if let Some(request_id) = current_request_id() {
+        fields.push(("REQUEST_ID", request_id));
+    }
+
+    let mut datagram = Vec::new();
+    for (name, value) in fields {
+        if let Err(e) = write_journald_field(&mut datagram, name, &value) {
+            eprintln!("Failed to encode journald field {}: {}", name, e);
+        }
+    }
+    if let Err(e) = socket.send(&datagram) {
+        eprintln!("Failed to send log record to journald: {}", e);
+    }
+}
+
+fn write_journald_field(buf: &mut Vec<u8>, name: &str, value: &str) -> io::Result<()> {
+    if value.contains('\n') {
+        // multi-line values are length-prefixed instead of newline-terminated
+        buf.write_all(name.as_bytes())?;
+        buf.write_all(b"\n")?;
+        buf.write_all(&(value.len() as u64).to_le_bytes())?;
+        buf.write_all(value.as_bytes())?;
+        buf.write_all(b"\n")
+    } else {
+        writeln!(buf, "{}={}", name, value)
+    }
+}
@@ -1,9 +1,9 @@
 
-use hyper::{body::Incoming, Request};
+use hyper::Request;
 
 use super::ForwardingRule;
 
-pub fn extract_host(req: &Request<Incoming>) -> Result<String, String> {
+pub fn extract_host<B>(req: &Request<B>) -> Result<String, String> {
     match req.headers().get("host") {
         Some(host) => match host.to_str() {
             Ok(host_str) => Ok(host_str.to_owned()),
@@ -1,10 +1,30 @@
+mod access_log;
+mod config_formats;
+mod config_interpolation;
+mod config_migrations;
+mod config_sources;
+mod config_validator;
 mod configs;
 mod logger;
 mod http_errors;
+mod ip_ranges;
 mod macros;
 mod redis;
+mod request_id;
+mod rotating_file;
+mod templates;
+pub use access_log::*;
+pub use config_formats::*;
+pub use config_interpolation::*;
+pub use config_migrations::*;
+pub use config_sources::*;
+pub use config_validator::*;
 pub use configs::*;
 pub use logger::*;
 pub use http_errors::*;
+pub use ip_ranges::*;
 pub use macros::*;
-pub use redis::*;
\ No newline at end of file
+pub use redis::*;
+pub use request_id::*;
+pub use rotating_file::*;
+pub use templates::*;
\ No newline at end of file
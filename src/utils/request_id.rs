@@ -0,0 +1,50 @@
+use hyper::header::{ HeaderMap, HeaderName, HeaderValue };
+use uuid::Uuid;
+
+use super::RequestIdConfig;
+
+/// Longest inbound request ID that is accepted as-is.
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+tokio::task_local! {
+    /// ID of the request being handled by the current task.
+    pub static REQUEST_ID: String;
+}
+
+/// Returns the ID of the request being handled by the current task, if any.
+pub fn current_request_id() -> Option<String> {
+    REQUEST_ID.try_with(|id| id.clone()).ok()
+}
+
+/// Picks the ID for an incoming request: the inbound header when `config` trusts it and it looks
+/// sane, otherwise a freshly generated UUIDv7.
+pub fn resolve_request_id(headers: &HeaderMap, config: &RequestIdConfig) -> String {
+    if config.trust_incoming {
+        let incoming = headers
+            .get(config.header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|id| is_valid_request_id(id));
+        if let Some(id) = incoming {
+            return id.to_string();
+        }
+    }
+
+    Uuid::now_v7().to_string()
+}
+
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() &&
+        id.len() <= MAX_REQUEST_ID_LEN &&
+        id.chars().all(|c| c.is_ascii_alphanumeric() || "-_.:".contains(c))
+}
+
+/// Sets the configured request ID header on `headers`, replacing any existing value.
+pub fn set_request_id_header(headers: &mut HeaderMap, config: &RequestIdConfig, id: &str) {
+    match (HeaderName::from_bytes(config.header.as_bytes()), HeaderValue::from_str(id)) {
+        (Ok(name), Ok(value)) => {
+            headers.insert(name, value);
+        }
+        _ => log::warn!("Unable to set request id header {:?}", config.header),
+    }
+}
@@ -0,0 +1,153 @@
+use std::fs::{ self, File, OpenOptions };
+use std::io::{ self, Write };
+use std::path::{ Path, PathBuf };
+
+use chrono::{ DateTime, Local };
+
+use super::{ LogRotationConfig, RotationInterval };
+
+/// A log file that is renamed to `<name>.<timestamp>` once it grows too large or the rotation
+/// interval elapses, keeping at most `retention` rotated files.
+///
+/// Rotation is checked on `flush`, which the logger calls after every record, so a record is
+/// never split across two files. A file last written in an earlier period is rotated on the
+/// first flush after it is opened.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    max_size: Option<u64>,
+    interval: RotationInterval,
+    period: String,
+    retention: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, rotation: &LogRotationConfig) -> io::Result<RotatingFile> {
+        if let Some(parent) = path.parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let size = metadata.len();
+        let last_written = match metadata.modified() {
+            Ok(modified) if size > 0 => DateTime::<Local>::from(modified),
+            _ => Local::now(),
+        };
+
+        Ok(RotatingFile {
+            path: path.to_path_buf(),
+            file,
+            size,
+            max_size: rotation.max_size_mb.map(|mb| mb * 1024 * 1024),
+            interval: rotation.interval,
+            period: period_at(rotation.interval, last_written),
+            retention: rotation.retention,
+        })
+    }
+
+    fn should_rotate(&self) -> bool {
+        let too_large = self.max_size.is_some_and(|max_size| self.size >= max_size);
+        too_large || current_period(self.interval) != self.period
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let timestamp = Local::now().format("%Y%m%d-%H%M%S").to_string();
+        let mut rotated = rotated_path(&self.path, &timestamp);
+        let mut sequence = 1;
+        while rotated.exists() {
+            rotated = rotated_path(&self.path, &format!("{}.{}", timestamp, sequence));
+            sequence += 1;
+        }
+        fs::rename(&self.path, &rotated)?;
+
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.period = current_period(self.interval);
+
+        self.remove_expired()
+    }
+
+    /// Deletes the oldest rotated files beyond `retention`. Only files named the way `rotate`
+    /// names them count; other files next to the log, such as another tool's backups, are left
+    /// alone.
+    fn remove_expired(&self) -> io::Result<()> {
+        let dir = match self.path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let name = file_name(&self.path);
+
+        let mut rotated: Vec<((String, u32), PathBuf)> = fs
+            ::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter_map(|path| rotation_order(&name, &file_name(&path)).map(|order| (order, path)))
+            .collect();
+        rotated.sort();
+
+        let expired = rotated.len().saturating_sub(self.retention);
+        for (_, path) in rotated.into_iter().take(expired) {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.should_rotate() {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+fn current_period(interval: RotationInterval) -> String {
+    period_at(interval, Local::now())
+}
+
+fn period_at(interval: RotationInterval, time: DateTime<Local>) -> String {
+    match interval {
+        RotationInterval::Never => String::new(),
+        RotationInterval::Hourly => time.format("%Y%m%d%H").to_string(),
+        RotationInterval::Daily => time.format("%Y%m%d").to_string(),
+    }
+}
+
+/// Where `candidate` sorts among the files rotated from `name`, oldest first, if it is one:
+/// `<name>.<%Y%m%d-%H%M%S>`, with `.<n>` appended when several rotations share a second.
+fn rotation_order(name: &str, candidate: &str) -> Option<(String, u32)> {
+    let suffix = candidate.strip_prefix(name)?.strip_prefix('.')?;
+    let (timestamp, sequence) = match suffix.split_once('.') {
+        Some((timestamp, sequence)) => (timestamp, sequence),
+        None => (suffix, "0"),
+    };
+
+    let is_timestamp = timestamp.len() == 15 &&
+        timestamp.char_indices().all(|(i, c)| if i == 8 { c == '-' } else { c.is_ascii_digit() });
+    if !is_timestamp || !sequence.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((timestamp.to_string(), sequence.parse().ok()?))
+}
+
+fn rotated_path(path: &Path, suffix: &str) -> PathBuf {
+    path.with_file_name(format!("{}.{}", file_name(path), suffix))
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
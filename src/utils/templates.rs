@@ -0,0 +1,25 @@
+/// Replaces every `{{name}}` in `template` with `lookup(name)`, leaving names it doesn't know as
+/// they are.
+///
+/// The template is read once from left to right and inserted values are never looked at again,
+/// so a value containing `{{...}}`, which may come from the client, is kept literally.
+pub fn render_placeholders<F>(template: &str, mut lookup: F) -> String where F: FnMut(&str) -> Option<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        match after.find("}}").and_then(|end| lookup(&after[..end]).map(|value| (end, value))) {
+            Some((end, value)) => {
+                rendered.push_str(&value);
+                rest = &after[end + 2..];
+            }
+            None => {
+                rendered.push_str("{{");
+                rest = after;
+            }
+        }
+    }
+    rendered.push_str(rest);
+    rendered
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::thread;
+use std::time::{ Duration, Instant };
+
+use chrono::{ Local, TimeZone };
+use sheldx::utils::{ write_access_log, AccessLogConfig, AccessLogEntry, AccessLogFormat };
+
+fn entry() -> AccessLogEntry {
+    AccessLogEntry {
+        timestamp: Local.with_ymd_and_hms(2024, 7, 1, 12, 30, 5).unwrap(),
+        request_id: "0190a0b1-7c2e-7000-8000-000000000001".to_string(),
+        client_ip: "203.0.113.7".to_string(),
+        host: "www.example.com".to_string(),
+        method: "GET".to_string(),
+        path: "/search?q=1".to_string(),
+        protocol: "HTTP/1.1".to_string(),
+        status: Some(200),
+        bytes_in: 0,
+        bytes_out: 512,
+        referer: None,
+        user_agent: Some("curl/8.5.0".to_string()),
+        upstream: Some("127.0.0.1:3000".to_string()),
+        upstream_latency_ms: Some(1.5),
+        total_latency_ms: 2.25,
+        rate_limit: "allowed".to_string(),
+    }
+}
+
+fn custom(template: &str) -> AccessLogFormat {
+    AccessLogFormat::Custom(template.to_string())
+}
+
+#[test]
+fn combined_lines_quote_referer_and_user_agent() {
+    let line = entry().format(&AccessLogFormat::Combined);
+    let time = entry().timestamp.format("%d/%b/%Y:%H:%M:%S %z").to_string();
+
+    assert_eq!(
+        line,
+        format!("203.0.113.7 - - [{}] \"GET /search?q=1 HTTP/1.1\" 200 512 \"-\" \"curl/8.5.0\"", time)
+    );
+    assert!(entry().format(&AccessLogFormat::Common).ends_with("\"GET /search?q=1 HTTP/1.1\" 200 512"));
+}
+
+#[test]
+fn client_values_are_escaped() {
+    let mut entry = entry();
+    entry.user_agent = Some("evil\" 500 \"x".to_string());
+    entry.referer = Some("back\\slash\nnew line".to_string());
+
+    let line = entry.format(&custom("\"{{referer}}\" \"{{user_agent}}\""));
+    assert_eq!(line, "\"back\\\\slash\\x0anew line\" \"evil\\\" 500 \\\"x\"");
+}
+
+#[test]
+fn client_values_are_not_rendered_again() {
+    let mut entry = entry();
+    entry.user_agent = Some("{{status}} {{request_id}}".to_string());
+    entry.path = "/{{host}}".to_string();
+
+    let line = entry.format(&custom("{{path}} {{user_agent}} {{status}}"));
+    assert_eq!(line, "/{{host}} {{status}} {{request_id}} 200");
+}
+
+#[test]
+fn custom_templates_use_dashes_for_missing_values() {
+    let mut entry = entry();
+    entry.status = None;
+    entry.upstream = None;
+    entry.upstream_latency_ms = None;
+
+    let line = entry.format(&custom("{{status}} {{upstream}} {{upstream_latency_ms}} {{total_latency_ms}} {{unknown}}"));
+    assert_eq!(line, "- - - 2.250 {{unknown}}");
+}
+
+#[test]
+fn json_lines_keep_values_as_they_are() {
+    let mut entry = entry();
+    entry.user_agent = Some("quote \" and {{status}}".to_string());
+
+    let json: serde_json::Value = serde_json::from_str(&entry.format(&AccessLogFormat::Json)).unwrap();
+    assert_eq!(json["user_agent"], "quote \" and {{status}}");
+    assert_eq!(json["status"], 200);
+    assert_eq!(json["referer"], serde_json::Value::Null);
+}
+
+#[test]
+fn lines_are_appended_to_the_configured_files() {
+    let dir = std::env::temp_dir().join(format!("sheldx-access-log-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("nested").join("access.log");
+    let access_logs = vec![
+        AccessLogConfig { path: path.to_string_lossy().into_owned(), format: custom("{{method}} {{status}}") },
+        AccessLogConfig { path: path.to_string_lossy().into_owned(), format: custom("{{request_id}}") }
+    ];
+
+    write_access_log(&entry(), &access_logs);
+    write_access_log(&entry(), &access_logs[..1]);
+
+    let expected = "GET 200\n0190a0b1-7c2e-7000-8000-000000000001\nGET 200\n";
+    assert_eq!(read_when_written(&path, expected.len()), expected);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Waits for the background writer to put at least `len` bytes in `path`.
+fn read_when_written(path: &std::path::Path, len: usize) -> String {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    let mut written = String::new();
+    while Instant::now() < deadline {
+        written = fs::read_to_string(path).unwrap_or_default();
+        if written.len() >= len {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    written
+}
+
+#[test]
+fn rotated_files_are_reopened() {
+    let dir = std::env::temp_dir().join(format!("sheldx-access-log-rotated-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("access.log");
+    let access_logs = vec![AccessLogConfig { path: path.to_string_lossy().into_owned(), format: custom("{{status}}") }];
+
+    write_access_log(&entry(), &access_logs);
+    assert_eq!(read_when_written(&path, 4), "200\n");
+
+    // moved away, as logrotate does
+    let rotated = dir.join("access.log.1");
+    fs::rename(&path, &rotated).unwrap();
+    write_access_log(&entry(), &access_logs);
+    assert_eq!(read_when_written(&path, 4), "200\n");
+    assert_eq!(fs::read_to_string(&rotated).unwrap(), "200\n");
+
+    // and removed
+    fs::remove_file(&path).unwrap();
+    write_access_log(&entry(), &access_logs);
+    assert_eq!(read_when_written(&path, 4), "200\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,124 @@
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sheldx::server::{ spawn_admin_server, RateLimiterMap };
+use sheldx::services::{ unix_now, LimitParams, LimiterKey, LimiterStore };
+use sheldx::utils::{ default_configs, set_current_configs, AdminConfig, RateLimitAlgorithm };
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+use tokio::net::UnixStream;
+use tokio::time::timeout;
+
+const TOKEN: &str = "admin-secret";
+
+/// Serves the admin API on a socket of its own, returning the socket's path.
+async fn start(test: &str, store: RateLimiterMap) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sheldx-admin-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    let socket = dir.join("admin.sock");
+
+    set_current_configs(default_configs());
+    let config = AdminConfig {
+        enabled: true,
+        listen: "127.0.0.1:0".to_string(),
+        socket: Some(socket.to_string_lossy().into_owned()),
+        token: TOKEN.to_string(),
+    };
+    spawn_admin_server(config, store).await.unwrap();
+    socket
+}
+
+fn store() -> RateLimiterMap {
+    Arc::new(LimiterStore::new(1000, Duration::from_secs(300)))
+}
+
+/// Sends a request with the given `Authorization` header, returning the status and the body.
+async fn send(socket: &PathBuf, method: &str, path: &str, authorization: Option<&str>) -> (u16, String) {
+    let mut stream = UnixStream::connect(socket).await.unwrap();
+    let authorization = authorization.map(|value| format!("Authorization: {}\r\n", value)).unwrap_or_default();
+    let request = format!(
+        "{} {} HTTP/1.1\r\nHost: localhost\r\n{}Content-Length: 0\r\nConnection: close\r\n\r\n",
+        method,
+        path,
+        authorization
+    );
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    timeout(Duration::from_secs(5), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response.split_once("\r\n\r\n").unwrap().1.to_string();
+    (status, body)
+}
+
+async fn authorized(socket: &PathBuf, method: &str, path: &str) -> (u16, String) {
+    send(socket, method, path, Some(&format!("Bearer {}", TOKEN))).await
+}
+
+#[tokio::test]
+async fn requests_need_the_token() {
+    let socket = start("token", store()).await;
+
+    for authorization in [None, Some("Bearer wrong-secret"), Some("Bearer admin-secre"), Some(TOKEN)] {
+        for path in ["/config", "/nonexistent"] {
+            let (status, _) = send(&socket, "GET", path, authorization).await;
+            assert_eq!(status, 401, "{:?} for {}", authorization, path);
+        }
+    }
+    let (status, body) = authorized(&socket, "GET", "/config").await;
+    assert_eq!(status, 200);
+    assert!(!body.contains(TOKEN), "{}", body);
+}
+
+#[tokio::test]
+async fn unknown_methods_and_paths_are_refused() {
+    let socket = start("routes", store()).await;
+
+    assert_eq!(authorized(&socket, "GET", "/reload").await.0, 405);
+    assert_eq!(authorized(&socket, "DELETE", "/config").await.0, 405);
+    assert_eq!(authorized(&socket, "POST", "/listeners").await.0, 405);
+    assert_eq!(authorized(&socket, "GET", "/reloads").await.0, 404);
+    assert_eq!(authorized(&socket, "GET", "/").await.0, 404);
+}
+
+#[tokio::test]
+async fn only_configured_upstreams_are_drained() {
+    let socket = start("drain", store()).await;
+
+    let (status, body) = authorized(&socket, "POST", "/upstreams/drain?target=10.9.9.9:1").await;
+    assert_eq!(status, 404, "{}", body);
+    assert_eq!(authorized(&socket, "POST", "/upstreams/drain").await.0, 400);
+}
+
+#[tokio::test]
+async fn resets_empty_the_limiters_of_a_key() {
+    let store = store();
+    let socket = start("reset", store.clone()).await;
+    let params = LimitParams { limit: 1, window: Duration::from_secs(60), burst: 1 };
+    for (rule, key) in [("api", "ip:198.51.100.7"), ("login", "ip:198.51.100.7"), ("api", "ip:198.51.100.8")] {
+        let key = LimiterKey { rule: rule.to_string(), key: key.to_string() };
+        store.check(&key, RateLimitAlgorithm::TokenBucket, params, unix_now());
+    }
+
+    let (status, body) = authorized(&socket, "POST", "/ratelimit/reset?key=198.51.100.7").await;
+    assert_eq!(status, 200, "{}", body);
+    assert!(body.contains("\"limiters\":2"), "{}", body);
+    assert_eq!(store.len(), 1);
+    assert!(store.snapshot("ip:198.51.100.7", None, unix_now()).is_empty());
+    assert_eq!(authorized(&socket, "GET", "/ratelimit?key=198.51.100.7").await.0, 404);
+    // a bare IP is short for its `ip:` key
+    assert_eq!(authorized(&socket, "GET", "/ratelimit?key=198.51.100.8").await.0, 200);
+    assert_eq!(authorized(&socket, "GET", "/ratelimit?key=ip:198.51.100.8").await.0, 200);
+}
+
+#[tokio::test]
+async fn the_socket_is_only_open_to_its_owner() {
+    let socket = start("socket", store()).await;
+
+    let mode = fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+    fs::remove_dir_all(socket.parent().unwrap()).unwrap();
+}
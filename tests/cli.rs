@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::PathBuf;
+
+use clap::Parser;
+use log::LevelFilter;
+use sheldx::cli::{ run_cli, Cli, Command };
+use sheldx::utils::ConfigFormat;
+
+fn parse(args: &[&str]) -> Cli {
+    Cli::try_parse_from(std::iter::once("sheldx").chain(args.iter().copied())).unwrap()
+}
+
+fn command(args: &[&str]) -> Command {
+    parse(args).command.unwrap()
+}
+
+#[test]
+fn parses_every_command() {
+    assert!(parse(&[]).command.is_none());
+
+    let Command::Run(run) = command(&["run", "--log-level", "debug", "--http-port", "8080", "--https-port", "8443"]) else {
+        panic!("not run");
+    };
+    assert_eq!(run.log_level, Some(LevelFilter::Debug));
+    assert_eq!((run.http_port, run.https_port), (Some(8080), Some(8443)));
+    assert!(!run.create_default_config);
+    let Command::Run(run) = command(&["run", "--create-default-config"]) else {
+        panic!("not run");
+    };
+    assert!(run.create_default_config);
+
+    assert!(matches!(command(&["check-config"]), Command::CheckConfig(args) if !args.json));
+    assert!(matches!(command(&["check-config", "--json"]), Command::CheckConfig(args) if args.json));
+
+    assert!(
+        matches!(command(&["print-default-config"]), Command::PrintDefaultConfig(args) if args.format == ConfigFormat::Toml)
+    );
+    assert!(
+        matches!(command(&["print-default-config", "--format", "yaml"]), Command::PrintDefaultConfig(args) if args.format == ConfigFormat::Yaml)
+    );
+
+    let Command::Convert(convert) = command(&["convert", "main.conf", "-o", "main.yaml", "--to", "json"]) else {
+        panic!("not convert");
+    };
+    assert_eq!(convert.input, Some(PathBuf::from("main.conf")));
+    assert_eq!(convert.output, Some(PathBuf::from("main.yaml")));
+    assert_eq!(convert.to, Some(ConfigFormat::Json));
+    assert!(matches!(command(&["convert"]), Command::Convert(args) if args.input.is_none() && args.to.is_none()));
+
+    let Command::MigrateConfig(migrate) = command(&["migrate-config", "old.conf", "--write"]) else {
+        panic!("not migrate-config");
+    };
+    assert_eq!(migrate.input, Some(PathBuf::from("old.conf")));
+    assert!(migrate.write);
+
+    assert!(matches!(command(&["print-schema"]), Command::PrintSchema));
+    assert!(matches!(command(&["reload"]), Command::Reload));
+    assert!(matches!(command(&["status"]), Command::Status));
+    assert!(matches!(command(&["version"]), Command::Version));
+}
+
+#[test]
+fn global_options_go_before_or_after_the_command() {
+    for args in [
+        ["--config", "/tmp/sheldx.yaml", "--config-format", "yaml", "check-config"],
+        ["check-config", "--config", "/tmp/sheldx.yaml", "--config-format", "yaml"],
+    ] {
+        let cli = parse(&args);
+        assert_eq!(cli.config, Some(PathBuf::from("/tmp/sheldx.yaml")));
+        assert_eq!(cli.config_format, Some(ConfigFormat::Yaml));
+        assert!(matches!(cli.command, Some(Command::CheckConfig(_))));
+    }
+}
+
+#[test]
+fn rejects_bad_arguments() {
+    for args in [
+        vec!["frobnicate"],
+        vec!["run", "--http-port", "99999"],
+        vec!["run", "--log-level", "loud"],
+        vec!["print-default-config", "--format", "xml"],
+        vec!["check-config", "--write"],
+        vec!["--config"],
+    ] {
+        let parsed = Cli::try_parse_from(std::iter::once("sheldx").chain(args.iter().copied()));
+        assert!(parsed.is_err(), "{:?} was accepted", args);
+    }
+}
+
+/// The only test reading `SHELDX_CONFIG`, so setting it can't affect the others.
+#[test]
+fn the_config_flag_wins_over_the_environment() {
+    std::env::set_var("SHELDX_CONFIG", "/etc/sheldx/from-env.conf");
+    let from_env = parse(&["check-config"]).config;
+    let from_flag = parse(&["--config", "/etc/sheldx/from-flag.conf", "check-config"]).config;
+    std::env::remove_var("SHELDX_CONFIG");
+
+    assert_eq!(from_env, Some(PathBuf::from("/etc/sheldx/from-env.conf")));
+    assert_eq!(from_flag, Some(PathBuf::from("/etc/sheldx/from-flag.conf")));
+}
+
+/// The configuration path can be set once per process, so this is the only test that runs
+/// commands.
+#[tokio::test]
+async fn a_missing_config_file_is_not_created() {
+    let dir = std::env::temp_dir().join(format!("sheldx-cli-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("main.conf");
+    let config = path.to_string_lossy();
+
+    let checked = run_cli(parse(&["--config", &config, "check-config"])).await;
+    assert!(checked.is_err());
+    let ran = run_cli(parse(&["--config", &config, "run", "--http-port", "0"])).await;
+    assert!(ran.is_err());
+    assert!(!path.exists());
+    assert!(!dir.exists());
+}
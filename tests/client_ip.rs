@@ -0,0 +1,101 @@
+use std::net::IpAddr;
+
+use hyper::header::{ HeaderMap, HeaderName };
+use sheldx::services::resolve_client_ip;
+use sheldx::utils::IpRanges;
+
+const PEER: &str = "10.0.0.2";
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+fn strings(entries: &[&str]) -> Vec<String> {
+    entries.iter().map(|entry| entry.to_string()).collect()
+}
+
+fn headers(entries: &[(&str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in entries {
+        headers.append(HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+    }
+    headers
+}
+
+/// Resolves the client of a request from the trusted proxy [`PEER`].
+fn resolve(entries: &[(&str, &str)], sources: &[&str]) -> IpAddr {
+    let trusted = IpRanges::parse(&strings(&["10.0.0.0/8", "2001:db8:ffff::/48"]));
+    resolve_client_ip(&headers(entries), &ip(PEER), &trusted, &strings(sources))
+}
+
+fn resolve_xff(value: &str) -> IpAddr {
+    resolve(&[("x-forwarded-for", value)], &["x-forwarded-for"])
+}
+
+#[test]
+fn untrusted_peers_are_the_client() {
+    let trusted = IpRanges::parse(&strings(&["10.0.0.0/8"]));
+    let headers = headers(&[("x-forwarded-for", "198.51.100.1")]);
+
+    let resolved = resolve_client_ip(&headers, &ip("203.0.113.9"), &trusted, &strings(&["x-forwarded-for"]));
+    assert_eq!(resolved, ip("203.0.113.9"));
+
+    // IPv4 clients of a dual-stack listener are reported as IPv4
+    let resolved = resolve_client_ip(&headers, &ip("::ffff:203.0.113.9"), &trusted, &[]);
+    assert_eq!(resolved, ip("203.0.113.9"));
+}
+
+#[test]
+fn x_forwarded_for_is_walked_from_the_right() {
+    assert_eq!(resolve_xff("198.51.100.1, 203.0.113.9, 10.0.0.1"), ip("203.0.113.9"));
+    assert_eq!(resolve_xff("198.51.100.1:4711, [2001:db8::1]:8080"), ip("2001:db8::1"));
+    // repeated headers form one chain
+    let resolved = resolve(
+        &[("x-forwarded-for", "198.51.100.1"), ("x-forwarded-for", "10.0.0.1")],
+        &["x-forwarded-for"]
+    );
+    assert_eq!(resolved, ip("198.51.100.1"));
+}
+
+#[test]
+fn all_trusted_hops_give_the_furthest_one() {
+    assert_eq!(resolve_xff("10.0.0.5, 10.0.0.6"), ip("10.0.0.5"));
+}
+
+#[test]
+fn empty_or_malformed_chains_fall_back_to_the_peer() {
+    assert_eq!(resolve_xff(""), ip(PEER));
+    assert_eq!(resolve_xff(" , "), ip(PEER));
+    assert_eq!(resolve_xff("198.51.100.1, unknown"), ip(PEER));
+    // entries before a garbled one can't be trusted, the ones after it can
+    assert_eq!(resolve_xff("unknown, 203.0.113.9"), ip("203.0.113.9"));
+    assert_eq!(resolve(&[], &["x-forwarded-for"]), ip(PEER));
+    assert_eq!(resolve(&[("x-forwarded-for", "198.51.100.1")], &[]), ip(PEER));
+}
+
+#[test]
+fn forwarded_nodes_may_be_quoted_bracketed_and_carry_ports() {
+    let forwarded = |value| resolve(&[("forwarded", value)], &["forwarded"]);
+
+    assert_eq!(forwarded("for=192.0.2.60;proto=http;by=203.0.113.43"), ip("192.0.2.60"));
+    assert_eq!(forwarded("For=\"192.0.2.43:47011\""), ip("192.0.2.43"));
+    assert_eq!(forwarded("for=\"[2001:db8:cafe::17]:4711\""), ip("2001:db8:cafe::17"));
+    assert_eq!(forwarded("for=\"[2001:db8:cafe::17]\""), ip("2001:db8:cafe::17"));
+    assert_eq!(
+        forwarded("for=192.0.2.43, for=\"[2001:db8:cafe::17]\";proto=https, for=\"[2001:db8:ffff::1]\""),
+        ip("2001:db8:cafe::17")
+    );
+    // obfuscated and unknown nodes can't be resolved
+    assert_eq!(forwarded("for=_hidden"), ip(PEER));
+    assert_eq!(forwarded("for=unknown, for=10.0.0.1"), ip(PEER));
+    assert_eq!(forwarded("proto=https"), ip(PEER));
+}
+
+#[test]
+fn sources_are_tried_in_order() {
+    let entries = [("cf-connecting-ip", "garbage"), ("x-forwarded-for", "198.51.100.1")];
+    assert_eq!(resolve(&entries, &["CF-Connecting-IP", "x-forwarded-for"]), ip("198.51.100.1"));
+
+    let entries = [("cf-connecting-ip", "203.0.113.9"), ("x-forwarded-for", "198.51.100.1")];
+    assert_eq!(resolve(&entries, &["CF-Connecting-IP", "x-forwarded-for"]), ip("203.0.113.9"));
+}
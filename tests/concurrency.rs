@@ -0,0 +1,88 @@
+use std::time::Duration;
+
+use sheldx::services::{ acquire_upstream_slot, Saturation };
+use sheldx::utils::ConcurrencyConfig;
+
+const TARGET: &str = "127.0.0.1:8080";
+
+fn config(max_in_flight: usize, queue_size: usize, queue_timeout_ms: u64) -> ConcurrencyConfig {
+    ConcurrencyConfig { max_in_flight, queue_size, queue_timeout_ms }
+}
+
+/// Gives spawned requests time to join the queue.
+async fn settle() {
+    tokio::time::sleep(Duration::from_millis(50)).await;
+}
+
+#[tokio::test]
+async fn slots_are_released_when_dropped() {
+    let config = config(1, 0, 1000);
+
+    let slot = acquire_upstream_slot("release.example.com", TARGET, &config).await.unwrap();
+    let busy = acquire_upstream_slot("release.example.com", TARGET, &config).await;
+    assert_eq!(busy.err(), Some(Saturation::QueueFull));
+
+    drop(slot);
+    assert!(acquire_upstream_slot("release.example.com", TARGET, &config).await.is_ok());
+}
+
+#[tokio::test]
+async fn requests_beyond_the_queue_are_turned_away() {
+    let config = config(1, 1, 5000);
+
+    let slot = acquire_upstream_slot("queue-full.example.com", TARGET, &config).await.unwrap();
+    let waiting = {
+        let config = config.clone();
+        tokio::spawn(async move { acquire_upstream_slot("queue-full.example.com", TARGET, &config).await.is_ok() })
+    };
+    settle().await;
+
+    let turned_away = acquire_upstream_slot("queue-full.example.com", TARGET, &config).await;
+    assert_eq!(turned_away.err(), Some(Saturation::QueueFull));
+
+    // the queued request gets the slot once it is free
+    drop(slot);
+    assert!(waiting.await.unwrap());
+}
+
+#[tokio::test]
+async fn queued_requests_give_up_after_the_timeout() {
+    let config = config(1, 1, 50);
+
+    let slot = acquire_upstream_slot("queue-timeout.example.com", TARGET, &config).await.unwrap();
+    let waited = acquire_upstream_slot("queue-timeout.example.com", TARGET, &config).await;
+    assert_eq!(waited.err(), Some(Saturation::QueueTimeout));
+
+    // the request that timed out left the queue
+    let waiting = {
+        let config = config.clone();
+        tokio::spawn(async move { acquire_upstream_slot("queue-timeout.example.com", TARGET, &config).await.err() })
+    };
+    settle().await;
+    drop(slot);
+    assert_eq!(waiting.await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn rules_sharing_a_target_keep_their_own_slots() {
+    let strict = config(1, 0, 1000);
+    let loose = config(2, 0, 1000);
+
+    let _slot = acquire_upstream_slot("strict.example.com", TARGET, &strict).await.unwrap();
+    let _first = acquire_upstream_slot("loose.example.com", TARGET, &loose).await.unwrap();
+    let _second = acquire_upstream_slot("loose.example.com", TARGET, &loose).await.unwrap();
+
+    let busy = acquire_upstream_slot("strict.example.com", TARGET, &strict).await;
+    assert_eq!(busy.err(), Some(Saturation::QueueFull));
+}
+
+#[tokio::test]
+async fn a_changed_limit_starts_with_fresh_slots() {
+    let _slot = acquire_upstream_slot("reload.example.com", TARGET, &config(1, 0, 1000)).await.unwrap();
+
+    let raised = config(2, 0, 1000);
+    let _first = acquire_upstream_slot("reload.example.com", TARGET, &raised).await.unwrap();
+    let _second = acquire_upstream_slot("reload.example.com", TARGET, &raised).await.unwrap();
+    let busy = acquire_upstream_slot("reload.example.com", TARGET, &raised).await;
+    assert_eq!(busy.err(), Some(Saturation::QueueFull));
+}
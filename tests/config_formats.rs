@@ -0,0 +1,72 @@
+use sheldx::utils::{ default_config_text, parse_config_text, write_config_text, ConfigFormat };
+use toml::{ Table, Value };
+
+fn parse(text: &str, format: ConfigFormat) -> Table {
+    parse_config_text(text, format).unwrap_or_else(|(message, _)| panic!("{}", message)).0
+}
+
+#[test]
+fn yaml_fields_are_located() {
+    let text = "# café\nforwarding_rules:\n  - host: é.example.com\n    target: 127.0.0.1:3000\n";
+    let (_, positions) = parse_config_text(text, ConfigFormat::Yaml).unwrap();
+
+    let at = |field: &str| positions.span(field).map(|span| &text[span.start..]);
+    assert!(at("forwarding_rules").unwrap().starts_with("forwarding_rules:"));
+    assert!(at("forwarding_rules[0].target").unwrap().starts_with("target:"));
+    // fields that aren't written fall back to their parent, on the line the rule starts
+    let line = |field: &str| positions.span(field).map(|span| text[..span.start].matches('\n').count() + 1);
+    assert_eq!(line("forwarding_rules[0].concurrency.max_in_flight"), Some(3));
+    assert_eq!(line("forwarding_rules[1].host"), Some(2));
+}
+
+#[test]
+fn yaml_aliases_repeat_their_anchor() {
+    let table = parse(
+        "first:\n  excluded: &office\n    - 10.0.0.0/8\n    - 192.168.0.0/16\nsecond:\n  excluded: *office\n",
+        ConfigFormat::Yaml
+    );
+    assert_eq!(table["second"]["excluded"], table["first"]["excluded"]);
+    assert_eq!(table["second"]["excluded"].as_array().unwrap().len(), 2);
+
+    assert!(parse_config_text("first: *missing\n", ConfigFormat::Yaml).is_err());
+}
+
+#[test]
+fn yaml_keys_must_be_scalars() {
+    let Err((message, span)) = parse_config_text("version: 2\n? [a, b]\n: 1\n", ConfigFormat::Yaml) else {
+        panic!("a list was accepted as a key");
+    };
+    assert!(message.contains("keys"), "{}", message);
+    assert!(span.is_some());
+}
+
+#[test]
+fn yaml_values_keep_their_types() {
+    let table = parse("port: 8080\nquoted: \"8080\"\nratio: 0.5\nenabled: true\nunset: ~\n", ConfigFormat::Yaml);
+    assert_eq!(table["port"], Value::Integer(8080));
+    assert_eq!(table["quoted"], Value::String("8080".to_string()));
+    assert_eq!(table["ratio"], Value::Float(0.5));
+    assert_eq!(table["enabled"], Value::Boolean(true));
+    // a null field is left out
+    assert!(!table.contains_key("unset"));
+}
+
+#[test]
+fn converting_to_yaml_and_back_keeps_every_value() {
+    let mut original = parse(&default_config_text(ConfigFormat::Toml).unwrap(), ConfigFormat::Toml);
+    // strings that would read as other types if they weren't quoted
+    original.insert("version_string".to_string(), Value::String("2".to_string()));
+    original.insert("flag_string".to_string(), Value::String("true".to_string()));
+    original.insert("empty".to_string(), Value::String(String::new()));
+    original.insert("ratio".to_string(), Value::Float(0.25));
+
+    let yaml = write_config_text(&original, ConfigFormat::Yaml).unwrap();
+    let from_yaml = parse(&yaml, ConfigFormat::Yaml);
+    assert_eq!(from_yaml, original, "{}", yaml);
+
+    let toml = write_config_text(&from_yaml, ConfigFormat::Toml).unwrap();
+    assert_eq!(parse(&toml, ConfigFormat::Toml), original);
+
+    let json = write_config_text(&original, ConfigFormat::Json).unwrap();
+    assert_eq!(parse(&json, ConfigFormat::Json), original);
+}
@@ -0,0 +1,118 @@
+use sheldx::utils::{ migrate_config, migrate_config_text, CONFIG_VERSION };
+use toml::Table;
+
+const V1: &str = r#"# SheldX configuration
+cert_path = ""
+key_path = ""
+is_tls_enabled = false
+show_logs_on_console = true
+# Seconds to wait for an upstream connection
+connection_timeout = 5 # generous, the backends are slow
+max_retries = 3
+
+[[rate_limit_rules]]
+host = "api.example.com"   # public API
+limit = 10
+duration = 60
+max_tokens = 10
+excluded_paths = []
+excluded_ip_list = []
+strategy = "HashMap" # kept in memory
+
+[[rate_limit_rules]]
+host = "example.com"
+limit = 5
+duration = 60
+max_tokens = 5
+excluded_paths = []
+excluded_ip_list = []
+strategy = "Redis"
+"#;
+
+fn fields(warnings: &[sheldx::utils::ConfigDiagnostic]) -> Vec<String> {
+    warnings
+        .iter()
+        .map(|warning| warning.field.clone().unwrap_or_default())
+        .collect()
+}
+
+#[test]
+fn version_1_tables_are_upgraded() {
+    let mut table: Table = V1.parse().unwrap();
+    let warnings = migrate_config(&mut table).unwrap();
+
+    assert_eq!(fields(&warnings), [
+        "connection_timeout",
+        "max_retries",
+        "rate_limit_rules[0].strategy",
+        "rate_limit_rules[1].strategy",
+        "version",
+    ]);
+    assert_eq!(table["version"].as_integer(), Some(CONFIG_VERSION as i64));
+    assert!(!table.contains_key("connection_timeout"));
+    assert_eq!(table["upstream"]["connect_timeout"].as_integer(), Some(5));
+    assert_eq!(table["upstream"]["max_retries"].as_integer(), Some(3));
+    assert_eq!(table["rate_limit_rules"][0]["strategy"].as_str(), Some("local"));
+    assert_eq!(table["rate_limit_rules"][1]["strategy"].as_str(), Some("redis"));
+}
+
+#[test]
+fn version_1_files_are_rewritten_with_their_comments() {
+    let (migrated, warnings) = migrate_config_text(V1).unwrap();
+
+    assert_eq!(fields(&warnings), [
+        "connection_timeout",
+        "max_retries",
+        "rate_limit_rules[0].strategy",
+        "rate_limit_rules[1].strategy",
+    ]);
+    assert!(migrated.starts_with("# SheldX configuration\ncert_path = \"\"\n"));
+    assert!(migrated.contains("show_logs_on_console = true\nversion = 2\n"));
+    assert!(migrated.contains("host = \"api.example.com\"   # public API\n"));
+    assert!(migrated.contains("strategy = \"local\" # kept in memory\n"));
+    assert!(migrated.contains("strategy = \"redis\"\n"));
+    assert!(
+        migrated.ends_with(
+            "[upstream]\n# Seconds to wait for an upstream connection\nconnect_timeout = 5 # generous, the backends are slow\nmax_retries = 3\n"
+        ),
+        "{}",
+        migrated
+    );
+
+    // the rewritten file reads the same as the upgraded table
+    let mut upgraded: Table = V1.parse().unwrap();
+    migrate_config(&mut upgraded).unwrap();
+    assert_eq!(migrated.parse::<Table>().unwrap(), upgraded);
+
+    // and upgrading it again changes nothing
+    let (again, warnings) = migrate_config_text(&migrated).unwrap();
+    assert_eq!(again, migrated);
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn existing_upstream_settings_are_kept() {
+    let text = "version = 1\nconnection_timeout = 5\n\n[upstream]\nconnect_timeout = 2\n";
+    let (migrated, warnings) = migrate_config_text(text).unwrap();
+
+    assert_eq!(fields(&warnings), ["connection_timeout"]);
+    assert_eq!(migrated, "version = 2\n\n[upstream]\nconnect_timeout = 2\n");
+}
+
+#[test]
+fn current_files_are_left_unchanged() {
+    let text = format!("version = {}  # current\n\n[upstream]\nconnect_timeout  =  2\n", CONFIG_VERSION);
+    let (migrated, warnings) = migrate_config_text(&text).unwrap();
+
+    assert_eq!(migrated, text);
+    assert!(warnings.is_empty());
+}
+
+#[test]
+fn unsupported_versions_are_rejected() {
+    let newer = migrate_config_text(&format!("version = {}\n", CONFIG_VERSION + 1)).unwrap_err();
+    assert_eq!(fields(&newer), ["version"]);
+    assert!(migrate_config_text("version = \"2\"\n").is_err());
+    assert!(migrate_config_text("version = 0\n").is_err());
+    assert!(migrate_config_text("version = \n").is_err());
+}
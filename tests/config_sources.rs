@@ -0,0 +1,113 @@
+use std::fs;
+use std::path::{ Path, PathBuf };
+
+use sheldx::utils::{ read_config_sources, ConfigDiagnostic, MergedConfig };
+
+/// Writes `files` to a directory of their own and returns the path of the first one.
+fn write_files(test: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sheldx-config-sources-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    for (name, text) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, text).unwrap();
+    }
+    dir.join(files[0].0)
+}
+
+fn read(test: &str, files: &[(&str, &str)]) -> MergedConfig {
+    read_config_sources(&write_files(test, files)).unwrap_or_else(|errors| panic!("{:?}", errors))
+}
+
+fn errors(test: &str, files: &[(&str, &str)]) -> Vec<ConfigDiagnostic> {
+    match read_config_sources(&write_files(test, files)) {
+        Ok(_) => panic!("{} was accepted", test),
+        Err(errors) => errors,
+    }
+}
+
+fn hosts(merged: &MergedConfig) -> Vec<&str> {
+    merged.table["forwarding_rules"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|rule| rule["host"].as_str().unwrap())
+        .collect()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
+
+fn rule(host: &str) -> String {
+    format!("[[forwarding_rules]]\nhost = \"{}\"\ntarget = \"127.0.0.1:3000\"\n", host)
+}
+
+#[test]
+fn includes_are_merged_in_order() {
+    let main = format!("include = [\"conf.d/*.toml\", \"extra.toml\"]\n\n[logging]\nlevel = \"info\"\n\n{}", rule("main"));
+    let merged = read("order", &[
+        ("main.conf", &main),
+        ("conf.d/b.toml", &rule("b")),
+        ("conf.d/a.toml", &rule("a")),
+        ("conf.d/c.yaml", "forwarding_rules:\n  - host: c\n    target: 127.0.0.1:3000\n"),
+        ("extra.toml", &format!("{}\n[logging]\nfile = \"/var/log/sheldx.log\"\n", rule("extra"))),
+    ]);
+
+    // the main file, then each pattern's matches sorted by name
+    let names: Vec<String> = merged.sources.iter().map(|source| file_name(&source.path)).collect();
+    assert_eq!(names, ["main.conf", "a.toml", "b.toml", "extra.toml"]);
+    assert_eq!(hosts(&merged), ["main", "a", "b", "extra"]);
+    // tables are merged key by key
+    assert_eq!(merged.table["logging"]["level"].as_str(), Some("info"));
+    assert_eq!(merged.table["logging"]["file"].as_str(), Some("/var/log/sheldx.log"));
+}
+
+#[test]
+fn patterns_may_match_nothing_but_files_must_exist() {
+    let merged = read("nothing", &[("main.conf", "include = [\"conf.d/*.toml\"]\n")]);
+    assert_eq!(merged.sources.len(), 1);
+
+    let errors = errors("missing", &[("main.conf", "show_logs_on_console = false\ninclude = [\"missing.toml\"]\n")]);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].field.as_deref(), Some("include[0]"));
+    assert_eq!(file_name(errors[0].file.as_ref().unwrap()), "main.conf");
+    assert_eq!(errors[0].line, Some(2));
+    assert!(errors[0].message.contains("missing.toml"), "{}", errors[0].message);
+}
+
+#[test]
+fn conflicting_values_are_reported_where_they_are_repeated() {
+    let errors = errors("conflict", &[
+        ("main.conf", "include = [\"other.toml\"]\nshow_logs_on_console = false\n"),
+        ("other.toml", "# set again\n\nshow_logs_on_console = true\n"),
+    ]);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].field.as_deref(), Some("show_logs_on_console"));
+    assert_eq!(file_name(errors[0].file.as_ref().unwrap()), "other.toml");
+    assert_eq!(errors[0].line, Some(3));
+    assert!(errors[0].message.contains("main.conf:2:"), "{}", errors[0].message);
+}
+
+#[test]
+fn equal_values_may_be_repeated() {
+    let merged = read("equal", &[
+        ("main.conf", "include = [\"other.toml\"]\nshow_logs_on_console = false\n"),
+        ("other.toml", "show_logs_on_console = false\n"),
+    ]);
+    assert_eq!(merged.table["show_logs_on_console"].as_bool(), Some(false));
+    assert_eq!(merged.sources.len(), 2);
+}
+
+#[test]
+fn included_files_cannot_include_others() {
+    let errors = errors("nested", &[
+        ("main.conf", "include = [\"other.toml\"]\n"),
+        ("other.toml", "show_logs_on_console = false\ninclude = [\"third.toml\"]\n"),
+        ("third.toml", &rule("third")),
+    ]);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].field.as_deref(), Some("include"));
+    assert_eq!(file_name(errors[0].file.as_ref().unwrap()), "other.toml");
+    assert_eq!(errors[0].line, Some(2));
+}
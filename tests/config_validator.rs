@@ -0,0 +1,328 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{ AtomicUsize, Ordering };
+
+use sheldx::server::reload_configs;
+use sheldx::utils::{ current_configs, set_config_path, validate_config_file, ConfigDiagnostic, Severity };
+
+const BASE: &str = r#"
+version = 2
+cert_path = ""
+key_path = ""
+is_tls_enabled = false
+show_logs_on_console = false
+"#;
+
+const RATE_LIMIT_RULE: &str = r#"
+[[rate_limit_rules]]
+host = "example.com"
+limit = 10
+duration = 60
+max_tokens = 10
+excluded_paths = []
+excluded_ip_list = []
+strategy = "local"
+"#;
+
+fn dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sheldx-config-validator-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Writes `extra` after the required settings to a new file and returns its path.
+fn write_config(extra: &str) -> PathBuf {
+    static FILES: AtomicUsize = AtomicUsize::new(0);
+    let path = dir().join(format!("sheldx-{}.toml", FILES.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&path, format!("{}{}", BASE, extra)).unwrap();
+    path
+}
+
+fn errors(extra: &str) -> Vec<ConfigDiagnostic> {
+    validate_config_file(&write_config(extra))
+        .diagnostics.into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .collect()
+}
+
+fn error_fields(extra: &str) -> Vec<String> {
+    errors(extra)
+        .into_iter()
+        .map(|diagnostic| diagnostic.field.unwrap_or_default())
+        .collect()
+}
+
+#[test]
+fn a_minimal_configuration_is_valid() {
+    assert_eq!(error_fields(""), Vec::<String>::new());
+    assert_eq!(error_fields(RATE_LIMIT_RULE), Vec::<String>::new());
+}
+
+#[test]
+fn tls_needs_existing_certificate_files() {
+    let path = write_config("");
+    let text = fs::read_to_string(&path)
+        .unwrap()
+        .replace("is_tls_enabled = false", "is_tls_enabled = true")
+        .replace("key_path = \"\"", "key_path = \"/nonexistent/sheldx.key\"");
+    fs::write(&path, text).unwrap();
+
+    let fields: Vec<String> = validate_config_file(&path)
+        .diagnostics.into_iter()
+        .filter_map(|diagnostic| diagnostic.field)
+        .collect();
+    assert_eq!(fields, ["cert_path", "key_path"]);
+}
+
+#[test]
+fn forwarding_rules_are_checked() {
+    let fields = error_fields(
+        r#"
+[[forwarding_rules]]
+host = "example.com"
+target = "127.0.0.1:3000"
+
+[[forwarding_rules]]
+host = "Example.com."
+target = "localhost:3000"
+
+[[forwarding_rules]]
+host = ""
+target = "not a target"
+
+[[forwarding_rules]]
+host = "api.example.com"
+target = "127.0.0.1:3001"
+concurrency = { max_in_flight = 0, queue_size = 10, queue_timeout_ms = 0 }
+"#
+    );
+    assert_eq!(fields, [
+        "forwarding_rules[1].host",
+        "forwarding_rules[2].host",
+        "forwarding_rules[2].target",
+        "forwarding_rules[3].concurrency.max_in_flight",
+        "forwarding_rules[3].concurrency.queue_timeout_ms",
+    ]);
+
+    // the duplicate points at the rule that shadows it
+    let duplicate = &errors("[[forwarding_rules]]\nhost = \"a\"\ntarget = \"a:1\"\n[[forwarding_rules]]\nhost = \"A\"\ntarget = \"a:1\"\n")[0];
+    assert!(duplicate.message.ends_with(".toml:8:8)"), "{}", duplicate.message);
+}
+
+#[test]
+fn rate_limit_rules_are_checked() {
+    let rule = |overrides: &str| format!("{}{}", RATE_LIMIT_RULE.replace("limit = 10\n", ""), overrides);
+
+    // unnamed rules for one host are numbered, names must be unique
+    assert_eq!(error_fields(&format!("{}{}", RATE_LIMIT_RULE, RATE_LIMIT_RULE)), Vec::<String>::new());
+    let named = RATE_LIMIT_RULE.replace("host = ", "name = \"api\"\nhost = ");
+    assert_eq!(error_fields(&format!("{}{}", named, named)), ["rate_limit_rules[1].name"]);
+    assert_eq!(error_fields(&rule("limit = 10\nkey = []\n")), ["rate_limit_rules[0].key"]);
+    assert_eq!(
+        error_fields(&rule("limit = 10\nkey = [\"global\", \"ip\"]\n")),
+        ["rate_limit_rules[0].key"]
+    );
+    assert_eq!(error_fields(&rule("limit = 0\n")), ["rate_limit_rules[0].limit"]);
+    assert_eq!(error_fields(&rule("limit = 20\n")), ["rate_limit_rules[0].max_tokens"]);
+    assert_eq!(
+        error_fields(&rule("limit = 10\n").replace("duration = 60", "duration = 0")),
+        ["rate_limit_rules[0].duration"]
+    );
+    assert_eq!(
+        error_fields(&rule("limit = 10\n").replace("duration = 60", "duration = 9223372036854775807")),
+        ["rate_limit_rules[0].duration"]
+    );
+    assert_eq!(
+        error_fields(&rule("limit = 10\n").replace("excluded_ip_list = []", "excluded_ip_list = [\"10.0.0.0/8\", \"10.0.0\"]")),
+        ["rate_limit_rules[0].excluded_ip_list[1]"]
+    );
+}
+
+#[test]
+fn ip_lists_are_checked() {
+    let fields = error_fields(
+        r#"
+trusted_proxies = ["10.0.0.1", "localhost"]
+
+[[ip_access_rules]]
+host = "example.com"
+allow = ["192.168.0.0/16", "192.168.0.0/33"]
+deny = ["nope"]
+
+[[ip_access_rules]]
+host = "EXAMPLE.com"
+"#
+    );
+    assert_eq!(fields, [
+        "ip_access_rules[0].allow[1]",
+        "ip_access_rules[0].deny[0]",
+        "ip_access_rules[1].host",
+        "trusted_proxies[1]",
+    ]);
+}
+
+#[test]
+fn listeners_and_clients_are_checked() {
+    let fields = error_fields(
+        r#"
+[metrics]
+listen = "9100"
+
+[admin]
+token = ""
+listen = "localhost"
+
+[redis]
+url = "ftp://localhost"
+timeout_ms = 0
+"#
+    );
+    assert_eq!(fields, ["metrics.listen", "redis.url", "redis.timeout_ms", "admin.token", "admin.listen"]);
+
+    // a disabled listener isn't checked, and a socket replaces the address
+    assert_eq!(error_fields("[metrics]\nenabled = false\nlisten = \"9100\"\n"), Vec::<String>::new());
+    assert_eq!(
+        error_fields("[admin]\ntoken = \"secret\"\nlisten = \"\"\nsocket = \"/run/sheldx.sock\"\n"),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn zero_limits_are_rejected() {
+    let fields = error_fields(
+        r#"
+[upstream]
+connect_timeout = 0
+max_retries = 0
+
+[rate_limiter]
+max_entries = 0
+idle_timeout = 0
+
+[connection_limits]
+max_connections = 0
+max_connections_per_ip = 0
+
+[request_limits]
+header_read_timeout = 0
+keep_alive_timeout = 0
+max_header_size = 8191
+max_headers = 0
+request_timeout = 0
+"#
+    );
+    assert_eq!(fields, [
+        "upstream.connect_timeout",
+        "upstream.max_retries",
+        "rate_limiter.max_entries",
+        "rate_limiter.idle_timeout",
+        "connection_limits.max_connections",
+        "connection_limits.max_connections_per_ip",
+        "request_limits.header_read_timeout",
+        "request_limits.keep_alive_timeout",
+        "request_limits.max_header_size",
+        "request_limits.max_headers",
+        "request_limits.request_timeout",
+    ]);
+}
+
+#[test]
+fn errors_point_at_the_offending_line() {
+    let errors = errors(&RATE_LIMIT_RULE.replace("limit = 10", "limit = 0"));
+    assert_eq!(errors.len(), 1);
+    // BASE and the rule each start with a blank line
+    assert_eq!(errors[0].line, Some(10));
+    assert!(errors[0].file.is_some());
+}
+
+#[test]
+fn an_invalid_configuration_is_not_loaded() {
+    let path = dir().join("reloaded.toml");
+    fs::write(&path, format!("{}{}", BASE, RATE_LIMIT_RULE)).unwrap();
+    set_config_path(path.clone());
+
+    let loaded = reload_configs().unwrap();
+    assert_eq!(loaded.rate_limit_rules.as_ref().unwrap()[0].limit, 10);
+
+    fs::write(&path, format!("{}{}", BASE, RATE_LIMIT_RULE.replace("limit = 10", "limit = 0"))).unwrap();
+    assert!(reload_configs().is_err());
+
+    let current = current_configs().unwrap();
+    assert!(std::sync::Arc::ptr_eq(&loaded, &current));
+}
+
+#[test]
+fn toml_and_json_errors_point_at_the_offending_line() {
+    let errors = errors("[[forwarding_rules]]\nhost = \"example.com\"\ntarget = 3000\n");
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].field.as_deref(), errors[0].line), (Some("forwarding_rules[0].target"), Some(9)));
+
+    let path = dir().join("sheldx.json");
+    fs::write(
+        &path,
+        r#"{
+  "version": 2,
+  "cert_path": "",
+  "key_path": "",
+  "is_tls_enabled": false,
+  "show_logs_on_console": false,
+  "rate_limit_rules": [
+    { "host": "example.com", "limit": 0, "duration": 60, "max_tokens": 10,
+      "excluded_paths": [], "excluded_ip_list": [], "strategy": "local" }
+  ]
+}
+"#
+    ).unwrap();
+    let errors: Vec<ConfigDiagnostic> = validate_config_file(&path)
+        .diagnostics.into_iter()
+        .filter(|diagnostic| diagnostic.severity == Severity::Error)
+        .collect();
+    assert_eq!(errors.len(), 1);
+    assert_eq!((errors[0].field.as_deref(), errors[0].line), (Some("rate_limit_rules[0].limit"), Some(8)));
+}
+
+#[test]
+fn yaml_errors_point_at_the_offending_line() {
+    let path = dir().join("sheldx.yaml");
+    let text = r#"# Réglages généraux
+version: 2
+cert_path: ""
+key_path: ""
+is_tls_enabled: false
+show_logs_on_console: false
+forwarding_rules:
+  - host: example.com
+    target: 127.0.0.1:3000
+  - host: "例え.example.com"
+    target: 3000
+rate_limit_rules:
+  - host: example.com
+    limit: 0
+    duration: 60
+    max_tokens: 10
+    excluded_paths: []
+    excluded_ip_list: []
+    strategy: local
+"#;
+    let yaml_errors = |text: &str| -> Vec<ConfigDiagnostic> {
+        fs::write(&path, text).unwrap();
+        validate_config_file(&path)
+            .diagnostics.into_iter()
+            .filter(|diagnostic| diagnostic.severity == Severity::Error)
+            .collect()
+    };
+
+    let errors = yaml_errors(text);
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert_eq!((errors[0].field.as_deref(), errors[0].line), (Some("forwarding_rules[1].target"), Some(11)));
+    let errors = yaml_errors(&text.replace("target: 3000", "target: 127.0.0.1:3001"));
+    assert_eq!(errors.len(), 1, "{:?}", errors);
+    assert_eq!((errors[0].field.as_deref(), errors[0].line), (Some("rate_limit_rules[0].limit"), Some(14)));
+
+    // a YAML syntax error is placed too
+    let path = dir().join("broken.yaml");
+    fs::write(&path, "version: 2\nforwarding_rules:\n  - host: [example.com\n").unwrap();
+    let errors = validate_config_file(&path).diagnostics;
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].line.is_some_and(|line| line >= 3), "{:?}", errors);
+}
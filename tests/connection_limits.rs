@@ -0,0 +1,114 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use sheldx::services::{ apply_connection_limits, connections_from_ip, ConnectionRefusal, ListenerConnections };
+use sheldx::utils::{ default_configs, ConnectionLimitsConfig, ConnectionOverflow, Configs };
+
+/// Every test runs with these limits, as they are shared by the whole process; each uses its
+/// own listener and client IPs instead.
+fn apply_limits() {
+    apply_connection_limits(
+        &(Configs {
+            connection_limits: Some(ConnectionLimitsConfig {
+                max_connections: Some(2),
+                max_connections_per_ip: Some(1),
+                when_full: ConnectionOverflow::Delay,
+            }),
+            trusted_proxies: Some(vec!["10.0.0.0/8".to_string()]),
+            ..default_configs()
+        })
+    );
+}
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+#[test]
+fn clients_are_limited_over_every_listener() {
+    apply_limits();
+    let (first, second) = (ListenerConnections::new("first"), ListenerConnections::new("second"));
+    let client = ip("198.51.100.1");
+
+    let permit = first.admit(&client).unwrap();
+    assert_eq!(connections_from_ip(&client), Some(1));
+    assert_eq!(second.admit(&client).err(), Some(ConnectionRefusal::PerIpLimit));
+    // IPv4 clients of a dual-stack listener count as themselves
+    assert_eq!(second.admit(&ip("::ffff:198.51.100.1")).err(), Some(ConnectionRefusal::PerIpLimit));
+    // refused connections aren't counted
+    assert_eq!(connections_from_ip(&client), Some(1));
+
+    drop(permit);
+    assert_eq!(connections_from_ip(&client), None);
+    assert!(second.admit(&client).is_ok());
+}
+
+#[test]
+fn full_listeners_refuse_without_counting_the_client() {
+    apply_limits();
+    let listener = ListenerConnections::new("full");
+
+    let _first = listener.admit(&ip("198.51.100.2")).unwrap();
+    let _second = listener.admit(&ip("198.51.100.3")).unwrap();
+    assert_eq!(listener.admit(&ip("198.51.100.4")).err(), Some(ConnectionRefusal::ListenerFull));
+    assert_eq!(connections_from_ip(&ip("198.51.100.4")), None);
+
+    // other listeners have room of their own
+    assert!(ListenerConnections::new("other").admit(&ip("198.51.100.4")).is_ok());
+}
+
+#[test]
+fn trusted_proxies_are_not_limited_per_ip() {
+    apply_limits();
+    let listener = ListenerConnections::new("proxies");
+    let proxy = ip("10.1.2.3");
+
+    let _first = listener.admit(&proxy).unwrap();
+    let _second = listener.admit(&proxy).unwrap();
+    assert_eq!(connections_from_ip(&proxy), None);
+    // the listener limit still applies
+    assert_eq!(listener.admit(&proxy).err(), Some(ConnectionRefusal::ListenerFull));
+}
+
+#[test]
+fn dropped_permits_remove_their_client_entries() {
+    apply_limits();
+    let listener = ListenerConnections::new("entries");
+    let clients = [ip("198.51.100.5"), ip("2001:db8::5")];
+
+    let permits: Vec<_> = clients
+        .iter()
+        .map(|client| listener.admit(client).unwrap())
+        .collect();
+    for client in &clients {
+        assert_eq!(connections_from_ip(client), Some(1));
+    }
+
+    drop(permits);
+    for client in &clients {
+        assert_eq!(connections_from_ip(client), None);
+    }
+}
+
+#[tokio::test]
+async fn waiting_listeners_wake_when_a_connection_closes() {
+    apply_limits();
+    let listener = ListenerConnections::new("waiting");
+
+    // room to spare, so there is no wait
+    listener.wait_for_room().await;
+
+    let first = listener.admit(&ip("198.51.100.6")).unwrap();
+    let _second = listener.admit(&ip("198.51.100.7")).unwrap();
+    let waiting = {
+        let listener = Arc::clone(&listener);
+        tokio::spawn(async move { listener.wait_for_room().await })
+    };
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!waiting.is_finished());
+
+    // woken by the close, well before the listener would look at its limit again
+    drop(first);
+    tokio::time::timeout(Duration::from_millis(500), waiting).await.unwrap().unwrap();
+}
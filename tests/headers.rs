@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+use hyper::header::{ HeaderMap, HeaderName };
+use sheldx::server::ConnectionInfo;
+use sheldx::services::{
+    apply_forwarding_headers,
+    apply_header_rules,
+    render_template,
+    strip_hop_by_hop_headers,
+    HeaderVars,
+};
+use sheldx::utils::{ HeaderRules, RequestLimitsConfig };
+
+fn headers(entries: &[(&str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in entries {
+        headers.append(HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+    }
+    headers
+}
+
+fn values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+    headers
+        .get_all(name)
+        .iter()
+        .map(|value| value.to_str().unwrap())
+        .collect()
+}
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+fn connection(peer_ip: &str, is_tls: bool) -> ConnectionInfo {
+    ConnectionInfo {
+        peer_ip: ip(peer_ip),
+        is_tls,
+        tls_version: None,
+        tls_cipher: None,
+        tls_sni: None,
+        request_limits: RequestLimitsConfig::default(),
+    }
+}
+
+fn spoofed_headers() -> HeaderMap {
+    headers(&[
+        ("x-forwarded-for", "198.51.100.1"),
+        ("x-forwarded-for", "198.51.100.2"),
+        ("x-forwarded-proto", "https"),
+        ("x-forwarded-host", "origin.example.com"),
+        ("x-real-ip", "198.51.100.3"),
+        ("forwarded", "for=198.51.100.1;proto=https"),
+    ])
+}
+
+fn map(entries: &[(&str, &str)]) -> HashMap<String, String> {
+    entries.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+}
+
+#[test]
+fn header_rules_remove_then_set_then_add() {
+    let mut headers = headers(&[("x-old", "1"), ("x-set", "a"), ("x-set", "b"), ("x-add", "first")]);
+    let rules = HeaderRules {
+        remove: vec!["x-old".to_string(), "x-missing".to_string()],
+        set: map(&[("x-set", "replaced"), ("x-client", "{{client_ip}} via {{scheme}}")]),
+        add: map(&[("x-add", "second")]),
+    };
+    let vars: HeaderVars = [("client_ip", "203.0.113.7".to_string()), ("scheme", "https".to_string())].into();
+
+    apply_header_rules(&mut headers, &rules, &vars);
+
+    assert!(!headers.contains_key("x-old"));
+    assert_eq!(values(&headers, "x-set"), ["replaced"]);
+    assert_eq!(values(&headers, "x-add"), ["first", "second"]);
+    assert_eq!(values(&headers, "x-client"), ["203.0.113.7 via https"]);
+}
+
+#[test]
+fn header_rules_skip_invalid_names_and_values() {
+    let mut headers = HeaderMap::new();
+    let rules = HeaderRules {
+        remove: vec![],
+        set: map(&[("bad name", "value"), ("x-bad-value", "line\nbreak"), ("x-good", "ok")]),
+        add: HashMap::new(),
+    };
+
+    apply_header_rules(&mut headers, &rules, &HeaderVars::new());
+
+    assert_eq!(headers.len(), 1);
+    assert_eq!(values(&headers, "x-good"), ["ok"]);
+}
+
+#[test]
+fn strips_hop_by_hop_headers_and_those_named_in_connection() {
+    let mut headers = headers(&[
+        ("connection", "keep-alive, X-Session-Hop"),
+        ("connection", " ,close"),
+        ("keep-alive", "timeout=5"),
+        ("transfer-encoding", "chunked"),
+        ("upgrade", "websocket"),
+        ("proxy-authorization", "Basic Zm9vOmJhcg=="),
+        ("x-session-hop", "1"),
+        ("x-end-to-end", "kept"),
+        ("content-type", "text/plain"),
+    ]);
+
+    strip_hop_by_hop_headers(&mut headers);
+
+    let mut remaining: Vec<&str> = headers.keys().map(|name| name.as_str()).collect();
+    remaining.sort();
+    assert_eq!(remaining, ["content-type", "x-end-to-end"]);
+}
+
+#[test]
+fn untrusted_peers_have_their_forwarding_headers_replaced() {
+    let mut headers = spoofed_headers();
+    let conn = connection("203.0.113.9", false);
+
+    apply_forwarding_headers(&mut headers, &conn, &ip("203.0.113.9"), "www.example.com", false);
+
+    assert_eq!(values(&headers, "x-forwarded-for"), ["203.0.113.9"]);
+    assert_eq!(values(&headers, "x-forwarded-proto"), ["http"]);
+    assert_eq!(values(&headers, "x-forwarded-host"), ["www.example.com"]);
+    assert_eq!(values(&headers, "x-real-ip"), ["203.0.113.9"]);
+    assert_eq!(values(&headers, "forwarded"), ["for=203.0.113.9;proto=http;host=www.example.com"]);
+}
+
+#[test]
+fn trusted_peers_have_this_hop_appended() {
+    let mut headers = spoofed_headers();
+    let conn = connection("::ffff:10.0.0.2", true);
+
+    apply_forwarding_headers(&mut headers, &conn, &ip("198.51.100.2"), "www.example.com:8443", true);
+
+    assert_eq!(values(&headers, "x-forwarded-for"), ["198.51.100.1, 198.51.100.2, 10.0.0.2"]);
+    assert_eq!(values(&headers, "x-forwarded-proto"), ["https"]);
+    assert_eq!(values(&headers, "x-forwarded-host"), ["origin.example.com"]);
+    assert_eq!(values(&headers, "x-real-ip"), ["198.51.100.2"]);
+    assert_eq!(
+        values(&headers, "forwarded"),
+        ["for=198.51.100.1;proto=https, for=10.0.0.2;proto=https;host=\"www.example.com:8443\""]
+    );
+}
+
+#[test]
+fn forwarded_quotes_ipv6_nodes() {
+    let mut headers = HeaderMap::new();
+    let conn = connection("2001:db8::7", false);
+
+    apply_forwarding_headers(&mut headers, &conn, &ip("2001:db8::7"), "www.example.com", true);
+
+    assert_eq!(values(&headers, "x-forwarded-for"), ["2001:db8::7"]);
+    assert_eq!(values(&headers, "forwarded"), ["for=\"[2001:db8::7]\";proto=http;host=www.example.com"]);
+}
+
+#[test]
+fn templates_are_rendered_in_one_pass() {
+    let vars: HeaderVars = [
+        ("client_ip", "203.0.113.7".to_string()),
+        ("tls_sni", "{{client_ip}}".to_string()),
+    ].into();
+
+    assert_eq!(render_template("{{tls_sni}}/{{client_ip}}", &vars), "{{client_ip}}/203.0.113.7");
+    assert_eq!(render_template("{{unknown}} {{client_ip", &vars), "{{unknown}} {{client_ip");
+    assert_eq!(render_template("plain", &vars), "plain");
+}
@@ -0,0 +1,109 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use sheldx::services::{ config_ip_lists, ip_access_rule, ConfigIpLists, IpAccessList };
+use sheldx::utils::{ default_configs, Configs, IpAccessRule, IpRanges };
+
+fn ip(address: &str) -> IpAddr {
+    address.parse().unwrap()
+}
+
+fn strings(entries: &[&str]) -> Vec<String> {
+    entries.iter().map(|entry| entry.to_string()).collect()
+}
+
+fn rule(host: &str, allow: &[&str], deny: &[&str]) -> IpAccessRule {
+    IpAccessRule { host: host.to_string(), allow: strings(allow), deny: strings(deny) }
+}
+
+#[test]
+fn ranges_match_ipv4_and_ipv6_networks() {
+    let ranges = IpRanges::parse(&strings(&["172.16.0.0/12", "192.168.1.7", "2001:db8::/32", "not an ip"]));
+
+    assert!(ranges.contains(&ip("172.31.255.255")));
+    assert!(!ranges.contains(&ip("172.32.0.0")));
+    assert!(ranges.contains(&ip("192.168.1.7")));
+    assert!(!ranges.contains(&ip("192.168.1.8")));
+    assert!(ranges.contains(&ip("2001:db8:1::1")));
+    assert!(!ranges.contains(&ip("2001:db9::1")));
+    // IPv4 clients of a dual-stack listener
+    assert!(ranges.contains(&ip("::ffff:172.16.0.1")));
+
+    assert!(IpRanges::parse(&[]).is_empty());
+    assert!(IpRanges::parse(&strings(&["0.0.0.0/0"])).contains(&ip("8.8.8.8")));
+}
+
+#[test]
+fn ipv4_mapped_ranges_match_ipv4_clients() {
+    let ranges = IpRanges::parse(&strings(&["::ffff:10.0.0.0/104", "::ffff:192.168.1.7", "::ffff:0:0/95"]));
+
+    assert!(ranges.contains(&ip("10.1.2.3")));
+    assert!(ranges.contains(&ip("::ffff:10.1.2.3")));
+    assert!(!ranges.contains(&ip("11.0.0.1")));
+    assert!(ranges.contains(&ip("192.168.1.7")));
+    assert!(!ranges.contains(&ip("192.168.1.8")));
+    // ranges wider than the mapped addresses stay IPv6
+    assert!(ranges.contains(&ip("::fffe:0:1")));
+    assert!(!ranges.contains(&ip("172.16.0.1")));
+
+    let list = IpAccessList::new(&rule("*", &["10.0.0.0/8"], &["::ffff:10.0.5.0/120"]));
+    assert!(list.allows(&ip("10.0.4.1")));
+    assert!(!list.allows(&ip("10.0.5.1")));
+}
+
+#[test]
+fn the_most_specific_entry_decides() {
+    let list = IpAccessList::new(&rule("*", &["10.0.0.0/8", "10.0.5.9"], &["10.0.5.0/24", "::/0"]));
+
+    assert!(list.allows(&ip("10.1.2.3")));
+    assert!(!list.allows(&ip("10.0.5.1")));
+    assert!(list.allows(&ip("10.0.5.9")));
+    assert!(!list.allows(&ip("2001:db8::1")));
+    // not listed, and there is an allow list
+    assert!(!list.allows(&ip("192.168.0.1")));
+}
+
+#[test]
+fn deny_lists_alone_let_everyone_else_in() {
+    let list = IpAccessList::new(&rule("*", &[], &["203.0.113.0/24", "203.0.113.0/24"]));
+
+    assert!(!list.allows(&ip("203.0.113.50")));
+    assert!(list.allows(&ip("198.51.100.1")));
+
+    // a network in both lists is denied
+    let list = IpAccessList::new(&rule("*", &["198.51.100.0/24"], &["198.51.100.0/24"]));
+    assert!(!list.allows(&ip("198.51.100.1")));
+}
+
+#[test]
+fn hosts_fall_back_to_the_wildcard_rule() {
+    let rules = [rule("*", &[], &["10.0.0.0/8"]), rule("admin.example.com", &["10.0.0.0/8"], &[])];
+
+    assert_eq!(ip_access_rule(&rules, "admin.example.com").unwrap().host, "admin.example.com");
+    assert_eq!(ip_access_rule(&rules, "www.example.com").unwrap().host, "*");
+    assert!(ip_access_rule(&rules[1..], "www.example.com").is_none());
+}
+
+#[test]
+fn config_lists_are_built_once_per_configuration() {
+    let configs = Arc::new(Configs {
+        trusted_proxies: Some(strings(&["10.0.0.0/8"])),
+        ip_access_rules: Some(vec![rule("*", &[], &["203.0.113.0/24"]), rule("admin.example.com", &["10.0.0.0/8"], &[])]),
+        ..default_configs()
+    });
+
+    let lists = ConfigIpLists::new(&configs);
+    assert!(lists.trusted_proxies.contains(&ip("10.1.2.3")));
+    assert!(!lists.access_list("admin.example.com").unwrap().allows(&ip("198.51.100.1")));
+    assert!(lists.access_list("www.example.com").unwrap().allows(&ip("198.51.100.1")));
+    assert!(!lists.access_list("www.example.com").unwrap().allows(&ip("203.0.113.1")));
+    // the default rules exclude 192.168.1.1 from the first one only
+    assert!(lists.rate_limit_exclusions(0).unwrap().contains(&ip("192.168.1.1")));
+    assert!(lists.rate_limit_exclusions(1).unwrap().is_empty());
+    assert!(lists.rate_limit_exclusions(2).is_none());
+
+    // the same configuration shares its lists, a reloaded one gets its own
+    assert!(Arc::ptr_eq(&config_ip_lists(&configs), &config_ip_lists(&configs)));
+    let reloaded = Arc::new((*configs).clone());
+    assert!(!Arc::ptr_eq(&config_ip_lists(&configs), &config_ip_lists(&reloaded)));
+}
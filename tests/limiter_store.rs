@@ -0,0 +1,87 @@
+use std::time::Duration;
+
+use sheldx::services::{ LimitParams, LimiterKey, LimiterStore };
+use sheldx::utils::RateLimitAlgorithm;
+
+const PARAMS: LimitParams = LimitParams { limit: 1, window: Duration::from_secs(10), burst: 1 };
+
+fn at(secs: u64) -> Duration {
+    Duration::from_secs(1_800_000_000 + secs)
+}
+
+fn key(rule: &str, key: &str) -> LimiterKey {
+    LimiterKey { rule: rule.to_string(), key: key.to_string() }
+}
+
+fn check(store: &LimiterStore, key: &LimiterKey, now: Duration) -> bool {
+    store.check(key, RateLimitAlgorithm::TokenBucket, PARAMS, now).allowed
+}
+
+#[test]
+fn forgets_the_least_recently_used_clients_when_full() {
+    // one entry per shard
+    let store = LimiterStore::new(1, Duration::from_secs(300));
+
+    for client in 0..1000 {
+        check(&store, &key("rule", &client.to_string()), at(0));
+    }
+    assert!(store.len() <= 64);
+
+    // the latest client is still limited, the first one was forgotten and starts over
+    assert!(!check(&store, &key("rule", "999"), at(0)));
+    assert!(check(&store, &key("rule", "0"), at(0)));
+}
+
+#[test]
+fn drops_idle_limiters_only_once_they_have_recovered() {
+    let store = LimiterStore::new(1000, Duration::from_secs(1));
+    check(&store, &key("rule", "a"), at(0));
+    check(&store, &key("rule", "b"), at(8));
+
+    // "b" is still waiting for its token
+    assert_eq!(store.remove_idle(at(12)), 1);
+    assert_eq!(store.snapshot("a", None, at(12)).len(), 0);
+    assert!(!check(&store, &key("rule", "b"), at(12)));
+
+    assert_eq!(store.remove_idle(at(30)), 1);
+    assert!(store.is_empty());
+}
+
+#[test]
+fn rebuilds_limiters_when_the_rule_changes() {
+    let store = LimiterStore::new(1000, Duration::from_secs(300));
+    let client = key("rule", "a");
+    assert!(check(&store, &client, at(0)));
+    assert!(!check(&store, &client, at(0)));
+
+    let raised = LimitParams { limit: 5, ..PARAMS };
+    assert!(store.check(&client, RateLimitAlgorithm::TokenBucket, raised, at(0)).allowed);
+    assert!(store.check(&client, RateLimitAlgorithm::FixedWindow, raised, at(0)).allowed);
+    assert_eq!(store.snapshot("a", None, at(0))[0].algorithm, RateLimitAlgorithm::FixedWindow);
+}
+
+#[test]
+fn finds_and_removes_a_clients_limiters_by_rule() {
+    let store = LimiterStore::new(1000, Duration::from_secs(300));
+    check(&store, &key("per-second", "a"), at(0));
+    check(&store, &key("per-hour", "a"), at(0));
+    check(&store, &key("per-hour", "b"), at(0));
+
+    assert_eq!(store.snapshot("a", None, at(0)).len(), 2);
+    assert_eq!(store.snapshot("a", Some("per-hour"), at(0)).len(), 1);
+
+    assert_eq!(store.remove("a", Some("per-hour")), 1);
+    assert_eq!(store.remove("a", None), 1);
+    assert_eq!(store.len(), 1);
+}
+
+#[test]
+fn keeps_limiters_with_clamped_limits() {
+    let store = LimiterStore::new(100, Duration::from_secs(300));
+    let zero = LimitParams { limit: 0, window: Duration::ZERO, burst: 0 };
+    let client = key("rule", "client");
+
+    assert!(store.check(&client, RateLimitAlgorithm::FixedWindow, zero, at(0)).allowed);
+    // the stored limiter has the clamped limits, which mustn't count as a changed rule
+    assert!(!store.check(&client, RateLimitAlgorithm::FixedWindow, zero, at(0)).allowed);
+}
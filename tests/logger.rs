@@ -0,0 +1,57 @@
+use std::collections::HashMap;
+use std::fs;
+
+use sheldx::utils::{ apply_log_levels, init_logger, LoggingConfig };
+
+fn config(level: &str, modules: &[(&str, &str)], file: &str) -> LoggingConfig {
+    LoggingConfig {
+        level: level.to_string(),
+        modules: modules
+            .iter()
+            .map(|(module, level)| (module.to_string(), level.to_string()))
+            .collect::<HashMap<_, _>>(),
+        console: Some(false),
+        file: Some(file.to_string()),
+        ..LoggingConfig::default()
+    }
+}
+
+/// The logger is global, so its levels are checked in a single test.
+#[test]
+fn module_levels_override_the_default() {
+    let dir = std::env::temp_dir().join(format!("sheldx-logger-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    let path = dir.join("sheldx.log");
+    let path = path.to_string_lossy();
+    let modules = [("app::noisy", "debug"), ("app::noisy::quiet", "error"), ("app", "info")];
+    fs::create_dir_all(&dir).unwrap();
+    init_logger(&config("warn", &modules, &path), false).unwrap();
+
+    log::debug!(target: "app::noisy", "noisy debug");
+    log::debug!(target: "app::noisy::inner", "inner debug");
+    log::warn!(target: "app::noisy::quiet", "quiet warning");
+    log::error!(target: "app::noisy::quiet::deeper", "deeper error");
+    log::info!(target: "app::other", "other info");
+    log::debug!(target: "app::other", "other debug");
+    log::info!(target: "application", "prefix info");
+    log::info!(target: "elsewhere", "elsewhere info");
+    log::warn!(target: "elsewhere", "elsewhere warning");
+
+    let written = fs::read_to_string(&*path).unwrap();
+    for logged in ["noisy debug", "inner debug", "deeper error", "other info", "elsewhere warning"] {
+        assert!(written.contains(logged), "{:?} missing from {}", logged, written);
+    }
+    // module names only match whole path segments
+    for dropped in ["quiet warning", "other debug", "prefix info", "elsewhere info"] {
+        assert!(!written.contains(dropped), "{:?} logged in {}", dropped, written);
+    }
+
+    // reloads swap the levels in place
+    apply_log_levels(&config("info", &[], &path));
+    log::info!(target: "elsewhere", "reloaded info");
+    log::debug!(target: "app::noisy", "reloaded debug");
+    let written = fs::read_to_string(&*path).unwrap();
+    assert!(written.contains("reloaded info"));
+    assert!(!written.contains("reloaded debug"));
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,62 @@
+use std::net::TcpListener as StdTcpListener;
+use std::time::Duration;
+
+use sheldx::server::spawn_metrics_server;
+use sheldx::services::{ gather_metrics, record_rate_limit_decision, record_request, RateLimitDecision };
+use sheldx::utils::MetricsConfig;
+use tokio::io::{ AsyncReadExt, AsyncWriteExt };
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+
+#[test]
+fn recorded_requests_and_decisions_are_exported() {
+    record_request("metrics.example.com", "10.0.0.1:8080", "200", Duration::from_millis(25));
+    record_request("metrics.example.com", "10.0.0.1:8080", "200", Duration::from_millis(25));
+    record_rate_limit_decision(RateLimitDecision::Limited);
+
+    let metrics = gather_metrics();
+    assert!(
+        metrics.contains(
+            "sheldx_requests_total{host=\"metrics.example.com\",route=\"10.0.0.1:8080\",status=\"200\"} 2"
+        ),
+        "{}",
+        metrics
+    );
+    assert!(
+        metrics.contains(
+            "sheldx_request_duration_seconds_count{host=\"metrics.example.com\",route=\"10.0.0.1:8080\",status=\"200\"} 2"
+        ),
+        "{}",
+        metrics
+    );
+    assert!(metrics.contains("sheldx_rate_limit_decisions_total{decision=\"limited\"}"), "{}", metrics);
+}
+
+async fn get(address: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).await.unwrap();
+    let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path);
+    stream.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = String::new();
+    timeout(Duration::from_secs(5), stream.read_to_string(&mut response)).await.unwrap().unwrap();
+    response
+}
+
+#[tokio::test]
+async fn the_metrics_listener_only_serves_its_path() {
+    // a port that was free a moment ago
+    let listen = StdTcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let config = MetricsConfig { enabled: true, listen: listen.clone(), path: "/metrics".to_string() };
+    spawn_metrics_server(config).await.unwrap();
+    record_rate_limit_decision(RateLimitDecision::Allowed);
+
+    let response = get(&listen, "/metrics").await;
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.contains("text/plain; version=0.0.4"), "{}", response);
+    assert!(response.contains("sheldx_rate_limit_decisions_total{decision=\"allowed\"}"), "{}", response);
+
+    for other in ["/", "/metrics/", "/metricsz", "/admin"] {
+        let response = get(&listen, other).await;
+        assert!(response.starts_with("HTTP/1.1 404"), "{} gave {}", other, response);
+    }
+}
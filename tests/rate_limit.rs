@@ -0,0 +1,170 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::Request;
+use sheldx::services::{
+    enforce_rate_limit,
+    unix_now,
+    ConfigIpLists,
+    LimiterStore,
+    RateLimitDecision,
+    RateLimitResponse,
+};
+use sheldx::utils::{
+    default_configs,
+    Configs,
+    RateLimitAlgorithm,
+    RateLimitKey,
+    RateLimitRule,
+    RateLimitStrategy,
+    RedisConfig,
+    RedisFailureMode,
+};
+
+const CLIENT_IP: &str = "203.0.113.9";
+
+/// A rule allowing `limit` requests an hour, so nothing refills while a test runs.
+fn rule(name: &str, host: &str, limit: u64) -> RateLimitRule {
+    RateLimitRule {
+        name: Some(name.to_string()),
+        host: host.to_string(),
+        limit,
+        duration: 3600,
+        max_tokens: limit,
+        excluded_paths: vec![],
+        excluded_ip_list: vec![],
+        strategy: RateLimitStrategy::Local,
+        algorithm: RateLimitAlgorithm::TokenBucket,
+        key: vec![RateLimitKey::Ip],
+    }
+}
+
+fn configs(rules: Vec<RateLimitRule>) -> Configs {
+    Configs { rate_limit_rules: Some(rules), ..default_configs() }
+}
+
+fn store() -> Arc<LimiterStore> {
+    Arc::new(LimiterStore::new(1000, Duration::from_secs(300)))
+}
+
+async fn enforce(store: &Arc<LimiterStore>, configs: &Configs, path: &str) -> RateLimitResponse {
+    let request = Request::builder().uri(path).header("host", "example.com").body(()).unwrap();
+    enforce_rate_limit(&request, CLIENT_IP, store, configs, &ConfigIpLists::new(configs)).await.unwrap()
+}
+
+/// Requests the client has left under `rule`, if the rule has counted any of its requests.
+fn remaining(store: &LimiterStore, rule: &str) -> Option<u64> {
+    store
+        .snapshot(&format!("ip:{}", CLIENT_IP), Some(rule), unix_now())
+        .first()
+        .map(|limiter| limiter.remaining)
+}
+
+#[tokio::test]
+async fn every_matching_rule_counts_the_request() {
+    let configs = configs(
+        vec![
+            rule("hourly", "example.com", 10),
+            rule("other-host", "api.example.com", 10),
+            rule("burst", "example.com", 5),
+            rule("fallback", "*", 10)
+        ]
+    );
+    let store = store();
+
+    let response = enforce(&store, &configs, "/").await;
+    assert_eq!((response.decision, response.status_code), (RateLimitDecision::Allowed, 200));
+    assert_eq!(remaining(&store, "hourly"), Some(9));
+    assert_eq!(remaining(&store, "burst"), Some(4));
+    assert_eq!(remaining(&store, "other-host"), None);
+    // wildcard rules only apply to hosts without rules of their own
+    assert_eq!(remaining(&store, "fallback"), None);
+}
+
+#[tokio::test]
+async fn the_most_restrictive_rule_wins() {
+    let configs = configs(vec![rule("loose", "example.com", 3), rule("strict", "example.com", 1)]);
+    let store = store();
+
+    let first = enforce(&store, &configs, "/").await;
+    assert_eq!(first.decision, RateLimitDecision::Allowed);
+    // the quota describes the rule closest to its limit
+    let quota = first.quota.unwrap();
+    assert_eq!((quota.limit, quota.remaining), (1, 0));
+
+    let second = enforce(&store, &configs, "/").await;
+    assert_eq!((second.decision, second.status_code), (RateLimitDecision::Limited, 429));
+    assert_eq!(second.seconds, 3600);
+    let quota = second.quota.unwrap();
+    assert_eq!((quota.limit, quota.remaining), (1, 0));
+    // the rule before the one that rejected the request still counted it
+    assert_eq!(remaining(&store, "loose"), Some(1));
+}
+
+#[tokio::test]
+async fn a_denial_does_not_charge_later_rules() {
+    let configs = configs(vec![rule("strict", "example.com", 1), rule("loose", "example.com", 3)]);
+    let store = store();
+
+    enforce(&store, &configs, "/").await;
+    assert_eq!(remaining(&store, "loose"), Some(2));
+
+    for _ in 0..3 {
+        let response = enforce(&store, &configs, "/").await;
+        assert_eq!(response.decision, RateLimitDecision::Limited);
+    }
+    assert_eq!(remaining(&store, "loose"), Some(2));
+}
+
+#[tokio::test]
+async fn a_refusal_while_redis_is_down_does_not_charge_later_rules() {
+    let shared = RateLimitRule { strategy: RateLimitStrategy::Redis, ..rule("shared", "example.com", 10) };
+    let configs = Configs {
+        // nobody listens there
+        redis: Some(RedisConfig {
+            url: "redis://127.0.0.1:1".to_string(),
+            timeout_ms: 200,
+            failure_mode: RedisFailureMode::Closed,
+            ..RedisConfig::default()
+        }),
+        ..configs(vec![shared, rule("local", "example.com", 1)])
+    };
+    let store = store();
+
+    for _ in 0..2 {
+        let response = enforce(&store, &configs, "/").await;
+        assert_eq!((response.decision, response.status_code), (RateLimitDecision::Unavailable, 503));
+    }
+    assert_eq!(remaining(&store, "local"), None);
+}
+
+#[tokio::test]
+async fn excluded_paths_and_clients_skip_a_rule() {
+    let excluded_path = RateLimitRule { excluded_paths: vec!["/health".to_string()], ..rule("paths", "example.com", 1) };
+    let excluded_ip = RateLimitRule {
+        excluded_ip_list: vec!["203.0.113.0/24".to_string()],
+        ..rule("clients", "example.com", 1)
+    };
+    let configs = configs(vec![excluded_path, excluded_ip]);
+    let store = store();
+
+    for _ in 0..3 {
+        let response = enforce(&store, &configs, "/health/live").await;
+        assert_eq!((response.decision, response.status_code), (RateLimitDecision::Excluded, 200));
+        assert!(response.quota.is_none());
+    }
+
+    // the other rule still excludes the client, so only the path rule counts
+    assert_eq!(enforce(&store, &configs, "/").await.decision, RateLimitDecision::Allowed);
+    assert_eq!(enforce(&store, &configs, "/").await.decision, RateLimitDecision::Limited);
+    assert_eq!(remaining(&store, "clients"), None);
+}
+
+#[tokio::test]
+async fn requests_without_a_rule_are_let_through() {
+    let configs = configs(vec![rule("other-host", "api.example.com", 1)]);
+
+    let response = enforce(&store(), &configs, "/").await;
+    assert_eq!((response.decision, response.status_code), (RateLimitDecision::NoRule, 200));
+    assert!(response.quota.is_none());
+}
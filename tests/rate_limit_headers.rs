@@ -0,0 +1,112 @@
+use std::time::Duration;
+
+use hyper::header::{ HeaderMap, HeaderValue, ACCEPT };
+use sheldx::services::{ LimitOutcome, RateLimitDecision, RateLimitQuota, RateLimitResponse };
+use sheldx::utils::{ prefers_json, RateLimitAlgorithm, RateLimitKey, RateLimitRule, RateLimitStrategy };
+
+fn rule(algorithm: RateLimitAlgorithm) -> RateLimitRule {
+    RateLimitRule {
+        name: None,
+        host: "example.com".to_string(),
+        limit: 10,
+        duration: 60,
+        max_tokens: 100,
+        excluded_paths: vec![],
+        excluded_ip_list: vec![],
+        strategy: RateLimitStrategy::Local,
+        algorithm,
+        key: vec![RateLimitKey::Ip],
+    }
+}
+
+fn outcome(allowed: bool, remaining: u64, retry_after_ms: u64, reset_after_ms: u64) -> LimitOutcome {
+    LimitOutcome {
+        allowed,
+        remaining,
+        retry_after: Duration::from_millis(retry_after_ms),
+        reset_after: Duration::from_millis(reset_after_ms),
+    }
+}
+
+fn response(decision: RateLimitDecision, seconds: u64, quota: Option<RateLimitQuota>) -> RateLimitResponse {
+    let status_code = if decision == RateLimitDecision::Limited { 429 } else { 200 };
+    RateLimitResponse { response: String::new(), status_code, seconds, decision, quota }
+}
+
+fn header<'a>(headers: &'a HeaderMap, name: &str) -> Option<&'a str> {
+    headers.get(name).map(|value| value.to_str().unwrap())
+}
+
+fn accepts(values: &[&str]) -> bool {
+    let mut headers = HeaderMap::new();
+    for value in values {
+        headers.append(ACCEPT, HeaderValue::from_str(value).unwrap());
+    }
+    prefers_json(&headers)
+}
+
+#[test]
+fn allowed_requests_carry_the_quota() {
+    let quota = RateLimitQuota::new(&rule(RateLimitAlgorithm::FixedWindow), &outcome(true, 7, 0, 42_001));
+    let mut headers = HeaderMap::new();
+    response(RateLimitDecision::Allowed, 0, Some(quota)).set_headers(&mut headers);
+
+    assert_eq!(header(&headers, "ratelimit-limit"), Some("10"));
+    assert_eq!(header(&headers, "ratelimit-remaining"), Some("7"));
+    // rounded up, so clients don't come back too early
+    assert_eq!(header(&headers, "ratelimit-reset"), Some("43"));
+    assert_eq!(header(&headers, "ratelimit-policy"), Some("10;w=60"));
+    assert_eq!(header(&headers, "retry-after"), None);
+}
+
+#[test]
+fn burst_algorithms_report_the_burst() {
+    for algorithm in [RateLimitAlgorithm::TokenBucket, RateLimitAlgorithm::Gcra] {
+        let quota = RateLimitQuota::new(&rule(algorithm), &outcome(true, 99, 0, 6_000));
+        assert_eq!(quota.limit, 100, "{:?}", algorithm);
+        assert_eq!(quota.policy, "10;w=60;burst=100", "{:?}", algorithm);
+    }
+    for algorithm in [RateLimitAlgorithm::SlidingWindowLog, RateLimitAlgorithm::SlidingWindowCounter] {
+        assert_eq!(RateLimitQuota::new(&rule(algorithm), &outcome(true, 9, 0, 0)).policy, "10;w=60", "{:?}", algorithm);
+    }
+}
+
+#[test]
+fn limited_requests_carry_retry_after() {
+    let quota = RateLimitQuota::new(&rule(RateLimitAlgorithm::FixedWindow), &outcome(false, 0, 12_500, 12_500));
+    let mut headers = HeaderMap::new();
+    response(RateLimitDecision::Limited, 13, Some(quota)).set_headers(&mut headers);
+
+    assert_eq!(header(&headers, "retry-after"), Some("13"));
+    assert_eq!(header(&headers, "ratelimit-remaining"), Some("0"));
+    assert_eq!(header(&headers, "ratelimit-reset"), Some("13"));
+}
+
+#[test]
+fn uncounted_requests_carry_no_headers() {
+    for decision in [RateLimitDecision::NoRule, RateLimitDecision::Excluded, RateLimitDecision::Unavailable] {
+        let mut headers = HeaderMap::new();
+        response(decision, 0, None).set_headers(&mut headers);
+        assert!(headers.is_empty(), "{:?}", decision);
+    }
+}
+
+#[test]
+fn json_is_chosen_for_api_clients() {
+    assert!(accepts(&["application/json"]));
+    assert!(accepts(&["application/problem+json"]));
+    assert!(accepts(&["application/json, text/html;q=0.5"]));
+    assert!(accepts(&["text/html;q=0.1", "application/*"]));
+    assert!(accepts(&["Application/JSON; Q=0.9, text/html; Q=0.8"]));
+}
+
+#[test]
+fn html_is_chosen_for_browsers_and_clients_without_a_preference() {
+    assert!(!accepts(&[]));
+    assert!(!accepts(&["*/*"]));
+    assert!(!accepts(&["text/html,application/xhtml+xml,application/xml;q=0.9,*/*;q=0.8"]));
+    assert!(!accepts(&["application/json;q=0.5, text/html"]));
+    assert!(!accepts(&["application/json;q=0.8, text/*;q=0.8"]));
+    assert!(!accepts(&["text/plain"]));
+    assert!(!accepts(&["application/json;q=0"]));
+}
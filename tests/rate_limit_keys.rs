@@ -0,0 +1,141 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper::Request;
+use sheldx::services::rate_limit_key;
+use sheldx::utils::RateLimitKey;
+
+const CLIENT_IP: &str = "203.0.113.9";
+const IP_KEY: &str = "ip:203.0.113.9";
+
+fn keys(names: &[&str]) -> Vec<RateLimitKey> {
+    names
+        .iter()
+        .map(|name| RateLimitKey::try_from(name.to_string()).unwrap())
+        .collect()
+}
+
+fn request(method: &str, uri: &str, headers: &[(&str, &str)]) -> Request<()> {
+    let mut builder = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        builder = builder.header(*name, *value);
+    }
+    builder.body(()).unwrap()
+}
+
+fn key(names: &[&str], headers: &[(&str, &str)]) -> String {
+    rate_limit_key(&request("GET", "/api/orders?page=2", headers), &keys(names), CLIENT_IP)
+}
+
+fn bearer(claims: &str) -> String {
+    format!("Bearer {}.{}.signature", URL_SAFE_NO_PAD.encode("{\"alg\":\"HS256\"}"), URL_SAFE_NO_PAD.encode(claims))
+}
+
+#[test]
+fn ip_keys_are_the_client_ip() {
+    assert_eq!(key(&["ip"], &[]), IP_KEY);
+}
+
+#[test]
+fn header_keys_are_trimmed_and_case_insensitive() {
+    assert_eq!(key(&["header:X-API-Key"], &[("x-api-key", " key-123 ")]), "header:x-api-key:key-123");
+    assert_eq!(key(&["header:x-api-key"], &[("X-Api-Key", "key-123")]), "header:x-api-key:key-123");
+}
+
+#[test]
+fn cookie_keys_find_the_named_cookie() {
+    let cookies = [("cookie", "theme=dark; session=\"abc123\""), ("cookie", "other=1")];
+    assert_eq!(key(&["cookie:session"], &cookies), "cookie:session:abc123");
+    assert_eq!(key(&["cookie:other"], &cookies), "cookie:other:1");
+    // names are matched exactly
+    assert_eq!(key(&["cookie:Session"], &cookies), IP_KEY);
+}
+
+#[test]
+fn path_keys_leave_out_the_query_string() {
+    assert_eq!(key(&["path"], &[]), "path:/api/orders");
+    let other_query = rate_limit_key(&request("GET", "/api/orders?page=3", &[]), &keys(&["path"]), CLIENT_IP);
+    assert_eq!(other_query, "path:/api/orders");
+}
+
+#[test]
+fn method_and_global_keys() {
+    let post = request("POST", "/", &[]);
+    assert_eq!(rate_limit_key(&post, &keys(&["method"]), CLIENT_IP), "method:POST");
+    assert_eq!(key(&["global"], &[]), "global");
+}
+
+#[test]
+fn bearer_subjects_are_read_from_the_token() {
+    let token = bearer("{\"sub\":\"user-42\",\"exp\":1}");
+    assert_eq!(key(&["bearer_subject"], &[("authorization", &token)]), "sub:user-42");
+    // the scheme is case-insensitive and padding is tolerated
+    let padded = format!("bearer {}==", &token["Bearer ".len()..]);
+    assert_eq!(key(&["bearer_subject"], &[("authorization", &padded)]), "sub:user-42");
+}
+
+#[test]
+fn combined_keys_are_joined_in_order() {
+    let headers = [("x-api-key", "key-123")];
+    assert_eq!(
+        key(&["header:x-api-key", "method", "path"], &headers),
+        "header:x-api-key:key-123|method:GET|path:/api/orders"
+    );
+    assert_eq!(key(&["ip", "header:x-api-key"], &headers), format!("{}|header:x-api-key:key-123", IP_KEY));
+}
+
+#[test]
+fn parts_from_different_sources_never_share_a_key() {
+    // a header holding some client's IP doesn't get that client's bucket
+    assert_ne!(key(&["header:x-api-key"], &[("x-api-key", CLIENT_IP)]), key(&["ip"], &[]));
+    assert_ne!(key(&["header:x-api-key"], &[("x-api-key", IP_KEY)]), key(&["ip"], &[]));
+    assert_ne!(key(&["header:x-api-key"], &[("x-api-key", "k")]), key(&["header:x-other"], &[("x-other", "k")]));
+    assert_ne!(key(&["header:x-api-key"], &[("x-api-key", "global")]), key(&["global"], &[]));
+
+    // values can't pass for several parts
+    let split = key(&["header:x-a", "header:x-b"], &[("x-a", "1|header:x-b:2"), ("x-b", "3")]);
+    assert_eq!(split, "header:x-a:1%7Cheader:x-b:2|header:x-b:3");
+    assert_ne!(split, key(&["header:x-a", "header:x-b"], &[("x-a", "1"), ("x-b", "2|header:x-b:3")]));
+    assert_eq!(key(&["header:x-a"], &[("x-a", "1%7C2")]), "header:x-a:1%257C2");
+}
+
+#[test]
+fn missing_parts_fall_back_to_the_client_ip() {
+    assert_eq!(key(&["header:x-api-key"], &[]), IP_KEY);
+    assert_eq!(key(&["header:x-api-key"], &[("x-api-key", "   ")]), IP_KEY);
+    assert_eq!(key(&["cookie:session"], &[("cookie", "session=")]), IP_KEY);
+    assert_eq!(key(&["bearer_subject"], &[]), IP_KEY);
+    assert_eq!(key(&["header:x-api-key", "method"], &[]), format!("{}|method:GET", IP_KEY));
+}
+
+#[test]
+fn malformed_credentials_fall_back_to_the_client_ip() {
+    let malformed = [
+        "Basic dXNlcjpwYXNz".to_string(),
+        "Bearer".to_string(),
+        "Bearer not-a-jwt".to_string(),
+        "Bearer header.%%%.signature".to_string(),
+        bearer("not json"),
+        bearer("{\"sub\":42}"),
+        bearer("{\"sub\":\"\"}"),
+        bearer("{\"name\":\"no subject\"}"),
+    ];
+    for authorization in &malformed {
+        assert_eq!(key(&["bearer_subject"], &[("authorization", authorization)]), IP_KEY, "{}", authorization);
+    }
+
+    assert_eq!(key(&["cookie:session"], &[("cookie", "garbage; ;=value")]), IP_KEY);
+    // header values that aren't UTF-8 are still usable
+    let request = Request::builder()
+        .header("x-api-key", hyper::header::HeaderValue::from_bytes(b"key-\xff").unwrap())
+        .body(())
+        .unwrap();
+    assert_eq!(rate_limit_key(&request, &keys(&["header:x-api-key"]), CLIENT_IP), "header:x-api-key:key-\u{fffd}");
+}
+
+#[test]
+fn key_names_are_checked() {
+    assert_eq!(keys(&["header:X-API-Key"]), [RateLimitKey::Header("x-api-key".to_string())]);
+    for invalid in ["header:", "cookie:", "header:bad name", "query:page", "hostname"] {
+        assert!(RateLimitKey::try_from(invalid.to_string()).is_err(), "{}", invalid);
+    }
+}
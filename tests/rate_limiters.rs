@@ -0,0 +1,189 @@
+use std::time::Duration;
+
+use sheldx::services::{ new_limiter, LimitParams, Limiter };
+use sheldx::utils::{ RateLimitAlgorithm, RateLimitRule };
+
+/// A time on a boundary of every window used below, so fixed windows start at `at(0)`.
+fn at(ms: u64) -> Duration {
+    Duration::from_secs(1_800_000_000) + Duration::from_millis(ms)
+}
+
+fn limiter(algorithm: RateLimitAlgorithm, limit: u64, window_secs: u64, burst: u64) -> Box<dyn Limiter> {
+    new_limiter(algorithm, LimitParams { limit, window: Duration::from_secs(window_secs), burst })
+}
+
+/// Checks `count` requests at `now`, returning how many were allowed.
+fn allowed(limiter: &mut Box<dyn Limiter>, now: Duration, count: usize) -> usize {
+    (0..count).filter(|_| limiter.check(now).allowed).count()
+}
+
+#[test]
+fn token_bucket_starts_full_and_refills_continuously() {
+    let mut bucket = limiter(RateLimitAlgorithm::TokenBucket, 1, 1, 3);
+
+    let remaining: Vec<u64> = (0..3).map(|_| bucket.check(at(0)).remaining).collect();
+    assert_eq!(remaining, vec![2, 1, 0]);
+
+    let outcome = bucket.check(at(0));
+    assert!(!outcome.allowed);
+    assert_eq!(outcome.retry_after, Duration::from_secs(1));
+    assert_eq!(outcome.reset_after, Duration::from_secs(3));
+    assert_eq!(bucket.check(at(500)).retry_after, Duration::from_millis(500));
+
+    assert!(bucket.check(at(1000)).allowed);
+    assert_eq!(bucket.remaining(at(60_000)), 3);
+}
+
+#[test]
+fn gcra_allows_the_same_requests_as_the_token_bucket() {
+    let mut bucket = limiter(RateLimitAlgorithm::TokenBucket, 5, 2, 4);
+    let mut gcra = limiter(RateLimitAlgorithm::Gcra, 5, 2, 4);
+
+    for step in 0..200 {
+        let now = at(step * 137);
+        let (from_bucket, from_gcra) = (bucket.check(now), gcra.check(now));
+        assert_eq!(from_bucket.allowed, from_gcra.allowed, "at step {}", step);
+        assert_eq!(from_bucket.remaining, from_gcra.remaining, "at step {}", step);
+    }
+}
+
+#[test]
+fn gcra_spaces_requests_after_a_burst() {
+    let mut gcra = limiter(RateLimitAlgorithm::Gcra, 10, 1, 2);
+
+    assert_eq!(allowed(&mut gcra, at(0), 5), 2);
+    assert_eq!(gcra.check(at(50)).retry_after, Duration::from_millis(50));
+    assert!(gcra.check(at(100)).allowed);
+    assert!(!gcra.check(at(150)).allowed);
+}
+
+#[test]
+fn fixed_window_resets_at_window_boundaries() {
+    let mut window = limiter(RateLimitAlgorithm::FixedWindow, 3, 10, 3);
+
+    assert_eq!(allowed(&mut window, at(8000), 3), 3);
+    let outcome = window.check(at(8000));
+    assert!(!outcome.allowed);
+    assert_eq!(outcome.retry_after, Duration::from_secs(2));
+    assert_eq!(outcome.reset_after, Duration::from_secs(2));
+
+    // a new window starts on the boundary, even though the last requests were just made
+    let outcome = window.check(at(10_000));
+    assert!(outcome.allowed);
+    assert_eq!(outcome.remaining, 2);
+    assert_eq!(window.remaining(at(25_000)), 3);
+}
+
+#[test]
+fn sliding_window_log_allows_limit_requests_in_any_window() {
+    let mut log = limiter(RateLimitAlgorithm::SlidingWindowLog, 3, 10, 3);
+
+    for ms in [0, 4000, 8000] {
+        assert!(log.check(at(ms)).allowed);
+    }
+    let outcome = log.check(at(9000));
+    assert_eq!(outcome.retry_after, Duration::from_secs(1));
+    assert_eq!(outcome.reset_after, Duration::from_secs(9));
+
+    // the first request has left the window
+    assert!(log.check(at(10_000)).allowed);
+    assert_eq!(log.check(at(11_000)).retry_after, Duration::from_secs(3));
+    assert_eq!(log.remaining(at(14_000)), 1);
+}
+
+#[test]
+fn sliding_window_counter_weights_the_previous_window() {
+    let mut counter = limiter(RateLimitAlgorithm::SlidingWindowCounter, 10, 10, 10);
+
+    assert_eq!(allowed(&mut counter, at(5000), 10), 10);
+    // the previous window has to slide out far enough for one more request to fit
+    assert_eq!(counter.check(at(5000)).retry_after, Duration::from_secs(6));
+
+    // 90% of the previous window is still covered: 9 requests
+    let outcome = counter.check(at(11_000));
+    assert!(outcome.allowed);
+    assert_eq!(outcome.remaining, 0);
+    assert_eq!(counter.check(at(11_000)).retry_after, Duration::from_secs(1));
+    assert!(counter.check(at(12_000)).allowed);
+    assert_eq!(counter.remaining(at(40_000)), 10);
+}
+
+#[test]
+fn window_algorithms_never_allow_more_than_limit_per_window() {
+    for algorithm in [
+        RateLimitAlgorithm::FixedWindow,
+        RateLimitAlgorithm::SlidingWindowLog,
+        RateLimitAlgorithm::SlidingWindowCounter,
+    ] {
+        let mut limiter = limiter(algorithm, 5, 1, 50);
+        assert_eq!(allowed(&mut limiter, at(0), 50), 5, "{:?}", algorithm);
+    }
+}
+
+#[test]
+fn rules_choose_the_algorithm() {
+    let rule: RateLimitRule = toml::from_str(
+        r#"
+        host = "*"
+        limit = 1
+        duration = 1
+        max_tokens = 1
+        excluded_paths = []
+        excluded_ip_list = []
+        strategy = "local"
+        "#
+    ).unwrap();
+    assert_eq!(rule.algorithm, RateLimitAlgorithm::TokenBucket);
+
+    for (name, algorithm) in [
+        ("fixed_window", RateLimitAlgorithm::FixedWindow),
+        ("sliding_window", RateLimitAlgorithm::SlidingWindowLog),
+        ("sliding_window_log", RateLimitAlgorithm::SlidingWindowLog),
+        ("sliding_window_counter", RateLimitAlgorithm::SlidingWindowCounter),
+        ("gcra", RateLimitAlgorithm::Gcra),
+    ] {
+        let rule: RateLimitRule = toml::from_str(
+            &format!(
+                "host = \"*\"\nlimit = 1\nduration = 1\nmax_tokens = 1\nexcluded_paths = []\nexcluded_ip_list = []\nstrategy = \"local\"\nalgorithm = \"{}\"",
+                name
+            )
+        ).unwrap();
+        assert_eq!(rule.algorithm, algorithm);
+        assert_eq!(new_limiter(rule.algorithm, LimitParams::from(&rule)).algorithm(), algorithm);
+    }
+}
+
+#[test]
+fn zero_limits_are_clamped_instead_of_panicking() {
+    let zero = LimitParams { limit: 0, window: Duration::ZERO, burst: 0 };
+    let clamped = LimitParams { limit: 1, window: Duration::from_millis(1), burst: 1 };
+    assert_eq!(zero.clamped(), clamped);
+
+    for algorithm in [
+        RateLimitAlgorithm::TokenBucket,
+        RateLimitAlgorithm::FixedWindow,
+        RateLimitAlgorithm::SlidingWindowLog,
+        RateLimitAlgorithm::SlidingWindowCounter,
+        RateLimitAlgorithm::Gcra,
+    ] {
+        let mut limiter = new_limiter(algorithm, zero);
+        assert_eq!(limiter.params(), clamped);
+
+        // one request per millisecond
+        assert!(limiter.check(at(0)).allowed, "{:?}", algorithm);
+        let outcome = limiter.check(at(0));
+        assert!(!outcome.allowed, "{:?}", algorithm);
+        assert!(outcome.retry_after <= Duration::from_millis(2), "{:?}", algorithm);
+        assert!(limiter.check(at(10)).allowed, "{:?}", algorithm);
+        assert!(limiter.is_reset(at(1000)), "{:?}", algorithm);
+    }
+}
+
+#[test]
+fn rules_with_zero_limits_are_clamped() {
+    let rule: RateLimitRule = toml::from_str(
+        "host = \"*\"\nlimit = 0\nduration = 0\nmax_tokens = 0\nexcluded_paths = []\nexcluded_ip_list = []\nstrategy = \"local\""
+    ).unwrap();
+
+    assert_eq!(LimitParams::from(&rule), LimitParams { limit: 1, window: Duration::from_millis(1), burst: 1 });
+}
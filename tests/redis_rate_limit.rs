@@ -0,0 +1,173 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::Request;
+use sheldx::services::{
+    check_redis_rate_limit,
+    enforce_rate_limit,
+    new_limiter,
+    unix_now,
+    ConfigIpLists,
+    LimitParams,
+    LimiterKey,
+    LimiterStore,
+    RateLimitDecision,
+    RateLimitResponse,
+};
+use sheldx::utils::{
+    default_configs,
+    Configs,
+    RateLimitAlgorithm,
+    RateLimitKey,
+    RateLimitRule,
+    RateLimitStrategy,
+    RedisConfig,
+    RedisFailureMode,
+};
+
+const ALGORITHMS: [RateLimitAlgorithm; 5] = [
+    RateLimitAlgorithm::TokenBucket,
+    RateLimitAlgorithm::FixedWindow,
+    RateLimitAlgorithm::SlidingWindowLog,
+    RateLimitAlgorithm::SlidingWindowCounter,
+    RateLimitAlgorithm::Gcra,
+];
+
+fn rule(algorithm: RateLimitAlgorithm, strategy: RateLimitStrategy) -> RateLimitRule {
+    RateLimitRule {
+        name: None,
+        host: "example.com".to_string(),
+        limit: 3,
+        duration: 60,
+        max_tokens: 3,
+        excluded_paths: vec![],
+        excluded_ip_list: vec![],
+        strategy,
+        algorithm,
+        key: vec![RateLimitKey::Ip],
+    }
+}
+
+/// The Redis server to test against, from `REDIS_URL`. Tests that need one are skipped
+/// without it.
+fn redis_config() -> Option<RedisConfig> {
+    let url = std::env::var("REDIS_URL").ok()?;
+    Some(RedisConfig {
+        url,
+        key_prefix: format!("sheldx-test-{}-{}:", std::process::id(), unix_now().as_nanos()),
+        ..RedisConfig::default()
+    })
+}
+
+/// A Redis server nobody listens on.
+fn unreachable_redis(failure_mode: RedisFailureMode) -> RedisConfig {
+    RedisConfig { url: "redis://127.0.0.1:1".to_string(), timeout_ms: 200, failure_mode, ..RedisConfig::default() }
+}
+
+fn configs(rules: Vec<RateLimitRule>, redis: RedisConfig) -> Configs {
+    Configs { rate_limit_rules: Some(rules), redis: Some(redis), ..default_configs() }
+}
+
+fn request() -> Request<()> {
+    Request::builder().uri("/").header("host", "example.com").body(()).unwrap()
+}
+
+fn store() -> Arc<LimiterStore> {
+    Arc::new(LimiterStore::new(1000, Duration::from_secs(300)))
+}
+
+async fn enforce(store: &Arc<LimiterStore>, configs: &Configs) -> RateLimitResponse {
+    enforce_rate_limit(&request(), "203.0.113.9", store, configs, &ConfigIpLists::new(configs)).await.unwrap()
+}
+
+#[tokio::test]
+async fn scripts_count_like_the_local_limiters() {
+    let Some(config) = redis_config() else {
+        eprintln!("REDIS_URL is not set, skipping");
+        return;
+    };
+
+    for algorithm in ALGORITHMS {
+        let rule = rule(algorithm, RateLimitStrategy::Redis);
+        let key = LimiterKey { rule: "example.com".to_string(), key: "203.0.113.9".to_string() };
+        let mut local = new_limiter(algorithm, LimitParams::from(&rule));
+
+        for request in 0..5 {
+            let expected = local.check(unix_now());
+            let outcome = check_redis_rate_limit(&config, &rule, &key).await.unwrap();
+            assert_eq!(outcome.allowed, expected.allowed, "{:?} request {}", algorithm, request);
+            assert_eq!(outcome.remaining, expected.remaining, "{:?} request {}", algorithm, request);
+            if !outcome.allowed {
+                assert!(outcome.retry_after > Duration::ZERO, "{:?} request {}", algorithm, request);
+                assert!(outcome.retry_after <= Duration::from_secs(60), "{:?} request {}", algorithm, request);
+            }
+        }
+    }
+}
+
+#[tokio::test]
+async fn scripts_keep_clients_and_rules_apart() {
+    let Some(config) = redis_config() else {
+        eprintln!("REDIS_URL is not set, skipping");
+        return;
+    };
+
+    let rule = rule(RateLimitAlgorithm::FixedWindow, RateLimitStrategy::Redis);
+    let key = |rule: &str, client: &str| LimiterKey { rule: rule.to_string(), key: client.to_string() };
+    for _ in 0..3 {
+        check_redis_rate_limit(&config, &rule, &key("example.com", "203.0.113.9")).await.unwrap();
+    }
+
+    assert!(!check_redis_rate_limit(&config, &rule, &key("example.com", "203.0.113.9")).await.unwrap().allowed);
+    assert!(check_redis_rate_limit(&config, &rule, &key("example.com", "198.51.100.1")).await.unwrap().allowed);
+    assert!(check_redis_rate_limit(&config, &rule, &key("example.com#2", "203.0.113.9")).await.unwrap().allowed);
+
+    // a changed limit starts from fresh state
+    let raised = RateLimitRule { limit: 4, max_tokens: 4, ..rule };
+    assert!(check_redis_rate_limit(&config, &raised, &key("example.com", "203.0.113.9")).await.unwrap().allowed);
+}
+
+#[tokio::test]
+async fn unreachable_redis_fails_open() {
+    let configs = configs(
+        vec![rule(RateLimitAlgorithm::TokenBucket, RateLimitStrategy::Redis)],
+        unreachable_redis(RedisFailureMode::Open)
+    );
+
+    let response = enforce(&store(), &configs).await;
+    assert_eq!(response.decision, RateLimitDecision::Unavailable);
+    assert_eq!(response.status_code, 200);
+}
+
+#[tokio::test]
+async fn unreachable_redis_fails_closed() {
+    let configs = configs(
+        vec![rule(RateLimitAlgorithm::TokenBucket, RateLimitStrategy::Redis)],
+        unreachable_redis(RedisFailureMode::Closed)
+    );
+
+    let response = enforce(&store(), &configs).await;
+    assert_eq!(response.decision, RateLimitDecision::Unavailable);
+    assert_eq!(response.status_code, 503);
+}
+
+#[tokio::test]
+async fn local_rules_still_limit_while_redis_is_unreachable() {
+    let local = RateLimitRule {
+        name: Some("local".to_string()),
+        limit: 1,
+        max_tokens: 1,
+        ..rule(RateLimitAlgorithm::FixedWindow, RateLimitStrategy::Local)
+    };
+    let configs = configs(
+        vec![rule(RateLimitAlgorithm::TokenBucket, RateLimitStrategy::Redis), local],
+        unreachable_redis(RedisFailureMode::Open)
+    );
+    let store = store();
+
+    let first = enforce(&store, &configs).await;
+    assert_eq!((first.decision, first.status_code), (RateLimitDecision::Unavailable, 200));
+
+    let second = enforce(&store, &configs).await;
+    assert_eq!((second.decision, second.status_code), (RateLimitDecision::Limited, 429));
+}
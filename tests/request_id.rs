@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::header::HeaderMap;
+use hyper_util::rt::TokioIo;
+use sheldx::server::{ serve_connection, ConnectionInfo };
+use sheldx::services::LimiterStore;
+use sheldx::utils::{
+    default_configs,
+    resolve_request_id,
+    set_current_configs,
+    Configs,
+    ForwardingRule,
+    RequestIdConfig,
+    RequestLimitsConfig,
+};
+use tokio::io::{ duplex, AsyncReadExt, AsyncWriteExt };
+use tokio::net::TcpListener;
+use tokio::time::timeout;
+use uuid::Uuid;
+
+fn headers(entries: &[(&str, &str)]) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    for (name, value) in entries {
+        headers.insert(hyper::header::HeaderName::from_bytes(name.as_bytes()).unwrap(), value.parse().unwrap());
+    }
+    headers
+}
+
+fn trusting(header: &str) -> RequestIdConfig {
+    RequestIdConfig { header: header.to_string(), trust_incoming: true }
+}
+
+fn is_generated(id: &str) -> bool {
+    Uuid::parse_str(id).is_ok_and(|uuid| uuid.get_version_num() == 7)
+}
+
+#[test]
+fn incoming_ids_are_ignored_unless_trusted() {
+    let inbound = headers(&[("x-request-id", "client-chosen")]);
+
+    let id = resolve_request_id(&inbound, &RequestIdConfig::default());
+    assert!(is_generated(&id), "{}", id);
+    assert_eq!(resolve_request_id(&inbound, &trusting("x-request-id")), "client-chosen");
+    // only the configured header is read
+    assert!(is_generated(&resolve_request_id(&inbound, &trusting("x-correlation-id"))));
+}
+
+#[test]
+fn trusted_ids_are_trimmed() {
+    let inbound = headers(&[("x-request-id", "  abc-123  ")]);
+    assert_eq!(resolve_request_id(&inbound, &trusting("x-request-id")), "abc-123");
+}
+
+#[test]
+fn unusable_ids_are_replaced() {
+    let longest = "a".repeat(128);
+    assert_eq!(resolve_request_id(&headers(&[("x-request-id", &longest)]), &trusting("x-request-id")), longest);
+
+    for unusable in ["a".repeat(129), "has space".to_string(), "semi;colon".to_string(), "slash/".to_string(), "   ".to_string()] {
+        let id = resolve_request_id(&headers(&[("x-request-id", &unusable)]), &trusting("x-request-id"));
+        assert!(is_generated(&id), "{:?} gave {}", unusable, id);
+    }
+    // so is one that isn't valid UTF-8
+    let mut inbound = HeaderMap::new();
+    inbound.insert("x-request-id", hyper::header::HeaderValue::from_bytes(b"id-\xff").unwrap());
+    assert!(is_generated(&resolve_request_id(&inbound, &trusting("x-request-id"))));
+}
+
+/// An upstream answering every request with the head of the request it received.
+async fn echo_upstream() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut head = Vec::new();
+                let mut buffer = [0; 1024];
+                while !head.ends_with(b"\r\n\r\n") {
+                    match stream.read(&mut buffer).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(read) => head.extend_from_slice(&buffer[..read]),
+                    }
+                }
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", head.len());
+                let _ = stream.write_all(response.as_bytes()).await;
+                let _ = stream.write_all(&head).await;
+            });
+        }
+    });
+    address
+}
+
+async fn exchange(request: &str) -> String {
+    let conn = Arc::new(ConnectionInfo {
+        peer_ip: "198.51.100.1".parse().unwrap(),
+        is_tls: false,
+        tls_version: None,
+        tls_cipher: None,
+        tls_sni: None,
+        request_limits: RequestLimitsConfig::default(),
+    });
+    let store = Arc::new(LimiterStore::new(1000, Duration::from_secs(300)));
+
+    let (mut client, server) = duplex(1 << 16);
+    tokio::spawn(serve_connection(TokioIo::new(server), conn, store));
+    client.write_all(request.as_bytes()).await.unwrap();
+
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(5), client.read_to_end(&mut response)).await.unwrap().unwrap();
+    String::from_utf8_lossy(&response).to_lowercase()
+}
+
+/// The value of `name` in the response head, and in the request head echoed in its body.
+fn sent_and_returned(response: &str, name: &str) -> (Option<String>, Option<String>) {
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let value = |lines: &str| {
+        lines
+            .lines()
+            .find_map(|line| line.strip_prefix(&format!("{}: ", name)).map(str::to_string))
+    };
+    (value(body), value(head))
+}
+
+#[tokio::test]
+async fn ids_reach_the_upstream_and_the_client() {
+    set_current_configs(Configs {
+        forwarding_rules: Some(
+            vec![ForwardingRule {
+                host: "app.test".to_string(),
+                target: echo_upstream().await,
+                request_headers: None,
+                response_headers: None,
+                concurrency: None,
+            }]
+        ),
+        request_id: Some(trusting("x-correlation-id")),
+        ..default_configs()
+    });
+
+    let response = exchange("GET / HTTP/1.1\r\nHost: app.test\r\nX-Correlation-Id: abc-123\r\nConnection: close\r\n\r\n").await;
+    assert!(response.starts_with("http/1.1 200"), "{}", response);
+    let (sent, returned) = sent_and_returned(&response, "x-correlation-id");
+    assert_eq!(sent.as_deref(), Some("abc-123"));
+    assert_eq!(returned.as_deref(), Some("abc-123"));
+
+    // generated IDs travel the same way
+    let response = exchange("GET / HTTP/1.1\r\nHost: app.test\r\nConnection: close\r\n\r\n").await;
+    let (sent, returned) = sent_and_returned(&response, "x-correlation-id");
+    assert!(sent.as_deref().is_some_and(is_generated), "{}", response);
+    assert_eq!(sent, returned);
+}
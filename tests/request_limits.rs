@@ -0,0 +1,192 @@
+use std::net::{ SocketAddr, TcpListener };
+use std::sync::{ Arc, OnceLock };
+use std::time::{ Duration, Instant };
+
+use hyper_util::rt::TokioIo;
+use rustls::server::{ ClientHello, ResolvesServerCert };
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+use sheldx::server::{ serve_connection, serve_tls_connection, ConnectionActivity, ConnectionInfo };
+use sheldx::services::{ gather_metrics, LimiterStore };
+use sheldx::utils::{
+    default_configs,
+    set_current_configs,
+    Configs,
+    ForwardingRule,
+    IpAccessRule,
+    RequestLimitsConfig,
+};
+use tokio::io::{ duplex, AsyncReadExt, AsyncWriteExt };
+use tokio::time::timeout;
+use tokio_rustls::TlsAcceptor;
+
+/// An upstream that takes connections but never answers.
+fn silent_upstream() -> SocketAddr {
+    static UPSTREAM: OnceLock<TcpListener> = OnceLock::new();
+    UPSTREAM.get_or_init(|| TcpListener::bind("127.0.0.1:0").unwrap()).local_addr().unwrap()
+}
+
+/// Every test serves with this configuration. Its request limits would reject any body, so the
+/// tests show connections keep the limits they were accepted with.
+fn configs() -> Configs {
+    Configs {
+        forwarding_rules: Some(
+            vec![ForwardingRule {
+                host: "slow.test".to_string(),
+                target: silent_upstream().to_string(),
+                request_headers: None,
+                response_headers: None,
+                concurrency: None,
+            }]
+        ),
+        ip_access_rules: Some(
+            vec![IpAccessRule { host: "denied.test".to_string(), allow: vec![], deny: vec!["0.0.0.0/0".to_string()] }]
+        ),
+        request_limits: Some(RequestLimitsConfig { max_body_size: Some(1), ..RequestLimitsConfig::default() }),
+        ..default_configs()
+    }
+}
+
+/// Serves one connection accepted with `limits`, sending it `request` and reading until it is
+/// closed.
+async fn exchange(limits: RequestLimitsConfig, request: &[u8]) -> String {
+    set_current_configs(configs());
+    let conn = Arc::new(ConnectionInfo {
+        peer_ip: "198.51.100.1".parse().unwrap(),
+        is_tls: false,
+        tls_version: None,
+        tls_cipher: None,
+        tls_sni: None,
+        request_limits: limits,
+    });
+    let store = Arc::new(LimiterStore::new(1000, Duration::from_secs(300)));
+
+    let (mut client, server) = duplex(1 << 16);
+    tokio::spawn(serve_connection(TokioIo::new(server), conn, store));
+    client.write_all(request).await.unwrap();
+
+    let mut response = Vec::new();
+    timeout(Duration::from_secs(5), client.read_to_end(&mut response)).await.unwrap().unwrap();
+    String::from_utf8_lossy(&response).into_owned()
+}
+
+fn limits() -> RequestLimitsConfig {
+    RequestLimitsConfig { max_body_size: None, request_timeout: None, ..RequestLimitsConfig::default() }
+}
+
+#[tokio::test]
+async fn declared_bodies_over_the_limit_get_a_413() {
+    let limits = RequestLimitsConfig { max_body_size: Some(16), ..limits() };
+    let body = "x".repeat(32);
+    let request = format!(
+        "POST / HTTP/1.1\r\nHost: slow.test\r\nContent-Length: 32\r\nConnection: close\r\n\r\n{}",
+        body
+    );
+
+    let response = exchange(limits, request.as_bytes()).await;
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+}
+
+#[tokio::test]
+async fn streamed_bodies_over_the_limit_get_a_413() {
+    let limits = RequestLimitsConfig { max_body_size: Some(16), ..limits() };
+    let request = format!(
+        "POST / HTTP/1.1\r\nHost: slow.test\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n20\r\n{}\r\n0\r\n\r\n",
+        "x".repeat(32)
+    );
+
+    let response = exchange(limits, request.as_bytes()).await;
+    assert!(response.starts_with("HTTP/1.1 413"), "{}", response);
+}
+
+#[tokio::test]
+async fn slow_requests_get_a_504() {
+    // the body is over the limit of the current configuration, but not of the connection
+    let limits = RequestLimitsConfig { request_timeout: Some(1), ..limits() };
+    let request = b"POST / HTTP/1.1\r\nHost: slow.test\r\nContent-Length: 8\r\nConnection: close\r\n\r\n12345678";
+
+    let started_at = Instant::now();
+    let response = exchange(limits, request).await;
+    assert!(response.starts_with("HTTP/1.1 504"), "{}", response);
+    assert!(started_at.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn oversized_headers_get_a_431() {
+    let limits = RequestLimitsConfig { max_header_size: 8192, ..limits() };
+    let request = format!("GET / HTTP/1.1\r\nHost: denied.test\r\nX-Padding: {}\r\n\r\n", "x".repeat(10_000));
+
+    let response = exchange(limits, request.as_bytes()).await;
+    assert!(response.starts_with("HTTP/1.1 431"), "{}", response);
+}
+
+#[tokio::test]
+async fn connections_without_headers_are_closed() {
+    let limits = RequestLimitsConfig { header_read_timeout: 1, ..limits() };
+
+    let started_at = Instant::now();
+    let response = exchange(limits, b"GET / HTTP/1.1\r\nHost: deni").await;
+    assert!(!response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(started_at.elapsed() >= Duration::from_secs(1));
+}
+
+/// Serves TLS without a certificate, which is enough for clients that never say hello.
+#[derive(Debug)]
+struct NoCertificate;
+
+impl ResolvesServerCert for NoCertificate {
+    fn resolve(&self, _: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        None
+    }
+}
+
+#[tokio::test]
+async fn silent_tls_clients_are_closed() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let mut client = tokio::net::TcpStream::connect(listener.local_addr().unwrap()).await.unwrap();
+    let (stream, peer) = listener.accept().await.unwrap();
+    let config = ServerConfig::builder().with_no_client_auth().with_cert_resolver(Arc::new(NoCertificate));
+    let limits = RequestLimitsConfig { header_read_timeout: 1, ..limits() };
+    let store = Arc::new(LimiterStore::new(1000, Duration::from_secs(300)));
+
+    let started_at = Instant::now();
+    tokio::spawn(serve_tls_connection(stream, peer.ip(), TlsAcceptor::from(Arc::new(config)), limits, store));
+    let mut received = Vec::new();
+    timeout(Duration::from_secs(5), client.read_to_end(&mut received)).await.unwrap().unwrap();
+    assert!(received.is_empty());
+    assert!(started_at.elapsed() >= Duration::from_secs(1));
+    assert!(gather_metrics().contains("sheldx_tls_handshake_failures_total{listener=\"https\"} 1"));
+}
+
+#[tokio::test]
+async fn idle_connections_are_closed() {
+    let limits = RequestLimitsConfig { keep_alive_timeout: 1, ..limits() };
+
+    let started_at = Instant::now();
+    let response = exchange(limits, b"GET / HTTP/1.1\r\nHost: denied.test\r\n\r\n").await;
+    assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+    assert!(started_at.elapsed() >= Duration::from_secs(1));
+}
+
+#[tokio::test]
+async fn connections_are_idle_once_their_requests_finish() {
+    let activity = Arc::new(ConnectionActivity::default());
+
+    let started_at = Instant::now();
+    activity.idle_for(Duration::from_millis(50)).await;
+    assert!(started_at.elapsed() >= Duration::from_millis(50));
+
+    let request = activity.start_request();
+    let idle = {
+        let activity = Arc::clone(&activity);
+        tokio::spawn(async move { activity.idle_for(Duration::from_millis(50)).await })
+    };
+    tokio::time::sleep(Duration::from_millis(150)).await;
+    assert!(!idle.is_finished());
+
+    // the idle time counts from the end of the request
+    let finished_at = Instant::now();
+    drop(request);
+    timeout(Duration::from_secs(1), idle).await.unwrap().unwrap();
+    assert!(finished_at.elapsed() >= Duration::from_millis(50));
+}
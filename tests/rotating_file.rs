@@ -0,0 +1,147 @@
+use std::fs::{ self, File };
+use std::io::Write;
+use std::path::{ Path, PathBuf };
+use std::time::{ Duration, SystemTime };
+
+use sheldx::utils::{ LogRotationConfig, RotatingFile, RotationInterval };
+
+const MEGABYTE: usize = 1024 * 1024;
+
+fn dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("sheldx-rotating-file-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn rotation(max_size_mb: Option<u64>, interval: RotationInterval, retention: usize) -> LogRotationConfig {
+    LogRotationConfig { max_size_mb, interval, retention }
+}
+
+/// The files in `dir`, by name.
+fn files(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs
+        ::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+/// Whether `name` is a file rotated from `sheldx.log`: `sheldx.log.<%Y%m%d-%H%M%S>[.<n>]`.
+fn is_rotated(name: &str) -> bool {
+    let Some(suffix) = name.strip_prefix("sheldx.log.") else {
+        return false;
+    };
+    let (timestamp, sequence) = suffix.split_once('.').unwrap_or((suffix, "0"));
+    timestamp.len() == 15 &&
+        timestamp.bytes().enumerate().all(|(i, b)| if i == 8 { b == b'-' } else { b.is_ascii_digit() }) &&
+        !sequence.is_empty() &&
+        sequence.bytes().all(|b| b.is_ascii_digit())
+}
+
+fn rotated(dir: &Path) -> Vec<String> {
+    files(dir)
+        .into_iter()
+        .filter(|name| is_rotated(name))
+        .collect()
+}
+
+fn write_megabyte(file: &mut RotatingFile) {
+    file.write_all(&vec![b'x'; MEGABYTE]).unwrap();
+    file.flush().unwrap();
+}
+
+#[test]
+fn files_rotate_once_they_reach_the_size_limit() {
+    let dir = dir("size");
+    let path = dir.join("sheldx.log");
+    let mut file = RotatingFile::open(&path, &rotation(Some(1), RotationInterval::Never, 5)).unwrap();
+
+    file.write_all(b"small\n").unwrap();
+    file.flush().unwrap();
+    assert!(rotated(&dir).is_empty());
+
+    write_megabyte(&mut file);
+    let rotated = rotated(&dir);
+    assert_eq!(rotated.len(), 1);
+    assert_eq!(fs::metadata(dir.join(&rotated[0])).unwrap().len(), (MEGABYTE + 6) as u64);
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_from_an_earlier_period_rotate() {
+    let dir = dir("interval");
+    let path = dir.join("sheldx.log");
+    fs::write(&path, "yesterday\n").unwrap();
+    let two_days_ago = SystemTime::now() - Duration::from_secs(2 * 24 * 3600);
+    File::options().append(true).open(&path).unwrap().set_modified(two_days_ago).unwrap();
+
+    let mut file = RotatingFile::open(&path, &rotation(None, RotationInterval::Daily, 5)).unwrap();
+    file.write_all(b"today\n").unwrap();
+    file.flush().unwrap();
+
+    let yesterday = rotated(&dir);
+    assert_eq!(yesterday.len(), 1);
+    assert_eq!(fs::read_to_string(dir.join(&yesterday[0])).unwrap(), "yesterday\ntoday\n");
+
+    // the new file stays put for the rest of the day
+    file.write_all(b"later\n").unwrap();
+    file.flush().unwrap();
+    assert_eq!(rotated(&dir), yesterday);
+    assert_eq!(fs::read_to_string(&path).unwrap(), "later\n");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn only_the_newest_rotated_files_are_kept() {
+    let dir = dir("retention");
+    let path = dir.join("sheldx.log");
+    fs::write(dir.join("sheldx.log.20200101-000000"), "oldest").unwrap();
+    let mut file = RotatingFile::open(&path, &rotation(Some(1), RotationInterval::Never, 2)).unwrap();
+
+    write_megabyte(&mut file);
+    let first = rotated(&dir);
+    assert_eq!(first.len(), 2);
+    assert!(first.contains(&"sheldx.log.20200101-000000".to_string()));
+
+    write_megabyte(&mut file);
+    write_megabyte(&mut file);
+    let kept = rotated(&dir);
+    assert_eq!(kept.len(), 2);
+    assert!(!kept.contains(&"sheldx.log.20200101-000000".to_string()));
+    assert!(!kept.contains(&first[1]), "{:?} {:?}", first, kept);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn unrelated_files_are_left_alone() {
+    let dir = dir("unrelated");
+    let path = dir.join("sheldx.log");
+    let unrelated = [
+        "sheldx.log.1",
+        "sheldx.log.0.gz",
+        "sheldx.log.bak",
+        "sheldx.log.20200101-000000.gz",
+        "sheldx.log.2020010-1000000",
+        "sheldx.logs.20200101-000000",
+        "other.log.20200101-000000",
+    ];
+    for name in unrelated {
+        fs::write(dir.join(name), name).unwrap();
+    }
+    let mut file = RotatingFile::open(&path, &rotation(Some(1), RotationInterval::Never, 1)).unwrap();
+
+    write_megabyte(&mut file);
+    write_megabyte(&mut file);
+
+    // the unrelated files don't count toward the retention either
+    assert_eq!(rotated(&dir).len(), 1);
+    let files = files(&dir);
+    for name in unrelated {
+        assert!(files.contains(&name.to_string()), "{} was removed", name);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
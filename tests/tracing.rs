@@ -0,0 +1,178 @@
+use std::convert::Infallible;
+use std::time::Duration;
+
+use http_body_util::{ BodyExt, Full };
+use hyper::body::{ Bytes, Incoming };
+use hyper::header::{ HeaderMap, HeaderValue };
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{ Request, Response };
+use hyper_util::rt::TokioIo;
+use serde_json::Value;
+use sheldx::services::{ init_tracing, tracing_enabled, Span, SpanContext, SpanKind };
+use sheldx::utils::TracingConfig;
+use tokio::net::TcpListener;
+use tokio::sync::mpsc::{ unbounded_channel, UnboundedSender };
+
+const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+fn traceparent_headers(traceparent: &str, tracestate: Option<&str>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("traceparent", HeaderValue::from_str(traceparent).unwrap());
+    if let Some(tracestate) = tracestate {
+        headers.insert("tracestate", HeaderValue::from_str(tracestate).unwrap());
+    }
+    headers
+}
+
+fn parse(traceparent: &str) -> Option<SpanContext> {
+    SpanContext::from_headers(&traceparent_headers(traceparent, None))
+}
+
+#[test]
+fn reads_traceparent_and_tracestate() {
+    let context = SpanContext::from_headers(&traceparent_headers(TRACEPARENT, Some("congo=t61rcWkgMzE"))).unwrap();
+
+    assert_eq!(context.trace_id[..4], [0x4b, 0xf9, 0x2f, 0x35]);
+    assert_eq!(context.span_id, [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]);
+    assert!(context.sampled);
+    assert_eq!(context.trace_state.as_deref(), Some("congo=t61rcWkgMzE"));
+    assert_eq!(context.traceparent(), TRACEPARENT);
+
+    assert!(!parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00").unwrap().sampled);
+    // later versions may append fields
+    assert!(parse("01-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-09-future").unwrap().sampled);
+    assert!(SpanContext::from_headers(&HeaderMap::new()).is_none());
+}
+
+#[test]
+fn ignores_malformed_traceparents() {
+    for traceparent in [
+        "",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+        "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "0-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+        "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+        "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e473-00f067aa0ba902b7-01",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-1",
+        "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902zz-01",
+    ] {
+        assert!(parse(traceparent).is_none(), "{:?} was accepted", traceparent);
+    }
+}
+
+#[test]
+fn injects_the_context_for_the_next_hop() {
+    let context = parse(TRACEPARENT).unwrap();
+    let mut headers = traceparent_headers("stale", Some("stale=1"));
+
+    context.inject(&mut headers);
+
+    assert_eq!(headers["traceparent"], TRACEPARENT);
+    assert!(!headers.contains_key("tracestate"));
+}
+
+#[test]
+fn generated_ids_use_every_bit() {
+    let mut trace_bits = [0u8; 16];
+    let mut span_bits = [0u8; 8];
+    for _ in 0..64 {
+        let root = Span::root("root", SpanKind::Server, None);
+        let child = Span::child(root.context(), "child", SpanKind::Client);
+        for context in [root.context(), child.context()] {
+            assert_ne!(context.span_id, [0; 8]);
+            span_bits.iter_mut().zip(context.span_id).for_each(|(bits, byte)| *bits |= byte);
+        }
+        assert_ne!(root.context().trace_id, [0; 16]);
+        assert_eq!(child.context().trace_id, root.context().trace_id);
+        trace_bits.iter_mut().zip(root.context().trace_id).for_each(|(bits, byte)| *bits |= byte);
+    }
+    // no bit is fixed, as the version and variant bits of a UUID would be
+    assert_eq!(trace_bits, [0xff; 16]);
+    assert_eq!(span_bits, [0xff; 8]);
+}
+
+/// Starts a stand-in OTLP collector that passes on the body of every request it receives.
+async fn start_collector(bodies: UnboundedSender<Value>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let bodies = bodies.clone();
+            tokio::spawn(async move {
+                let service = service_fn(move |req: Request<Incoming>| {
+                    let bodies = bodies.clone();
+                    async move {
+                        let body = req.into_body().collect().await.unwrap().to_bytes();
+                        let _ = bodies.send(serde_json::from_slice(&body).unwrap());
+                        Ok::<_, Infallible>(Response::new(Full::new(Bytes::new())))
+                    }
+                });
+                let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), service).await;
+            });
+        }
+    });
+    port
+}
+
+#[tokio::test]
+async fn exports_sampled_spans_as_otlp_json() {
+    let (bodies, mut received) = unbounded_channel();
+    let port = start_collector(bodies).await;
+    let config = TracingConfig {
+        enabled: true,
+        otlp_endpoint: format!("http://127.0.0.1:{}/v1/traces", port),
+        service_name: "sheldx-test".to_string(),
+        sample_ratio: 1.0,
+    };
+    init_tracing(&config).unwrap();
+    assert!(tracing_enabled());
+
+    let parent = SpanContext::from_headers(&traceparent_headers(TRACEPARENT, Some("congo=t61rcWkgMzE")));
+    let mut server = Span::root("GET www.example.com", SpanKind::Server, parent);
+    server.set_attribute("http.response.status_code", 502i64);
+    server.set_error("502 Bad Gateway");
+    let mut client = Span::child(server.context(), "upstream request", SpanKind::Client);
+    client.set_attribute("server.address", "127.0.0.1:3000");
+    client.set_attribute("sheldx.retried", false);
+    let server_span_id = server.context().span_id;
+    drop(client);
+    drop(server);
+    // the caller decided against recording this trace
+    drop(Span::root("unsampled", SpanKind::Server, parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00")));
+
+    let body = tokio::time::timeout(Duration::from_secs(15), received.recv()).await.unwrap().unwrap();
+    let resource = &body["resourceSpans"][0];
+    assert_eq!(resource["resource"]["attributes"][0]["key"], "service.name");
+    assert_eq!(resource["resource"]["attributes"][0]["value"]["stringValue"], "sheldx-test");
+    assert_eq!(resource["scopeSpans"][0]["scope"]["name"], "sheldx");
+
+    let spans = resource["scopeSpans"][0]["spans"].as_array().unwrap();
+    assert_eq!(spans.len(), 2);
+    let (client, server) = (&spans[0], &spans[1]);
+
+    assert_eq!(server["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(server["parentSpanId"], "00f067aa0ba902b7");
+    assert_eq!(server["traceState"], "congo=t61rcWkgMzE");
+    assert_eq!(server["name"], "GET www.example.com");
+    assert_eq!(server["kind"], 2);
+    assert_eq!(server["status"]["code"], 2);
+    assert_eq!(server["status"]["message"], "502 Bad Gateway");
+    assert_eq!(server["attributes"][0]["value"]["intValue"], "502");
+    let start: u128 = server["startTimeUnixNano"].as_str().unwrap().parse().unwrap();
+    let end: u128 = server["endTimeUnixNano"].as_str().unwrap().parse().unwrap();
+    assert!(start > 0 && start <= end);
+
+    let server_span_id: String = server_span_id.iter().map(|byte| format!("{:02x}", byte)).collect();
+    assert_eq!(server["spanId"], server_span_id.as_str());
+    assert_eq!(client["parentSpanId"], server_span_id.as_str());
+    assert_eq!(client["traceId"], "4bf92f3577b34da6a3ce929d0e0e4736");
+    assert_eq!(client["kind"], 3);
+    assert_eq!(client["status"]["code"], 0);
+    assert_eq!(client["attributes"][0]["value"]["stringValue"], "127.0.0.1:3000");
+    assert_eq!(client["attributes"][1]["value"]["boolValue"], false);
+}